@@ -0,0 +1,19 @@
+CREATE TABLE chats (
+    chat_id UUID PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE chat_messages (
+    chat_id UUID NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    -- Preserves the order the messages were appended in, timestamps
+    -- of concurrently sent messages might not.
+    position BIGINT GENERATED ALWAYS AS IDENTITY,
+    timestamp TIMESTAMPTZ NOT NULL,
+    user_id UUID NOT NULL,
+    display_name TEXT NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (chat_id, event_id)
+);
+
+CREATE INDEX chat_messages_chat_id_position_idx ON chat_messages (chat_id, position);
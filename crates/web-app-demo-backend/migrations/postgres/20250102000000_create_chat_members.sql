@@ -0,0 +1,6 @@
+CREATE TABLE chat_members (
+    chat_id UUID NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    role TEXT NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
@@ -0,0 +1,7 @@
+-- Counts how often an invite was used to add a member, the invites
+-- themselves are signed tokens held by the invitees.
+CREATE TABLE invite_uses (
+    invite_id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    uses BIGINT NOT NULL
+);
@@ -0,0 +1,13 @@
+CREATE TABLE notes (
+    chat_id UUID NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    note_id UUID NOT NULL,
+    -- Preserves the order the notes were created in.
+    position BIGINT GENERATED ALWAYS AS IDENTITY,
+    author UUID NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (chat_id, note_id)
+);
+
+CREATE INDEX notes_chat_id_position_idx ON notes (chat_id, position);
@@ -0,0 +1,18 @@
+-- Numbers the messages of each chat in the order they were appended,
+-- counting on from the last sequence number of the chat.
+ALTER TABLE chats ADD COLUMN last_sequence BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE chat_messages ADD COLUMN sequence BIGINT;
+
+UPDATE chat_messages SET sequence = numbered.sequence
+FROM (
+    SELECT chat_id, event_id, ROW_NUMBER() OVER (PARTITION BY chat_id ORDER BY position) AS sequence
+    FROM chat_messages
+) AS numbered
+WHERE chat_messages.chat_id = numbered.chat_id AND chat_messages.event_id = numbered.event_id;
+
+UPDATE chats SET last_sequence = (
+    SELECT COALESCE(MAX(sequence), 0) FROM chat_messages WHERE chat_messages.chat_id = chats.chat_id
+);
+
+ALTER TABLE chat_messages ALTER COLUMN sequence SET NOT NULL;
+CREATE UNIQUE INDEX chat_messages_chat_id_sequence_idx ON chat_messages (chat_id, sequence);
@@ -0,0 +1,4 @@
+-- Retention limits set for a chat, NULL falls back to the configured default.
+ALTER TABLE chats ADD COLUMN retention_max_age_secs BIGINT;
+ALTER TABLE chats ADD COLUMN retention_max_messages BIGINT;
+ALTER TABLE chats ADD COLUMN retention_max_bytes BIGINT;
@@ -0,0 +1,16 @@
+-- Edited messages keep their earlier texts as revisions, deleted ones are
+-- kept as tombstones without text and revisions.
+ALTER TABLE chat_messages ADD COLUMN edited_at TIMESTAMPTZ;
+ALTER TABLE chat_messages ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE TABLE message_revisions (
+    -- Preserves the order the revisions were made in.
+    position BIGINT GENERATED ALWAYS AS IDENTITY,
+    chat_id UUID NOT NULL,
+    event_id UUID NOT NULL,
+    message TEXT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (chat_id, event_id) REFERENCES chat_messages (chat_id, event_id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_revisions_chat_id_event_id_idx ON message_revisions (chat_id, event_id, position);
@@ -0,0 +1,12 @@
+-- Every reaction of a user with an emoji to a message, aggregated per emoji
+-- when the message is read. Deleted messages lose their reactions.
+CREATE TABLE message_reactions (
+    -- Preserves the order the reactions were added in.
+    position BIGINT GENERATED ALWAYS AS IDENTITY,
+    chat_id UUID NOT NULL,
+    event_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    emoji TEXT NOT NULL,
+    PRIMARY KEY (chat_id, event_id, emoji, user_id),
+    FOREIGN KEY (chat_id, event_id) REFERENCES chat_messages (chat_id, event_id) ON DELETE CASCADE
+);
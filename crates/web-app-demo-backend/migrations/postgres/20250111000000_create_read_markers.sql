@@ -0,0 +1,9 @@
+-- The message every member has read a chat up to. The sequence number of
+-- the message is kept along, so unread messages are counted without it.
+CREATE TABLE read_markers (
+    chat_id UUID NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    event_id UUID NOT NULL,
+    sequence BIGINT NOT NULL,
+    PRIMARY KEY (chat_id, user_id)
+);
@@ -0,0 +1,4 @@
+-- Rate limits set for a chat, NULL falls back to the configured default.
+ALTER TABLE chats ADD COLUMN rate_limit_burst BIGINT;
+ALTER TABLE chats ADD COLUMN rate_limit_per_minute BIGINT;
+ALTER TABLE chats ADD COLUMN slow_mode_secs BIGINT;
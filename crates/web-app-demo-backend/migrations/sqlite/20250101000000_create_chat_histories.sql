@@ -0,0 +1,19 @@
+CREATE TABLE chats (
+    chat_id BLOB PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE TABLE chat_messages (
+    -- Preserves the order the messages were appended in, timestamps
+    -- of concurrently sent messages might not.
+    position INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id BLOB NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    event_id BLOB NOT NULL,
+    timestamp TEXT NOT NULL,
+    user_id BLOB NOT NULL,
+    display_name TEXT NOT NULL,
+    message TEXT NOT NULL,
+    UNIQUE (chat_id, event_id)
+);
+
+CREATE INDEX chat_messages_chat_id_position_idx ON chat_messages (chat_id, position);
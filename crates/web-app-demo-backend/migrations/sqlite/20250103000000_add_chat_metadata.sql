@@ -0,0 +1,4 @@
+ALTER TABLE chats ADD COLUMN name TEXT;
+ALTER TABLE chats ADD COLUMN topic TEXT;
+-- Unknown for chats created before chats had creators.
+ALTER TABLE chats ADD COLUMN creator BLOB;
@@ -0,0 +1,13 @@
+CREATE TABLE notes (
+    -- Preserves the order the notes were created in.
+    position INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id BLOB NOT NULL REFERENCES chats (chat_id) ON DELETE CASCADE,
+    note_id BLOB NOT NULL,
+    author BLOB NOT NULL,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    UNIQUE (chat_id, note_id)
+);
+
+CREATE INDEX notes_chat_id_position_idx ON notes (chat_id, position);
@@ -0,0 +1,15 @@
+-- Numbers the messages of each chat in the order they were appended,
+-- counting on from the last sequence number of the chat.
+ALTER TABLE chats ADD COLUMN last_sequence INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE chat_messages ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+
+UPDATE chat_messages SET sequence = (
+    SELECT COUNT(*) FROM chat_messages AS earlier
+    WHERE earlier.chat_id = chat_messages.chat_id AND earlier.position <= chat_messages.position
+);
+
+UPDATE chats SET last_sequence = (
+    SELECT COALESCE(MAX(sequence), 0) FROM chat_messages WHERE chat_messages.chat_id = chats.chat_id
+);
+
+CREATE UNIQUE INDEX chat_messages_chat_id_sequence_idx ON chat_messages (chat_id, sequence);
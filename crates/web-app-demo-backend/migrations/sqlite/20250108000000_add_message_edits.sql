@@ -0,0 +1,16 @@
+-- Edited messages keep their earlier texts as revisions, deleted ones are
+-- kept as tombstones without text and revisions.
+ALTER TABLE chat_messages ADD COLUMN edited_at TEXT;
+ALTER TABLE chat_messages ADD COLUMN deleted_at TEXT;
+
+CREATE TABLE message_revisions (
+    -- Preserves the order the revisions were made in.
+    position INTEGER PRIMARY KEY AUTOINCREMENT,
+    chat_id BLOB NOT NULL,
+    event_id BLOB NOT NULL,
+    message TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    FOREIGN KEY (chat_id, event_id) REFERENCES chat_messages (chat_id, event_id) ON DELETE CASCADE
+);
+
+CREATE INDEX message_revisions_chat_id_event_id_idx ON message_revisions (chat_id, event_id, position);
@@ -0,0 +1,7 @@
+-- Replies reference the message they reply to and the first message of
+-- their thread, both in the same chat. Pruning may remove the referenced
+-- messages, so the references aren't enforced.
+ALTER TABLE chat_messages ADD COLUMN in_reply_to BLOB;
+ALTER TABLE chat_messages ADD COLUMN thread_root BLOB;
+
+CREATE INDEX chat_messages_chat_id_thread_root_idx ON chat_messages (chat_id, thread_root, position);
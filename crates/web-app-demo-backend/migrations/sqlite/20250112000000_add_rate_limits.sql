@@ -0,0 +1,4 @@
+-- Rate limits set for a chat, NULL falls back to the configured default.
+ALTER TABLE chats ADD COLUMN rate_limit_burst INTEGER;
+ALTER TABLE chats ADD COLUMN rate_limit_per_minute INTEGER;
+ALTER TABLE chats ADD COLUMN slow_mode_secs INTEGER;
@@ -0,0 +1,363 @@
+use std::{
+    str::FromStr as _,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    errors::{Error as JwtError, ErrorKind as JwtErrorKind},
+    jwk::{AlgorithmParameters, Jwk, JwkSet},
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{chat::models::UserId, settings::AuthSettings};
+
+// An unknown key id triggers refetching the JWKS, as the identity provider
+// might have rotated its keys. But not more often than this, so clients
+// can't make us hammer the identity provider with made up key ids.
+const MIN_JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub user_id: UserId,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthErrors {
+    #[error("invalid token: {0}")]
+    InvalidToken(#[from] JwtError),
+
+    #[error("token signed with unknown key {0:?}")]
+    UnknownKey(Option<String>),
+
+    #[error("subject {0:?} is not a valid user id")]
+    InvalidSubject(String),
+
+    #[error("failed to load the JWKS: {0}")]
+    JwksUnavailable(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+}
+
+struct Jwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Validates bearer tokens issued by the configured OpenID Connect provider.
+pub struct Authenticator {
+    settings: AuthSettings,
+    jwks: RwLock<Jwks>,
+}
+
+impl Authenticator {
+    /// Loads the JWKS from the configured file or url, falling back to the
+    /// `jwks_uri` announced in the discovery document of the issuer.
+    pub async fn from_settings(settings: AuthSettings) -> Result<Self, AuthErrors> {
+        let keys = load_jwks(&settings).await?;
+        Ok(Self::from_jwks(settings, keys))
+    }
+
+    pub fn from_jwks(settings: AuthSettings, keys: JwkSet) -> Self {
+        Self {
+            settings,
+            jwks: RwLock::new(Jwks {
+                keys,
+                fetched_at: Instant::now(),
+            }),
+        }
+    }
+
+    pub async fn authenticate(&self, token: &str) -> Result<AuthenticatedUser, AuthErrors> {
+        let header = decode_header(token)?;
+        let key_id = header.kid;
+
+        let jwk = match self.find_key(key_id.as_deref()) {
+            Some(jwk) => jwk,
+            None => {
+                self.refresh_jwks().await?;
+                self.find_key(key_id.as_deref())
+                    .ok_or_else(|| AuthErrors::UnknownKey(key_id.clone()))?
+            }
+        };
+
+        if !allowed_algorithms(&jwk).contains(&header.alg) {
+            return Err(JwtError::from(JwtErrorKind::InvalidAlgorithm).into());
+        }
+        let decoding_key = DecodingKey::from_jwk(&jwk)?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.settings.issuer]);
+        match &self.settings.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        validation.leeway = self.settings.leeway_secs;
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+
+        let claims = decode::<Claims>(token, &decoding_key, &validation)?.claims;
+        let user_id = uuid::Uuid::parse_str(&claims.sub)
+            .map(UserId::from_uuid)
+            .map_err(|_| AuthErrors::InvalidSubject(claims.sub.clone()))?;
+        let expires_at = DateTime::from_timestamp(claims.exp, 0)
+            .ok_or_else(|| JwtError::from(JwtErrorKind::ExpiredSignature))?;
+
+        Ok(AuthenticatedUser {
+            user_id,
+            expires_at,
+        })
+    }
+
+    // Without a key id, a JWKS containing a single key is unambiguous.
+    fn find_key(&self, key_id: Option<&str>) -> Option<Jwk> {
+        let jwks = self
+            .jwks
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match key_id {
+            Some(key_id) => jwks.keys.find(key_id).cloned(),
+            None if jwks.keys.keys.len() == 1 => jwks.keys.keys.first().cloned(),
+            None => None,
+        }
+    }
+
+    async fn refresh_jwks(&self) -> Result<(), AuthErrors> {
+        if self.settings.jwks_file.is_some() {
+            return Ok(());
+        }
+        {
+            let jwks = self
+                .jwks
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if jwks.fetched_at.elapsed() < MIN_JWKS_REFRESH_INTERVAL {
+                return Ok(());
+            }
+        }
+        let keys = load_jwks(&self.settings).await?;
+        tracing::info!(keys = keys.keys.len(), "JWKS refreshed");
+        *self
+            .jwks
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Jwks {
+            keys,
+            fetched_at: Instant::now(),
+        };
+        Ok(())
+    }
+}
+
+// Restricts the algorithms to the ones the key is meant for, so nobody can
+// trick us into e.g. using a public RSA key as HMAC secret.
+fn allowed_algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    if let Some(key_algorithm) = jwk.common.key_algorithm {
+        return Algorithm::from_str(&key_algorithm.to_string())
+            .map(|algorithm| vec![algorithm])
+            .unwrap_or_default();
+    }
+    match jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(_) => vec![Algorithm::ES256, Algorithm::ES384],
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => {
+            vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+        }
+        _ => vec![],
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscoveryDocument {
+    jwks_uri: String,
+}
+
+async fn load_jwks(settings: &AuthSettings) -> Result<JwkSet, AuthErrors> {
+    if let Some(path) = &settings.jwks_file {
+        let content = std::fs::read(path).map_err(|err| {
+            AuthErrors::JwksUnavailable(format!("reading {}: {err}", path.display()))
+        })?;
+        return serde_json::from_slice(&content).map_err(|err| {
+            AuthErrors::JwksUnavailable(format!("parsing {}: {err}", path.display()))
+        });
+    }
+
+    let jwks_url = match &settings.jwks_url {
+        Some(jwks_url) => jwks_url.clone(),
+        None => {
+            let discovery_url = format!(
+                "{}/.well-known/openid-configuration",
+                settings.issuer.trim_end_matches('/')
+            );
+            fetch_json::<DiscoveryDocument>(&discovery_url)
+                .await?
+                .jwks_uri
+        }
+    };
+    fetch_json(&jwks_url).await
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, AuthErrors> {
+    let fetch = async {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await
+    };
+    fetch
+        .await
+        .map_err(|err| AuthErrors::JwksUnavailable(format!("fetching {url}: {err}")))
+}
+
+#[cfg(test)]
+pub mod testing {
+    use std::path::PathBuf;
+
+    use chrono::{DateTime, Utc};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+    use serde::Serialize;
+
+    use super::Authenticator;
+    use crate::{chat::models::UserId, settings::AuthSettings};
+
+    pub const TEST_ISSUER: &str = "https://issuer.test/realms/web-app-demo";
+    const TEST_KEY_ID: &str = "test-key";
+    // The same secret is base64url encoded in `test-fixtures/jwks.json`.
+    const TEST_SECRET: &[u8] = b"web-app-demo-test-secret-do-not-use-in-production";
+
+    #[derive(Serialize)]
+    struct TestClaims<'a> {
+        iss: &'a str,
+        sub: String,
+        exp: i64,
+    }
+
+    pub fn test_auth_settings() -> AuthSettings {
+        AuthSettings {
+            issuer: TEST_ISSUER.to_string(),
+            audience: None,
+            jwks_file: Some(
+                PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test-fixtures/jwks.json"),
+            ),
+            jwks_url: None,
+            leeway_secs: 0,
+        }
+    }
+
+    pub async fn test_authenticator() -> Authenticator {
+        Authenticator::from_settings(test_auth_settings())
+            .await
+            .expect("the test JWKS should load")
+    }
+
+    pub fn token_expiring_at(user_id: UserId, expires_at: DateTime<Utc>) -> String {
+        signed_token(TEST_ISSUER, &user_id.to_string(), expires_at)
+    }
+
+    pub fn token_for(user_id: UserId) -> String {
+        token_expiring_at(user_id, Utc::now() + chrono::Duration::hours(1))
+    }
+
+    pub fn signed_token(issuer: &str, subject: &str, expires_at: DateTime<Utc>) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(TEST_KEY_ID.to_string());
+        encode(
+            &header,
+            &TestClaims {
+                iss: issuer,
+                sub: subject.to_string(),
+                exp: expires_at.timestamp(),
+            },
+            &EncodingKey::from_secret(TEST_SECRET),
+        )
+        .expect("signing a test token should succeed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{testing::*, *};
+
+    #[tokio::test]
+    async fn a_valid_token_yields_the_user_from_the_subject() -> anyhow::Result<()> {
+        let authenticator = test_authenticator().await;
+        let user_id = UserId::random();
+        let expires_at = DateTime::from_timestamp(Utc::now().timestamp() + 60, 0).unwrap();
+
+        let user = authenticator
+            .authenticate(&token_expiring_at(user_id, expires_at))
+            .await?;
+        assert_eq!(
+            user,
+            AuthenticatedUser {
+                user_id,
+                expires_at
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn an_expired_token_is_rejected() {
+        let authenticator = test_authenticator().await;
+        let token = token_expiring_at(UserId::random(), Utc::now() - chrono::Duration::minutes(1));
+        assert!(authenticator.authenticate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_token_of_another_issuer_is_rejected() {
+        let authenticator = test_authenticator().await;
+        let token = signed_token(
+            "https://evil.test",
+            &UserId::random().to_string(),
+            Utc::now() + chrono::Duration::hours(1),
+        );
+        assert!(authenticator.authenticate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_tampered_token_is_rejected() {
+        let authenticator = test_authenticator().await;
+        let token = token_for(UserId::random());
+        let other_token = token_for(UserId::random());
+        // Header and claims of one token with the signature of another.
+        let (signed_part, _) = token.rsplit_once('.').unwrap();
+        let (_, signature) = other_token.rsplit_once('.').unwrap();
+        let tampered = format!("{signed_part}.{signature}");
+        assert!(authenticator.authenticate(&tampered).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_subject_which_is_no_uuid_is_rejected() {
+        let authenticator = test_authenticator().await;
+        let token = signed_token(
+            TEST_ISSUER,
+            "slartibartfass",
+            Utc::now() + chrono::Duration::hours(1),
+        );
+        assert!(matches!(
+            authenticator.authenticate(&token).await,
+            Err(AuthErrors::InvalidSubject(_))
+        ));
+    }
+}
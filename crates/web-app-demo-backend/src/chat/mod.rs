@@ -1,56 +1,334 @@
-use std::{backtrace::Backtrace, sync::Arc};
+use std::{backtrace::Backtrace, collections::HashMap};
 
 use thiserror::Error;
 use tokio_stream::wrappers::BroadcastStream;
 
-use crate::util::wrappedbacktrace::WrappedBacktrace;
+use crate::{
+    notes::models::NoteId, settings::ChatSettings, util::wrappedbacktrace::WrappedBacktrace,
+};
 
 pub mod models;
+pub mod ratelimit;
+pub mod reactions;
+pub mod receipts;
+pub mod retention;
+pub mod store;
+
+/// Number of messages of a history page, unless the client asks for less.
+pub const DEFAULT_HISTORY_LIMIT: usize = 50;
+/// Maximum number of messages of a history page.
+pub const MAX_HISTORY_LIMIT: usize = 500;
+
+// The broadcast channel of a chat, which exists while somebody joined it.
+struct ChatChannel {
+    sender: tokio::sync::broadcast::Sender<models::ChatEvent>,
+    // The members connected to the chat with their number of sessions.
+    present: HashMap<models::UserId, usize>,
+    // Set when the channel was removed from `ChatServer::broadcasts`, whoever
+    // locked it meanwhile has to look up the current channel again.
+    closed: bool,
+    // Counts the events published, and remembers the count at the last
+    // amendment, so lagging members can tell whether they skipped one.
+    published: u64,
+    last_amendment: u64,
+}
+
+impl ChatChannel {
+    // Yields whether it is the first session of the member.
+    fn enter(&mut self, user_id: models::UserId) -> bool {
+        let sessions = self.present.entry(user_id).or_default();
+        *sessions += 1;
+        *sessions == 1
+    }
+
+    // Yields whether it was the last session of the member.
+    fn leave(&mut self, user_id: models::UserId) -> bool {
+        let Some(sessions) = self.present.get_mut(&user_id) else {
+            return false;
+        };
+        *sessions -= 1;
+        if *sessions > 0 {
+            return false;
+        }
+        self.present.remove(&user_id);
+        true
+    }
+
+    fn publish(&mut self, event: models::ChatEvent) {
+        self.published += 1;
+        if event.is_amendment() {
+            self.last_amendment = self.published;
+        }
+        // Intentionally ignoring errors here. If no receiver is interested
+        // in the event any more, we don't care.
+        #[allow(unused_must_use)]
+        let _ = self.sender.send(event);
+    }
+
+    fn present_members(&self) -> Vec<models::UserId> {
+        let mut members: Vec<_> = self.present.keys().copied().collect();
+        members.sort();
+        members
+    }
+}
+
+// Publishing messages and joining a chat lock its channel, so joining
+// members read the history and subscribe to new messages atomically. That
+// way they neither miss a message nor receive one twice.
+type LockedChatChannel = tokio::sync::OwnedMutexGuard<ChatChannel>;
+
+/// What a member gets on joining a chat.
+pub struct JoinedChat {
+    pub role: models::Role,
+    /// The latest messages at the time of joining, `events` continues right
+    /// after them.
+    pub history: models::HistoryPage,
+    /// The sequence number of the last message in or before `history`.
+    pub last_sequence: u64,
+    /// The members connected to the chat, including the joining one.
+    /// `events` tells about later changes.
+    pub present: Vec<models::UserId>,
+    /// The number of events published to the chat before `events`, see
+    /// `ChatServer::amended_since`.
+    pub published: u64,
+    pub events: BroadcastStream<models::ChatEvent>,
+}
 
 #[allow(dead_code)]
 pub struct ChatServer {
-    // We intentionally use a std::sync::Mutex, as we never expect a
-    // lock to be held while awaiting a future.
-    histories: dashmap::DashMap<models::ChatId, Arc<std::sync::Mutex<Vec<models::ChatMessage>>>>,
-    broadcasts:
-        dashmap::DashMap<models::ChatId, tokio::sync::broadcast::Sender<models::ChatMessage>>,
+    store: Box<dyn store::ChatStore>,
+    broadcasts: dashmap::DashMap<models::ChatId, std::sync::Arc<tokio::sync::Mutex<ChatChannel>>>,
+    rate_limiter: ratelimit::RateLimiter,
+    settings: ChatSettings,
 }
 
 #[allow(dead_code)]
 impl ChatServer {
-    pub fn new() -> Self {
+    pub fn new(settings: ChatSettings, store: Box<dyn store::ChatStore>) -> Self {
         Self {
-            histories: Default::default(),
+            store,
             broadcasts: Default::default(),
+            rate_limiter: ratelimit::RateLimiter::new(settings.rate_limits.clone()),
+            settings,
         }
     }
 
-    fn broadcast_message(&self, message: models::ChatMessage) {
-        if let Some(sender) = self
-            .broadcasts
-            .get(&message.chat_id)
-            .map(|r| r.value().clone())
-        {
-            // Intentionally ignoring errors here. If no receiver is interested
-            // in the message any more, we don't care.
-            #[allow(unused_must_use)]
-            let _ = sender.send(message);
+    pub(crate) fn store(&self) -> &dyn store::ChatStore {
+        self.store.as_ref()
+    }
+
+    async fn lock_channel(&self, chat_id: models::ChatId) -> LockedChatChannel {
+        loop {
+            let channel = self
+                .broadcasts
+                .entry(chat_id)
+                .or_insert_with(|| {
+                    let (sender, _) =
+                        tokio::sync::broadcast::channel(self.settings.broadcast_capacity);
+                    std::sync::Arc::new(tokio::sync::Mutex::new(ChatChannel {
+                        sender,
+                        present: HashMap::new(),
+                        closed: false,
+                        published: 0,
+                        last_amendment: 0,
+                    }))
+                })
+                .clone();
+            let channel = channel.lock_owned().await;
+            if !channel.closed {
+                return channel;
+            }
         }
     }
 
-    pub fn send_message(&self, message: models::ChatMessage) -> Result<(), ChatServerErrors> {
-        let shared_history = self.histories.entry(message.chat_id).or_default().clone();
+    fn close_channel_if_unused(&self, chat_id: models::ChatId, channel: &mut LockedChatChannel) {
+        if channel.sender.receiver_count() == 0 {
+            let mutex = tokio::sync::OwnedMutexGuard::mutex(channel).clone();
+            self.broadcasts
+                .remove_if(&chat_id, |_, v| std::sync::Arc::ptr_eq(v, &mutex));
+            channel.closed = true;
+        }
+    }
 
-        shared_history
-            .lock()
-            .map_err(|_| ChatServerErrors::lock_poisened("accessing history".to_string()))?
-            .push(message.clone());
+    /// Pushes the event to the members connected to the chat. Events which
+    /// have to be ordered with the history, like messages, are published
+    /// with the history change instead.
+    pub(crate) async fn broadcast_event(&self, chat_id: models::ChatId, event: models::ChatEvent) {
+        let Some(channel) = self.broadcasts.get(&chat_id).map(|r| r.value().clone()) else {
+            return;
+        };
+        let mut channel = channel.lock().await;
+        if !channel.closed {
+            channel.publish(event);
+        }
+    }
 
-        self.broadcast_message(message);
+    /// Appends the message to its chat and pushes it to the members
+    /// connected to the chat. Only members who may post may send messages. A
+    /// reply has to reference a message of the same chat, its `thread_root`
+    /// is derived from that message. The display name and the text are
+    /// normalized and checked like edits.
+    pub async fn send_message(
+        &self,
+        mut message: models::ChatMessage,
+    ) -> Result<(), ChatServerErrors> {
+        let chat_id = message.chat_id;
+        let user_id = message.user_id;
+        let role = self.authorize(chat_id, user_id).await?;
+        if !role.can_post() {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                user_id,
+                format!("{role} members can't post"),
+            ));
+        }
+        let invalid_text = |invalid_text| ChatServerErrors::invalid_text(chat_id, invalid_text);
+        message.display_name =
+            models::DisplayName::try_new(message.display_name.into()).map_err(invalid_text)?;
+        message.message = models::Message::try_new(message.message.into()).map_err(invalid_text)?;
+        message.thread_root = match message.in_reply_to {
+            Some(in_reply_to) => {
+                let replied_to = self
+                    .store
+                    .read_message(message.chat_id, in_reply_to)
+                    .await?
+                    .ok_or_else(|| {
+                        ChatServerErrors::event_not_found(message.chat_id, in_reply_to)
+                    })?;
+                Some(replied_to.thread_root.unwrap_or(in_reply_to))
+            }
+            None => None,
+        };
+        let mut channel = self.lock_channel(chat_id).await;
+        let appended = self.store.append_message(&message).await;
+        if let Ok(sequence) = appended {
+            channel.publish(models::ChatEvent::Message(models::ChatMessage {
+                sequence,
+                ..message
+            }));
+        }
+        self.close_channel_if_unused(chat_id, &mut channel);
+        appended.map(|_| ())
+    }
 
+    // Only authors may change their messages, and only until they deleted
+    // them.
+    async fn authorize_author(
+        &self,
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+        user_id: models::UserId,
+        action: &str,
+    ) -> Result<(), ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        let message = self
+            .store
+            .read_message(chat_id, event_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::event_not_found(chat_id, event_id))?;
+        if message.user_id != user_id {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                user_id,
+                format!("only the author may {action} a message"),
+            ));
+        }
+        if message.is_deleted() {
+            return Err(ChatServerErrors::message_deleted(chat_id, event_id));
+        }
         Ok(())
     }
 
+    /// Replaces the text of a message sent by the user and pushes the edited
+    /// message to the members connected to the chat. The replaced text is
+    /// kept as revision. The new text is normalized and checked like new
+    /// messages.
+    pub async fn edit_message(
+        &self,
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+        user_id: models::UserId,
+        message: models::Message,
+    ) -> Result<models::ChatMessage, ChatServerErrors> {
+        self.authorize_author(chat_id, event_id, user_id, "edit")
+            .await?;
+        // Checked here rather than by the endpoints, so edits over HTTP and
+        // websockets are held to the same rules.
+        let message = models::Message::try_new(message.into())
+            .map_err(|invalid_text| ChatServerErrors::invalid_text(chat_id, invalid_text))?;
+        let edited = self
+            .store
+            .edit_message(chat_id, event_id, &message, &models::ChatTimestamp::now())
+            .await?
+            // Deleted meanwhile.
+            .ok_or_else(|| ChatServerErrors::message_deleted(chat_id, event_id))?;
+        // Edits replace the message as a whole, so members joining meanwhile
+        // may receive an edit they already read, but never miss one.
+        self.broadcast_event(chat_id, models::ChatEvent::MessageEdited(edited.clone()))
+            .await;
+        Ok(edited)
+    }
+
+    /// Replaces a message sent by the user by a tombstone and pushes it to
+    /// the members connected to the chat.
+    pub async fn delete_message(
+        &self,
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+        user_id: models::UserId,
+    ) -> Result<models::ChatMessage, ChatServerErrors> {
+        self.authorize_author(chat_id, event_id, user_id, "delete")
+            .await?;
+        let tombstone = self
+            .store
+            .delete_message(chat_id, event_id, &models::ChatTimestamp::now())
+            .await?
+            .ok_or_else(|| ChatServerErrors::message_deleted(chat_id, event_id))?;
+        tracing::info!(%chat_id, %event_id, %user_id, "message deleted");
+        self.broadcast_event(
+            chat_id,
+            models::ChatEvent::MessageDeleted(tombstone.clone()),
+        )
+        .await;
+        Ok(tombstone)
+    }
+
+    /// Reads the texts a message in a chat the user is a member of had
+    /// before its edits, oldest first.
+    pub async fn get_message_revisions(
+        &self,
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+        user_id: models::UserId,
+    ) -> Result<Vec<models::MessageRevision>, ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        self.store
+            .read_message_revisions(chat_id, event_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::event_not_found(chat_id, event_id))
+    }
+
+    /// Reads the thread a message in a chat the user is a member of belongs
+    /// to, see `ChatStore::read_thread`. Any message of the thread may be
+    /// given.
+    pub async fn get_thread(
+        &self,
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+        user_id: models::UserId,
+    ) -> Result<Vec<models::ChatMessage>, ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        let message = self
+            .store
+            .read_message(chat_id, event_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::event_not_found(chat_id, event_id))?;
+        self.store
+            .read_thread(chat_id, message.thread_root.unwrap_or(event_id))
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))
+    }
+
     // Contract: If you got a stream by calling `join_chat`, you must call
     // `part_chat` after you dropped the stream. Otherwise some internal
     // state might not be cleaned up properly and a memory leak could
@@ -59,42 +337,306 @@ impl ChatServer {
     // Rationale: We could wrap the returned type to implement the Drop
     // trait and do everything `part_chat`, but I assume that would
     // require all sorts of Pin/Unpin shenanigans. Maybe we do that later.
-    pub fn join_chat(&self, chat_id: models::ChatId) -> BroadcastStream<models::ChatMessage> {
-        // Ensure the chat exists.
-        self.histories.entry(chat_id).or_default();
+    //
+    // Joining an unknown chat creates it with the user as owner, unless
+    // implicit creation is disabled. Otherwise the user has to be a member.
+    // Yields the role of the user, which decides what they may do in the
+    // chat, and the latest page of the history followed by the stream of
+    // new events. Resuming `since` the sequence number of the last message
+    // received yields the messages missed in between instead, see
+    // `read_missed_messages`.
+    pub async fn join_chat(
+        &self,
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+        since: Option<u64>,
+    ) -> Result<JoinedChat, ChatServerErrors> {
+        if self.settings.implicit_creation {
+            self.store
+                .create_chat(&models::ChatMetadata::unnamed(
+                    chat_id,
+                    user_id,
+                    models::ChatTimestamp::now(),
+                ))
+                .await?;
+        }
+        let role = self.authorize(chat_id, user_id).await?;
 
-        let receiver =
-            if let Some(receiver) = self.broadcasts.get(&chat_id).map(|r| r.value().subscribe()) {
-                receiver
-            } else {
-                let (sender, _) = tokio::sync::broadcast::channel(16);
+        let mut channel = self.lock_channel(chat_id).await;
+        let history = match since {
+            Some(since) => self.read_missed_messages(chat_id, since).await,
+            None => {
+                self.read_history_page(
+                    chat_id,
+                    models::HistoryCursor::Latest,
+                    DEFAULT_HISTORY_LIMIT,
+                )
+                .await
+            }
+        };
+        let history = match history {
+            Ok(history) => history,
+            Err(err) => {
+                self.close_channel_if_unused(chat_id, &mut channel);
+                return Err(err);
+            }
+        };
+        // Announced before subscribing, so the member doesn't hear about
+        // its own arrival.
+        if channel.enter(user_id) {
+            channel.publish(models::ChatEvent::MemberJoined(user_id));
+        }
+        let receiver = channel.sender.subscribe();
+        let last_sequence = history
+            .messages
+            .last()
+            .map_or(since.unwrap_or(0), |message| message.sequence);
+        Ok(JoinedChat {
+            role,
+            history,
+            last_sequence,
+            present: channel.present_members(),
+            published: channel.published,
+            events: BroadcastStream::new(receiver),
+        })
+    }
 
-                // Since our last dashmap operation, somebody could already inserted a sender for
-                // this chat concurrently. So we only use our newly created channel, if the map
-                // still doesn't have an entry for this chat. This is analogous to some concurrent
-                // singleton double initialization pattern.
-                self.broadcasts.entry(chat_id).or_insert(sender).subscribe()
-            };
-        return BroadcastStream::new(receiver);
+    pub async fn part_chat(&self, chat_id: models::ChatId, user_id: models::UserId) {
+        let Some(channel) = self.broadcasts.get(&chat_id).map(|r| r.value().clone()) else {
+            return;
+        };
+        let mut channel = channel.lock_owned().await;
+        if !channel.closed {
+            if channel.leave(user_id) {
+                channel.publish(models::ChatEvent::MemberLeft(user_id));
+            }
+            self.close_channel_if_unused(chat_id, &mut channel);
+        }
     }
 
-    pub fn part_chat(&self, chat_id: models::ChatId) {
-        self.broadcasts
-            .remove_if(&chat_id, |_, v| v.receiver_count() == 0);
+    /// Yields the members connected to the chat.
+    pub async fn present_members(&self, chat_id: models::ChatId) -> Vec<models::UserId> {
+        let Some(channel) = self.broadcasts.get(&chat_id).map(|r| r.value().clone()) else {
+            return Vec::new();
+        };
+        let channel = channel.lock().await;
+        if channel.closed {
+            return Vec::new();
+        }
+        channel.present_members()
     }
 
-    pub fn get_chat_history(
+    /// Tells the members connected to the chat that the user is typing,
+    /// unless it was told shortly before, see `admit_typing`.
+    pub async fn notify_typing(&self, chat_id: models::ChatId, user_id: models::UserId) {
+        if !self.admit_typing(chat_id, user_id) {
+            return;
+        }
+        self.broadcast_event(chat_id, models::ChatEvent::Typing(user_id))
+            .await;
+    }
+
+    /// Creates a new chat owned by its creator.
+    pub async fn create_chat(
+        &self,
+        name: models::ChatName,
+        topic: Option<models::ChatTopic>,
+        creator: models::UserId,
+    ) -> Result<models::ChatMetadata, ChatServerErrors> {
+        let chat = models::ChatMetadata {
+            chat_id: models::ChatId::random(),
+            name: Some(name),
+            topic,
+            creator: Some(creator),
+            created_at: models::ChatTimestamp::now(),
+            retention: models::RetentionPolicy::default(),
+            rate_limit: models::RateLimitPolicy::default(),
+        };
+        if !self.store.create_chat(&chat).await? {
+            return Err(ChatServerErrors::storage_failure(
+                format!("creating chat {}", chat.chat_id),
+                "chat id already taken",
+            ));
+        }
+        tracing::info!(chat_id = %chat.chat_id, %creator, "chat created");
+        Ok(chat)
+    }
+
+    /// Reads the metadata of a chat the user is a member of.
+    pub async fn get_chat(
         &self,
         chat_id: models::ChatId,
-    ) -> Result<Vec<models::ChatMessage>, ChatServerErrors> {
-        let history = self
-            .histories
-            .get(&chat_id)
-            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))?
-            .lock()
-            .map_err(|_| ChatServerErrors::lock_poisened("accessing history".to_string()))?
-            .clone();
-        Ok(history)
+        user_id: models::UserId,
+    ) -> Result<models::ChatMetadata, ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        self.store
+            .read_chat(chat_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))
+    }
+
+    /// Lists the chats the user is a member of, the most recently active
+    /// first, whoever was active in them.
+    pub async fn get_member_chats(
+        &self,
+        user_id: models::UserId,
+    ) -> Result<Vec<models::ChatSummary>, ChatServerErrors> {
+        let mut chats = self.store.read_member_chats(user_id).await?;
+        chats.sort_by(|a, b| {
+            b.last_activity
+                .cmp(&a.last_activity)
+                .then(a.metadata.chat_id.cmp(&b.metadata.chat_id))
+        });
+        Ok(chats)
+    }
+
+    /// Yields the role of the user in the chat, failing if the user isn't a
+    /// member.
+    pub async fn authorize(
+        &self,
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+    ) -> Result<models::Role, ChatServerErrors> {
+        let members = self
+            .store
+            .read_members(chat_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))?;
+        members
+            .get(&user_id)
+            .copied()
+            .ok_or_else(|| ChatServerErrors::not_a_member(chat_id, user_id))
+    }
+
+    /// Creates an invite to the chat. Members may only invite with roles they
+    /// may grant, see `Role::can_grant`.
+    pub async fn create_invite(
+        &self,
+        chat_id: models::ChatId,
+        inviter: models::UserId,
+        role: models::Role,
+        max_uses: u32,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<models::Invite, ChatServerErrors> {
+        let inviter_role = self.authorize(chat_id, inviter).await?;
+        if !inviter_role.can_grant(role) {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                inviter,
+                format!("{inviter_role} members can't invite {role} members"),
+            ));
+        }
+        Ok(models::Invite {
+            invite_id: models::InviteId::random(),
+            chat_id,
+            role,
+            max_uses,
+            expires_at,
+        })
+    }
+
+    /// Adds the user to the chat of a verified invite.
+    pub async fn redeem_invite(
+        &self,
+        invite: &models::Invite,
+        user_id: models::UserId,
+    ) -> Result<models::InviteRedemption, ChatServerErrors> {
+        let redemption = self.store.redeem_invite(invite, user_id).await?;
+        if let models::InviteRedemption::Joined(role) = redemption {
+            tracing::info!(chat_id = %invite.chat_id, %user_id, %role, "invite redeemed");
+        }
+        Ok(redemption)
+    }
+
+    /// Reads a page of up to `limit` messages of the history next to the
+    /// cursor. The limit is capped at `MAX_HISTORY_LIMIT`.
+    pub async fn get_chat_history(
+        &self,
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+        cursor: models::HistoryCursor,
+        limit: usize,
+    ) -> Result<models::HistoryPage, ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        self.read_history_page(chat_id, cursor, limit).await
+    }
+
+    /// Whether an amendment, like an edit or a reaction, was published to
+    /// the chat after the first `published` events. Members lagging behind
+    /// the chat may have skipped it then, as they can't tell which events
+    /// they skipped.
+    pub async fn amended_since(&self, chat_id: models::ChatId, published: u64) -> bool {
+        let Some(channel) = self.broadcasts.get(&chat_id).map(|r| r.value().clone()) else {
+            // Can't tell, so better assume it was.
+            return true;
+        };
+        channel.lock().await.last_amendment > published
+    }
+
+    /// Reads the messages appended after the one with the sequence number
+    /// `since`. If more than a page of them were missed, the latest page is
+    /// read instead, the client pages back to fill the gap. Also used to
+    /// catch up with the messages a lagging member skipped.
+    pub async fn read_missed_messages(
+        &self,
+        chat_id: models::ChatId,
+        since: u64,
+    ) -> Result<models::HistoryPage, ChatServerErrors> {
+        let missed = self
+            .read_history_page(
+                chat_id,
+                models::HistoryCursor::Since(since),
+                MAX_HISTORY_LIMIT,
+            )
+            .await?;
+        if missed.next_cursor.is_none() {
+            return Ok(missed);
+        }
+        self.read_latest_messages(chat_id).await
+    }
+
+    /// Reads the latest page of messages, as large as possible, to start
+    /// over from.
+    pub async fn read_latest_messages(
+        &self,
+        chat_id: models::ChatId,
+    ) -> Result<models::HistoryPage, ChatServerErrors> {
+        self.read_history_page(chat_id, models::HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+            .await
+    }
+
+    async fn read_history_page(
+        &self,
+        chat_id: models::ChatId,
+        cursor: models::HistoryCursor,
+        limit: usize,
+    ) -> Result<models::HistoryPage, ChatServerErrors> {
+        let limit = limit.clamp(1, MAX_HISTORY_LIMIT);
+        // Reading one message more tells whether there is a next page.
+        let mut messages = self
+            .store
+            .read_message_range(chat_id, cursor, limit + 1)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))?;
+        let has_more = messages.len() > limit;
+        let next_cursor = match cursor {
+            models::HistoryCursor::Latest | models::HistoryCursor::Before(_) => {
+                if has_more {
+                    messages.remove(0);
+                }
+                messages.first()
+            }
+            models::HistoryCursor::After(_) | models::HistoryCursor::Since(_) => {
+                messages.truncate(limit);
+                messages.last()
+            }
+        }
+        .filter(|_| has_more)
+        .map(|message| message.event_id);
+        Ok(models::HistoryPage {
+            messages,
+            next_cursor,
+        })
     }
 }
 
@@ -107,6 +649,50 @@ pub enum ChatServerErrors {
     },
     #[error("chat {chat_id} not found")]
     ChatNotFound { chat_id: models::ChatId },
+    #[error("user {user_id} is no member of chat {chat_id}")]
+    NotAMember {
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+    },
+    #[error("user {user_id} is not permitted in chat {chat_id}: {reason}")]
+    NotPermitted {
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+        reason: String,
+    },
+    #[error("note {note_id} not found in chat {chat_id}")]
+    NoteNotFound {
+        chat_id: models::ChatId,
+        note_id: NoteId,
+    },
+    #[error("message {event_id} not found in chat {chat_id}")]
+    EventNotFound {
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+    },
+    #[error("message {event_id} in chat {chat_id} was deleted")]
+    MessageDeleted {
+        chat_id: models::ChatId,
+        event_id: models::EventId,
+    },
+    #[error("{invalid_text}")]
+    InvalidText {
+        chat_id: models::ChatId,
+        #[source]
+        invalid_text: models::InvalidText,
+    },
+    #[error("user {user_id} sends too fast to chat {chat_id}, retry in {retry_after:?}")]
+    RateLimited {
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+        retry_after: std::time::Duration,
+    },
+    #[error("storage failure: {message}: {source}")]
+    StorageFailure {
+        backtrace: WrappedBacktrace,
+        message: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 }
 
 impl ChatServerErrors {
@@ -119,6 +705,59 @@ impl ChatServerErrors {
     pub fn chat_not_found(chat_id: models::ChatId) -> ChatServerErrors {
         ChatServerErrors::ChatNotFound { chat_id }
     }
+    pub fn not_a_member(chat_id: models::ChatId, user_id: models::UserId) -> ChatServerErrors {
+        ChatServerErrors::NotAMember { chat_id, user_id }
+    }
+    pub fn not_permitted(
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+        reason: String,
+    ) -> ChatServerErrors {
+        ChatServerErrors::NotPermitted {
+            chat_id,
+            user_id,
+            reason,
+        }
+    }
+    pub fn note_not_found(chat_id: models::ChatId, note_id: NoteId) -> ChatServerErrors {
+        ChatServerErrors::NoteNotFound { chat_id, note_id }
+    }
+    pub fn event_not_found(chat_id: models::ChatId, event_id: models::EventId) -> ChatServerErrors {
+        ChatServerErrors::EventNotFound { chat_id, event_id }
+    }
+    pub fn message_deleted(chat_id: models::ChatId, event_id: models::EventId) -> ChatServerErrors {
+        ChatServerErrors::MessageDeleted { chat_id, event_id }
+    }
+    pub fn invalid_text(
+        chat_id: models::ChatId,
+        invalid_text: models::InvalidText,
+    ) -> ChatServerErrors {
+        ChatServerErrors::InvalidText {
+            chat_id,
+            invalid_text,
+        }
+    }
+    pub fn rate_limited(
+        chat_id: models::ChatId,
+        user_id: models::UserId,
+        retry_after: std::time::Duration,
+    ) -> ChatServerErrors {
+        ChatServerErrors::RateLimited {
+            chat_id,
+            user_id,
+            retry_after,
+        }
+    }
+    pub fn storage_failure(
+        message: String,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> ChatServerErrors {
+        ChatServerErrors::StorageFailure {
+            backtrace: WrappedBacktrace(Backtrace::capture()),
+            message,
+            source: source.into(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,7 +1,11 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SubsecRound as _, Utc};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization as _;
+
+use crate::notes::models::Note;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct UserId(uuid::Uuid);
@@ -21,6 +25,10 @@ impl UserId {
     pub fn from_uuid(uuid: uuid::Uuid) -> Self {
         Self(uuid)
     }
+
+    pub fn as_uuid(&self) -> uuid::Uuid {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -41,6 +49,10 @@ impl ChatId {
     pub fn from_uuid(uuid: uuid::Uuid) -> Self {
         Self(uuid)
     }
+
+    pub fn as_uuid(&self) -> uuid::Uuid {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -57,6 +69,38 @@ impl EventId {
     pub fn random() -> Self {
         Self(uuid::Uuid::new_v4())
     }
+
+    pub fn from_uuid(uuid: uuid::Uuid) -> Self {
+        Self(uuid)
+    }
+
+    pub fn as_uuid(&self) -> uuid::Uuid {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct InviteId(uuid::Uuid);
+
+impl Display for InviteId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(dead_code)]
+impl InviteId {
+    pub fn random() -> Self {
+        Self(uuid::Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: uuid::Uuid) -> Self {
+        Self(uuid)
+    }
+
+    pub fn as_uuid(&self) -> uuid::Uuid {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -70,9 +114,36 @@ impl Display for DisplayName {
 
 #[allow(dead_code)]
 impl DisplayName {
+    /// Longest display name accepted, in characters after normalizing.
+    pub const MAX_CHARS: usize = 64;
+
+    /// Takes the display name as is, for names checked before, like stored
+    /// ones.
     pub fn new(display_name: String) -> Self {
         Self(display_name)
     }
+
+    /// Normalizes and checks a display name entered by a user. Names are
+    /// single lines.
+    pub fn try_new(display_name: String) -> Result<Self, InvalidText> {
+        check_text(
+            TextField::DisplayName,
+            &display_name,
+            Self::MAX_CHARS,
+            |_| false,
+        )
+        .map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<DisplayName> for String {
+    fn from(display_name: DisplayName) -> Self {
+        display_name.0
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -86,9 +157,125 @@ impl Display for Message {
 
 #[allow(dead_code)]
 impl Message {
+    /// Longest message accepted, in characters after normalizing.
+    pub const MAX_CHARS: usize = 4000;
+
+    /// Takes the message as is, for messages checked before, like stored
+    /// ones, and the empty texts of deleted messages.
     pub fn new(message: String) -> Self {
         Self(message)
     }
+
+    /// Normalizes and checks a message written by a user. Messages may span
+    /// several lines and contain tabs.
+    pub fn try_new(message: String) -> Result<Self, InvalidText> {
+        check_text(TextField::Message, &message, Self::MAX_CHARS, |c| {
+            c == '\n' || c == '\t'
+        })
+        .map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Message> for String {
+    fn from(message: Message) -> Self {
+        message.0
+    }
+}
+
+/// The texts entered by users, which are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextField {
+    DisplayName,
+    Message,
+}
+
+impl Display for TextField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TextField::DisplayName => "display name",
+            TextField::Message => "message",
+        })
+    }
+}
+
+/// Why a text entered by a user was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum InvalidText {
+    #[error("the {field} must not be empty")]
+    Empty { field: TextField },
+    #[error("the {field} must not be longer than {max_chars} characters")]
+    TooLong { field: TextField, max_chars: usize },
+    #[error("the {field} must not contain the character U+{:04X}", *.character as u32)]
+    ForbiddenCharacter { field: TextField, character: char },
+}
+
+/// Normalizes a text entered by a user to NFC and trims surrounding
+/// whitespace. Rejects empty texts, texts longer than `max_chars`
+/// characters and texts with control characters, unless `allowed`, or with
+/// bidi embeddings, overrides and isolates, which could reorder what others
+/// see.
+fn check_text(
+    field: TextField,
+    text: &str,
+    max_chars: usize,
+    allowed: impl Fn(char) -> bool,
+) -> Result<String, InvalidText> {
+    let normalized: String = text.nfc().collect();
+    let trimmed = normalized.trim();
+    if trimmed.is_empty() {
+        return Err(InvalidText::Empty { field });
+    }
+    if trimmed.chars().count() > max_chars {
+        return Err(InvalidText::TooLong { field, max_chars });
+    }
+    let forbidden = |c: char| {
+        (c.is_control() && !allowed(c))
+            || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+    };
+    if let Some(character) = trimmed.chars().find(|&c| forbidden(c)) {
+        return Err(InvalidText::ForbiddenCharacter { field, character });
+    }
+    Ok(trimmed.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Emoji(String);
+
+impl Display for Emoji {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(dead_code)]
+impl Emoji {
+    /// Longest emoji accepted, in bytes. Emojis joined from several code
+    /// points, like families, take about 25 bytes.
+    pub const MAX_LEN: usize = 32;
+
+    pub fn new(emoji: String) -> Self {
+        Self(emoji)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Tells emojis apart from words, without knowing every emoji: they
+    /// are short, not plain ASCII and have no whitespace or control
+    /// characters.
+    pub fn is_plausible(&self) -> bool {
+        !self.0.is_empty()
+            && self.0.len() <= Self::MAX_LEN
+            && !self.0.is_ascii()
+            && !self.0.chars().any(|c| c.is_whitespace() || c.is_control())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -106,13 +293,377 @@ impl Display for ChatTimestamp {
 
 #[allow(dead_code)]
 impl ChatTimestamp {
+    // Truncated to microseconds, as that is the precision databases like
+    // postgres store timestamps with. Otherwise a timestamp would change
+    // when stored and read back.
     pub fn now() -> Self {
-        ChatTimestamp(chrono::Utc::now())
+        ChatTimestamp(chrono::Utc::now().trunc_subsecs(6))
     }
 
     pub fn epoch() -> Self {
         ChatTimestamp(chrono::DateTime::UNIX_EPOCH)
     }
+
+    pub fn from_datetime(datetime: DateTime<Utc>) -> Self {
+        ChatTimestamp(datetime)
+    }
+
+    pub fn as_datetime(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ChatName(String);
+
+impl Display for ChatName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(dead_code)]
+impl ChatName {
+    pub fn new(name: String) -> Self {
+        Self(name)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ChatTopic(String);
+
+impl Display for ChatTopic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(dead_code)]
+impl ChatTopic {
+    pub fn new(topic: String) -> Self {
+        Self(topic)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Describes a chat. Chats created implicitly by joining them have neither
+/// name nor topic, chats created before chats had creators lack a creator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMetadata {
+    pub chat_id: ChatId,
+    pub name: Option<ChatName>,
+    pub topic: Option<ChatTopic>,
+    pub creator: Option<UserId>,
+    pub created_at: ChatTimestamp,
+    /// Overrides the server's default retention limits for this chat.
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Overrides the server's default rate limits for this chat.
+    #[serde(default)]
+    pub rate_limit: RateLimitPolicy,
+}
+
+impl ChatMetadata {
+    /// Metadata of a chat created implicitly by `creator`.
+    pub fn unnamed(chat_id: ChatId, creator: UserId, created_at: ChatTimestamp) -> Self {
+        Self {
+            chat_id,
+            name: None,
+            topic: None,
+            creator: Some(creator),
+            created_at,
+            retention: RetentionPolicy::default(),
+            rate_limit: RateLimitPolicy::default(),
+        }
+    }
+}
+
+/// Limits how much of a chat history is kept, the oldest messages are pruned
+/// until every limit is met. Unset limits aren't enforced, limits set to
+/// `"unlimited"` lift the limits of the defaults.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Messages older than this many seconds are pruned.
+    #[serde(default, with = "retention_limit")]
+    pub max_age_secs: Option<u64>,
+    #[serde(default, with = "retention_limit")]
+    pub max_messages: Option<u64>,
+    /// Maximum total size in bytes of the message texts.
+    #[serde(default, with = "retention_limit")]
+    pub max_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// A limit which is never reached, written as `"unlimited"`. It is the
+    /// largest one every store can keep.
+    pub const UNLIMITED: u64 = i64::MAX as u64;
+
+    /// Takes the limits this policy doesn't set from `defaults`.
+    pub fn or(self, defaults: RetentionPolicy) -> Self {
+        Self {
+            max_age_secs: self.max_age_secs.or(defaults.max_age_secs),
+            max_messages: self.max_messages.or(defaults.max_messages),
+            max_bytes: self.max_bytes.or(defaults.max_bytes),
+        }
+    }
+
+    /// Resolves the limits at the time `now`.
+    pub fn limits_at(&self, now: &ChatTimestamp) -> RetentionLimits {
+        let limited = |limit: Option<u64>| limit.filter(|&limit| limit < Self::UNLIMITED);
+        RetentionLimits {
+            // Ages beyond the range of timestamps don't prune anything.
+            older_than: limited(self.max_age_secs).and_then(|max_age_secs| {
+                let max_age = chrono::Duration::try_seconds(i64::try_from(max_age_secs).ok()?)?;
+                now.as_datetime()
+                    .checked_sub_signed(max_age)
+                    .map(ChatTimestamp::from_datetime)
+            }),
+            max_messages: limited(self.max_messages),
+            max_bytes: limited(self.max_bytes),
+        }
+    }
+}
+
+// Retention limits are numbers or `"unlimited"`, which is kept as
+// `RetentionPolicy::UNLIMITED`, as are larger numbers.
+mod retention_limit {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::RetentionPolicy;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Limit {
+        Limited(u64),
+        Keyword(Keyword),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Keyword {
+        Unlimited,
+    }
+
+    pub fn serialize<S: Serializer>(limit: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        limit
+            .map(|limit| match limit {
+                RetentionPolicy::UNLIMITED.. => Limit::Keyword(Keyword::Unlimited),
+                limit => Limit::Limited(limit),
+            })
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        Ok(
+            Option::<Limit>::deserialize(deserializer)?.map(|limit| match limit {
+                Limit::Limited(limit) => limit.min(RetentionPolicy::UNLIMITED),
+                Limit::Keyword(Keyword::Unlimited) => RetentionPolicy::UNLIMITED,
+            }),
+        )
+    }
+}
+
+/// Limits how fast every member of a chat may send messages. Unset limits
+/// aren't enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitPolicy {
+    /// Messages a member may send at once.
+    pub burst: Option<u32>,
+    /// Messages a member may send per minute after a burst.
+    pub per_minute: Option<u32>,
+    /// Slow mode, seconds a member has to wait after every message.
+    pub slow_mode_secs: Option<u64>,
+}
+
+impl RateLimitPolicy {
+    /// Takes the limits this policy doesn't set from `defaults`.
+    pub fn or(self, defaults: RateLimitPolicy) -> Self {
+        Self {
+            burst: self.burst.or(defaults.burst),
+            per_minute: self.per_minute.or(defaults.per_minute),
+            slow_mode_secs: self.slow_mode_secs.or(defaults.slow_mode_secs),
+        }
+    }
+}
+
+/// The limits of a history at the time of pruning it, see
+/// `ChatStore::prune_messages`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionLimits {
+    /// Messages with an older timestamp are pruned.
+    pub older_than: Option<ChatTimestamp>,
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl RetentionLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.older_than.is_none() && self.max_messages.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// What pruning a history removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrunedMessages {
+    pub messages: u64,
+    /// The total size of the texts of the pruned messages.
+    pub bytes: u64,
+}
+
+/// What a member of a chat may do in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Member,
+    ReadOnly,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "owner" => Ok(Role::Owner),
+            "member" => Ok(Role::Member),
+            "read_only" => Ok(Role::ReadOnly),
+            _ => Err(format!("unknown role {role:?}")),
+        }
+    }
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Member => "member",
+            Role::ReadOnly => "read_only",
+        }
+    }
+
+    pub fn can_post(&self) -> bool {
+        !matches!(self, Role::ReadOnly)
+    }
+
+    /// Only owners may change the settings of a chat, like its retention.
+    pub fn can_manage(&self) -> bool {
+        matches!(self, Role::Owner)
+    }
+
+    /// Owners may invite with any role, members only as members or read-only
+    /// members and read-only members not at all.
+    pub fn can_grant(&self, role: Role) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Member => role != Role::Owner,
+            Role::ReadOnly => false,
+        }
+    }
+}
+
+/// Grants the role to whoever redeems it, until it is used up or expires.
+/// Handed out as signed token, see `crate::invites`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invite {
+    pub invite_id: InviteId,
+    pub chat_id: ChatId,
+    pub role: Role,
+    pub max_uses: u32,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InviteRedemption {
+    /// The user became a member with the role of the invite.
+    Joined(Role),
+    /// The user already was a member, the invite wasn't used.
+    AlreadyMember(Role),
+    /// The invite was used up by others.
+    UsedUp,
+}
+
+/// Selects a page of a chat history relative to a message of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryCursor {
+    /// The most recent messages.
+    Latest,
+    /// The messages right before the one with this id.
+    Before(EventId),
+    /// The messages right after the one with this id.
+    After(EventId),
+    /// The messages with a greater sequence number than this one, i.e. the
+    /// ones appended after it.
+    Since(u64),
+}
+
+/// A page of a chat history in the order the messages were sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub messages: Vec<ChatMessage>,
+    /// Passed as the same cursor again, e.g. as `before` when paging
+    /// backwards, it yields the next page. `None` if there are no more
+    /// messages in that direction.
+    pub next_cursor: Option<EventId>,
+}
+
+/// What is pushed to the members connected to a chat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatEvent {
+    Message(ChatMessage),
+    MessageEdited(ChatMessage),
+    /// Carries the tombstone left by the deleted message.
+    MessageDeleted(ChatMessage),
+    ReactionAdded(ReactionChange),
+    ReactionRemoved(ReactionChange),
+    NoteCreated(Note),
+    NoteUpdated(Note),
+    /// The first session of the member connected to the chat.
+    MemberJoined(UserId),
+    /// The last session of the member disconnected from the chat.
+    MemberLeft(UserId),
+    /// The member is typing a message. Like joins and leaves it isn't
+    /// stored.
+    Typing(UserId),
+    /// A member has read the chat up to a message.
+    ReadUpTo(ReadReceipt),
+}
+
+impl ChatEvent {
+    /// Whether the event changes what was pushed before, like edits,
+    /// deletions, reactions, notes and read receipts. Unlike new messages,
+    /// members skipping them can't catch up with them from the history.
+    pub fn is_amendment(&self) -> bool {
+        match self {
+            ChatEvent::MessageEdited(_)
+            | ChatEvent::MessageDeleted(_)
+            | ChatEvent::ReactionAdded(_)
+            | ChatEvent::ReactionRemoved(_)
+            | ChatEvent::NoteCreated(_)
+            | ChatEvent::NoteUpdated(_)
+            | ChatEvent::ReadUpTo(_) => true,
+            ChatEvent::Message(_)
+            | ChatEvent::MemberJoined(_)
+            | ChatEvent::MemberLeft(_)
+            | ChatEvent::Typing(_) => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -120,9 +671,180 @@ pub struct ChatMessage {
     pub event_id: EventId,
     pub timestamp: ChatTimestamp,
     pub chat_id: ChatId,
+    /// Numbers the messages of a chat in the order they were appended,
+    /// starting with 1. It is assigned by the store on appending, so clients
+    /// can resume from the last message they received.
+    #[serde(default)]
+    pub sequence: u64,
+    pub user_id: UserId,
+    pub display_name: DisplayName,
+    /// Empty for deleted messages.
+    pub message: Message,
+    /// The message this one replies to, in the same chat.
+    #[serde(default)]
+    pub in_reply_to: Option<EventId>,
+    /// The first message of the thread a reply belongs to, i.e. the one
+    /// replied to unless that is a reply itself. Set by the chat server,
+    /// `None` for messages which aren't replies.
+    #[serde(default)]
+    pub thread_root: Option<EventId>,
+    /// When the message was last edited by its author, see
+    /// `MessageRevision` for the texts it had before.
+    #[serde(default)]
+    pub edited_at: Option<ChatTimestamp>,
+    /// Set for the tombstone of a deleted message, which keeps its place in
+    /// the history but neither its text nor its revisions.
+    #[serde(default)]
+    pub deleted_at: Option<ChatTimestamp>,
+    /// In the order of the first reaction with each emoji.
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+impl ChatMessage {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    pub fn has_reacted(&self, emoji: &Emoji, user_id: UserId) -> bool {
+        self.reactions
+            .iter()
+            .any(|reaction| reaction.emoji == *emoji && reaction.users.contains(&user_id))
+    }
+
+    /// Yields whether the user didn't react with the emoji yet.
+    pub fn add_reaction(&mut self, emoji: &Emoji, user_id: UserId) -> bool {
+        match self
+            .reactions
+            .iter_mut()
+            .find(|reaction| reaction.emoji == *emoji)
+        {
+            Some(reaction) if reaction.users.contains(&user_id) => false,
+            Some(reaction) => {
+                reaction.users.push(user_id);
+                reaction.count = reaction.users.len() as u64;
+                true
+            }
+            None => {
+                self.reactions.push(Reaction {
+                    emoji: emoji.clone(),
+                    count: 1,
+                    users: vec![user_id],
+                });
+                true
+            }
+        }
+    }
+
+    /// Yields whether the user had reacted with the emoji.
+    pub fn remove_reaction(&mut self, emoji: &Emoji, user_id: UserId) -> bool {
+        let Some(position) = self
+            .reactions
+            .iter()
+            .position(|reaction| reaction.emoji == *emoji)
+        else {
+            return false;
+        };
+        let reaction = &mut self.reactions[position];
+        let Some(user_position) = reaction.users.iter().position(|user| *user == user_id) else {
+            return false;
+        };
+        reaction.users.remove(user_position);
+        reaction.count = reaction.users.len() as u64;
+        if reaction.users.is_empty() {
+            self.reactions.remove(position);
+        }
+        true
+    }
+}
+
+/// The users who reacted to a message with an emoji, in the order they
+/// reacted.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: Emoji,
+    /// The number of `users`.
+    pub count: u64,
+    pub users: Vec<UserId>,
+}
+
+/// The message a member has read a chat up to, including it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadReceipt {
+    pub user_id: UserId,
+    pub event_id: EventId,
+    /// The sequence number of the message, read markers only move forward.
+    pub sequence: u64,
+}
+
+/// A chat the user is a member of with the messages of others the user
+/// hasn't read yet. Deleted messages don't count.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnreadMessages {
+    pub chat_id: ChatId,
+    /// `None` until the user read a message of the chat.
+    pub last_read: Option<EventId>,
+    pub unread: u64,
+}
+
+/// A chat the user is a member of, as listed for the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatSummary {
+    #[serde(flatten)]
+    pub metadata: ChatMetadata,
+    /// The time of the latest message, deleted ones included, or the creation
+    /// of the chat without any.
+    pub last_activity: ChatTimestamp,
+    /// The latest message which isn't deleted.
+    pub last_message: Option<MessagePreview>,
+}
+
+/// The beginning of a message, enough to show it in a list of chats.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessagePreview {
+    pub event_id: EventId,
     pub user_id: UserId,
     pub display_name: DisplayName,
+    pub timestamp: ChatTimestamp,
+    pub text: String,
+    /// Whether the text was cut at `MessagePreview::MAX_CHARS` characters.
+    pub truncated: bool,
+}
+
+impl MessagePreview {
+    pub const MAX_CHARS: usize = 100;
+
+    pub fn of(message: &ChatMessage) -> Self {
+        let text = message.message.as_str();
+        let cut = text
+            .char_indices()
+            .nth(Self::MAX_CHARS)
+            .map(|(index, _)| index);
+        Self {
+            event_id: message.event_id,
+            user_id: message.user_id,
+            display_name: message.display_name.clone(),
+            timestamp: message.timestamp.clone(),
+            text: cut.map_or(text, |cut| &text[..cut]).to_string(),
+            truncated: cut.is_some(),
+        }
+    }
+}
+
+/// A reaction added to or removed from a message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReactionChange {
+    pub event_id: EventId,
+    pub user_id: UserId,
+    pub emoji: Emoji,
+}
+
+/// A text a message had before it was edited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageRevision {
     pub message: Message,
+    /// When the message got this text, i.e. when it was sent or edited.
+    pub timestamp: ChatTimestamp,
 }
 
 impl Display for ChatMessage {
@@ -0,0 +1,343 @@
+// Messages are limited by token buckets per member of a chat, per
+// connection and per IP address. A message has to get a token from every
+// bucket, it takes none if one of them is empty. The limits of the members
+// are the rate limit policy of the chat, falling back to the configured
+// defaults, the other limits are configured only. The other requests of a
+// connection, like edits and reactions, have a bucket of their own, typing
+// indicators are passed on once per interval and member. Buckets which are
+// full again are swept from time to time, see `sweep_rate_limits`.
+
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use super::{
+    ChatServer, ChatServerErrors,
+    models::{ChatId, ChatMetadata, RateLimitPolicy, UserId},
+};
+use crate::settings::{RateLimit, RateLimitSettings};
+
+/// A bucket of tokens refilled at a constant rate, one every `interval`.
+///
+/// Instead of counting the tokens, it keeps the time it is full again at,
+/// every token taken moves it one `interval` further.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    interval: Duration,
+    // How far `full_at` may be ahead, the time to refill all tokens but one.
+    tolerance: Duration,
+    full_at: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket of `capacity` tokens, at least one.
+    pub fn new(capacity: u32, interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            tolerance: interval.saturating_mul(capacity.saturating_sub(1)),
+            full_at: now,
+        }
+    }
+
+    /// A full bucket of `limit.burst` tokens refilled with `limit.per_minute`
+    /// tokens per minute.
+    pub fn with_limit(limit: RateLimit, now: Instant) -> Self {
+        Self::new(
+            limit.burst,
+            Duration::from_secs(60) / limit.per_minute.max(1),
+            now,
+        )
+    }
+
+    /// Yields how long it takes until a token is available, zero if one is.
+    pub fn wait_time(&self, now: Instant) -> Duration {
+        self.full_at
+            .saturating_duration_since(now)
+            .saturating_sub(self.tolerance)
+    }
+
+    /// Takes a token, even if none is available.
+    pub fn take(&mut self, now: Instant) {
+        self.full_at = self.full_at.max(now) + self.interval;
+    }
+
+    /// Takes a token if one is available, otherwise yields how long it takes
+    /// until one is.
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let wait_time = self.wait_time(now);
+        if !wait_time.is_zero() {
+            return Err(wait_time);
+        }
+        self.take(now);
+        Ok(())
+    }
+
+    pub fn is_full(&self, now: Instant) -> bool {
+        self.full_at <= now
+    }
+}
+
+/// The buckets of a member of a chat, built from the policy of the chat.
+#[derive(Debug)]
+struct MemberBuckets {
+    policy: RateLimitPolicy,
+    messages: Option<TokenBucket>,
+    slow_mode: Option<TokenBucket>,
+}
+
+impl MemberBuckets {
+    fn new(policy: RateLimitPolicy, now: Instant) -> Self {
+        let messages = match (policy.burst, policy.per_minute) {
+            (None, None) => None,
+            // Without a rate, the burst is all a member may send per minute
+            // and vice versa.
+            (burst, per_minute) => Some(TokenBucket::with_limit(
+                RateLimit {
+                    burst: burst.or(per_minute).unwrap_or(1),
+                    per_minute: per_minute.or(burst).unwrap_or(1),
+                },
+                now,
+            )),
+        };
+        Self {
+            policy,
+            messages,
+            slow_mode: policy
+                .slow_mode_secs
+                .map(|secs| TokenBucket::new(1, Duration::from_secs(secs), now)),
+        }
+    }
+
+    fn buckets(&mut self) -> impl Iterator<Item = &mut TokenBucket> {
+        self.messages.iter_mut().chain(self.slow_mode.iter_mut())
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        self.messages
+            .iter()
+            .chain(self.slow_mode.iter())
+            .all(|bucket| bucket.is_full(now))
+    }
+}
+
+/// The buckets of the members of all chats and of all IP addresses.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    settings: RateLimitSettings,
+    members: DashMap<(ChatId, UserId), MemberBuckets>,
+    ips: DashMap<IpAddr, TokenBucket>,
+    typing: DashMap<(ChatId, UserId), TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            members: DashMap::new(),
+            ips: DashMap::new(),
+            typing: DashMap::new(),
+        }
+    }
+
+    // Takes a token from every bucket of the message, if all have one.
+    fn admit(
+        &self,
+        chat: &ChatMetadata,
+        user_id: UserId,
+        connection: &mut ConnectionRateLimits,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let policy = chat.rate_limit.or(self.settings.defaults);
+        // Always locking the member before the IP address, so concurrent
+        // messages can't deadlock.
+        let mut member = self
+            .members
+            .entry((chat.chat_id, user_id))
+            .or_insert_with(|| MemberBuckets::new(policy, now));
+        if member.policy != policy {
+            // Changed by an owner meanwhile, the members start over.
+            *member = MemberBuckets::new(policy, now);
+        }
+        let mut ip = connection.ip.map(|ip| {
+            self.ips
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::with_limit(self.settings.per_ip, now))
+        });
+        let mut buckets: Vec<&mut TokenBucket> = member
+            .buckets()
+            .chain(ip.as_deref_mut())
+            .chain([&mut connection.messages])
+            .collect();
+        let wait_time = buckets
+            .iter()
+            .map(|bucket| bucket.wait_time(now))
+            .max()
+            .unwrap_or_default();
+        if !wait_time.is_zero() {
+            return Err(wait_time);
+        }
+        for bucket in &mut buckets {
+            bucket.take(now);
+        }
+        Ok(())
+    }
+
+    // Takes the token of the typing indicator of the member, if it has one.
+    fn admit_typing(&self, chat_id: ChatId, user_id: UserId, now: Instant) -> bool {
+        let interval = Duration::from_secs(self.settings.typing_interval_secs);
+        self.typing
+            .entry((chat_id, user_id))
+            .or_insert_with(|| TokenBucket::new(1, interval, now))
+            .try_take(now)
+            .is_ok()
+    }
+
+    // Buckets which are full again don't remember anything.
+    fn forget_idle(&self, chat_id: ChatId, user_id: UserId, ip: Option<IpAddr>, now: Instant) {
+        self.members
+            .remove_if(&(chat_id, user_id), |_, member| member.is_full(now));
+        self.typing
+            .remove_if(&(chat_id, user_id), |_, bucket| bucket.is_full(now));
+        if let Some(ip) = ip {
+            self.ips.remove_if(&ip, |_, bucket| bucket.is_full(now));
+        }
+    }
+
+    // Catches the buckets which weren't full yet when their connections were
+    // closed. Yields how many were dropped.
+    fn sweep(&self, now: Instant) -> usize {
+        let before = self.len();
+        self.members.retain(|_, member| !member.is_full(now));
+        self.ips.retain(|_, bucket| !bucket.is_full(now));
+        self.typing.retain(|_, bucket| !bucket.is_full(now));
+        before - self.len()
+    }
+
+    fn len(&self) -> usize {
+        self.members.len() + self.ips.len() + self.typing.len()
+    }
+}
+
+/// The limits of a single websocket connection.
+#[derive(Debug)]
+pub struct ConnectionRateLimits {
+    ip: Option<IpAddr>,
+    messages: TokenBucket,
+    events: TokenBucket,
+    violations: TokenBucket,
+}
+
+impl ConnectionRateLimits {
+    /// Counts a rejected message. Yields whether the connection may go on,
+    /// connections exceeding the rate of violations should be closed.
+    pub fn tolerate_violation(&mut self) -> bool {
+        self.violations.try_take(Instant::now()).is_ok()
+    }
+}
+
+impl ChatServer {
+    /// Starts limiting a connection from the IP address, if known.
+    pub fn connection_rate_limits(&self, ip: Option<IpAddr>) -> ConnectionRateLimits {
+        let now = Instant::now();
+        let settings = &self.rate_limiter.settings;
+        ConnectionRateLimits {
+            ip,
+            messages: TokenBucket::with_limit(settings.per_connection, now),
+            events: TokenBucket::with_limit(settings.per_connection_events, now),
+            violations: TokenBucket::with_limit(settings.violations, now),
+        }
+    }
+
+    /// Admits a message of the user to the chat over the connection, unless
+    /// it exceeds a rate limit. Has to be called before every message sent.
+    pub async fn admit_message(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        connection: &mut ConnectionRateLimits,
+    ) -> Result<(), ChatServerErrors> {
+        let chat = self
+            .store
+            .read_chat(chat_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))?;
+        self.rate_limiter
+            .admit(&chat, user_id, connection, Instant::now())
+            .map_err(|retry_after| ChatServerErrors::rate_limited(chat_id, user_id, retry_after))
+    }
+
+    /// Admits a request other than a message over the connection, like an
+    /// edit, a reaction or a read receipt, unless it exceeds the rate limit.
+    /// Has to be called before every such request passed on to the members.
+    pub fn admit_event(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        connection: &mut ConnectionRateLimits,
+    ) -> Result<(), ChatServerErrors> {
+        connection
+            .events
+            .try_take(Instant::now())
+            .map_err(|retry_after| ChatServerErrors::rate_limited(chat_id, user_id, retry_after))
+    }
+
+    /// Whether a typing indicator of the user may be passed on, at most one
+    /// is every `typing_interval_secs`. The others are dropped, as typing
+    /// indicators are sent repeatedly anyway.
+    pub fn admit_typing(&self, chat_id: ChatId, user_id: UserId) -> bool {
+        self.rate_limiter
+            .admit_typing(chat_id, user_id, Instant::now())
+    }
+
+    /// Drops the limits of all members and IP addresses which didn't send
+    /// anything for long enough, so they don't pile up. Called periodically,
+    /// yields how many were dropped.
+    pub fn sweep_rate_limits(&self) -> usize {
+        let swept = self.rate_limiter.sweep(Instant::now());
+        if swept > 0 {
+            tracing::debug!(swept, "idle rate limits dropped");
+        }
+        swept
+    }
+
+    /// Stops limiting a connection, which is closed. The limits of the member
+    /// and of the IP address are dropped, if nothing was sent for long
+    /// enough.
+    pub fn release_rate_limits(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        connection: ConnectionRateLimits,
+    ) {
+        self.rate_limiter
+            .forget_idle(chat_id, user_id, connection.ip, Instant::now());
+    }
+
+    /// Replaces the rate limit policy of a chat. Only owners may change it.
+    pub async fn set_rate_limit(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        rate_limit: RateLimitPolicy,
+    ) -> Result<ChatMetadata, ChatServerErrors> {
+        let role = self.authorize(chat_id, user_id).await?;
+        if !role.can_manage() {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                user_id,
+                format!("{role} members can't change the rate limits"),
+            ));
+        }
+        if !self.store.update_rate_limit(chat_id, &rate_limit).await? {
+            return Err(ChatServerErrors::chat_not_found(chat_id));
+        }
+        tracing::info!(%chat_id, %user_id, ?rate_limit, "rate limit policy changed");
+        self.store
+            .read_chat(chat_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))
+    }
+}
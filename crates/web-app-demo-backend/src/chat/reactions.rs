@@ -0,0 +1,86 @@
+// Reactions are stored per message and pushed to the connected members as
+// changes. A message read from the history carries its reactions aggregated
+// per emoji, see `ChatMessage::reactions`.
+
+use super::{
+    ChatServer, ChatServerErrors,
+    models::{ChatEvent, ChatId, Emoji, EventId, ReactionChange, UserId},
+};
+
+impl ChatServer {
+    /// Adds the reaction of the user with the emoji to a message and pushes
+    /// it to the members connected to the chat. Reacting twice with the same
+    /// emoji changes nothing.
+    pub async fn add_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: Emoji,
+    ) -> Result<(), ChatServerErrors> {
+        let role = self.authorize(chat_id, user_id).await?;
+        if !role.can_post() {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                user_id,
+                format!("{role} members can't react"),
+            ));
+        }
+        let message = self
+            .store
+            .read_message(chat_id, event_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::event_not_found(chat_id, event_id))?;
+        if message.is_deleted() {
+            return Err(ChatServerErrors::message_deleted(chat_id, event_id));
+        }
+        // Storing while the channel is locked, so the changes of a message
+        // reach the members in the order they were stored and members
+        // joining meanwhile don't miss one.
+        let mut channel = self.lock_channel(chat_id).await;
+        let added = self
+            .store
+            .add_reaction(chat_id, event_id, user_id, &emoji)
+            .await;
+        if let Ok(true) = added {
+            channel.publish(ChatEvent::ReactionAdded(ReactionChange {
+                event_id,
+                user_id,
+                emoji,
+            }));
+        }
+        self.close_channel_if_unused(chat_id, &mut channel);
+        added.map(|_| ())
+    }
+
+    /// Removes the reaction of the user with the emoji from a message and
+    /// pushes the removal to the members connected to the chat. Removing a
+    /// reaction the user didn't add changes nothing.
+    pub async fn remove_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: Emoji,
+    ) -> Result<(), ChatServerErrors> {
+        // Members who may no longer react may still take back their reactions.
+        self.authorize(chat_id, user_id).await?;
+        if self.store.read_message(chat_id, event_id).await?.is_none() {
+            return Err(ChatServerErrors::event_not_found(chat_id, event_id));
+        }
+        let mut channel = self.lock_channel(chat_id).await;
+        let removed = self
+            .store
+            .remove_reaction(chat_id, event_id, user_id, &emoji)
+            .await;
+        if let Ok(true) = removed {
+            channel.publish(ChatEvent::ReactionRemoved(ReactionChange {
+                event_id,
+                user_id,
+                emoji,
+            }));
+        }
+        self.close_channel_if_unused(chat_id, &mut channel);
+        removed.map(|_| ())
+    }
+}
@@ -0,0 +1,41 @@
+// Every member has a read marker per chat, the last message the member has
+// read. Markers only move forward, so the members connected to the chat are
+// told where a marker moved to, not how far it moved.
+
+use super::{
+    ChatServer, ChatServerErrors,
+    models::{ChatEvent, ChatId, EventId, UnreadMessages, UserId},
+};
+
+impl ChatServer {
+    /// Moves the read marker of the user to a message and pushes the receipt
+    /// to the members connected to the chat. Marking a message read which is
+    /// older than the marker changes nothing.
+    pub async fn mark_read(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+    ) -> Result<(), ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        let receipt = self.store.mark_read(chat_id, user_id, event_id).await?;
+        if let Some(receipt) = receipt {
+            // Receipts of the same user may overtake each other here, clients
+            // keep the one with the highest sequence number.
+            self.broadcast_event(chat_id, ChatEvent::ReadUpTo(receipt))
+                .await;
+        } else if self.store.read_message(chat_id, event_id).await?.is_none() {
+            return Err(ChatServerErrors::event_not_found(chat_id, event_id));
+        }
+        Ok(())
+    }
+
+    /// Yields the number of unread messages in every chat the user is a
+    /// member of.
+    pub async fn get_unread_messages(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<UnreadMessages>, ChatServerErrors> {
+        self.store.read_unread_messages(user_id).await
+    }
+}
@@ -0,0 +1,152 @@
+// Pruning the histories runs in the background, see `run_retention`. The
+// limits of a chat are its own retention policy, falling back to the
+// configured defaults.
+
+use std::sync::Arc;
+
+use super::{
+    ChatServer, ChatServerErrors,
+    models::{ChatId, ChatMetadata, ChatTimestamp, PrunedMessages, RetentionPolicy, UserId},
+};
+use crate::settings::RetentionSettings;
+
+/// What a retention run removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub pruned_chats: u64,
+    pub pruned: PrunedMessages,
+    pub evicted_chats: u64,
+}
+
+impl ChatServer {
+    /// Replaces the retention policy of a chat. Only owners may change it.
+    pub async fn set_retention(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        retention: RetentionPolicy,
+    ) -> Result<ChatMetadata, ChatServerErrors> {
+        let role = self.authorize(chat_id, user_id).await?;
+        if !role.can_manage() {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                user_id,
+                format!("{role} members can't change the retention policy"),
+            ));
+        }
+        if !self.store.update_retention(chat_id, &retention).await? {
+            return Err(ChatServerErrors::chat_not_found(chat_id));
+        }
+        tracing::info!(%chat_id, %user_id, ?retention, "retention policy changed");
+        self.store
+            .read_chat(chat_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))
+    }
+
+    /// Prunes the histories of all chats as of `now` and evicts the chats
+    /// nobody used, see `ChatStore::delete_chat_if_unused`, once they are old
+    /// enough and nobody is connected to them. A chat that fails is logged
+    /// and skipped, the store is compacted afterwards in any case, see
+    /// `ChatStore::compact`.
+    pub async fn enforce_retention(
+        &self,
+        settings: &RetentionSettings,
+        now: &ChatTimestamp,
+    ) -> Result<RetentionReport, ChatServerErrors> {
+        let evict_created_before = settings
+            .evict_empty_chats_after_secs
+            .and_then(|after_secs| {
+                let after = chrono::Duration::try_seconds(i64::try_from(after_secs).ok()?)?;
+                now.as_datetime()
+                    .checked_sub_signed(after)
+                    .map(ChatTimestamp::from_datetime)
+            });
+        let mut report = RetentionReport::default();
+        for chat in self.store.read_chats().await? {
+            let chat_id = chat.chat_id;
+            let limits = chat.retention.or(settings.defaults).limits_at(now);
+            if !limits.is_unlimited() {
+                let pruned = match self.store.prune_messages(chat_id, &limits).await {
+                    Ok(pruned) => pruned,
+                    Err(err) => {
+                        tracing::error!(%chat_id, %err, "pruning history failed");
+                        continue;
+                    }
+                };
+                if pruned.messages > 0 {
+                    tracing::info!(
+                        %chat_id,
+                        messages = pruned.messages,
+                        bytes = pruned.bytes,
+                        "history pruned"
+                    );
+                    report.pruned_chats += 1;
+                    report.pruned.messages += pruned.messages;
+                    report.pruned.bytes += pruned.bytes;
+                }
+            }
+            // Chats emptied by pruning were used, so the creation is the last
+            // activity of the chats evicted.
+            if evict_created_before
+                .as_ref()
+                .is_none_or(|created_before| chat.created_at >= *created_before)
+            {
+                continue;
+            }
+            match self.evict_chat_if_idle(chat_id).await {
+                Ok(true) => {
+                    tracing::info!(%chat_id, "unused chat evicted");
+                    report.evicted_chats += 1;
+                }
+                Ok(false) => {}
+                Err(err) => tracing::error!(%chat_id, %err, "evicting chat failed"),
+            }
+        }
+        // Also removes the texts of the messages deleted since the last run.
+        self.store.compact().await?;
+        if report != RetentionReport::default() {
+            tracing::info!(
+                pruned_chats = report.pruned_chats,
+                pruned_messages = report.pruned.messages,
+                pruned_bytes = report.pruned.bytes,
+                evicted_chats = report.evicted_chats,
+                "retention enforced"
+            );
+        }
+        Ok(report)
+    }
+
+    // Holding the channel lock keeps members from joining meanwhile.
+    async fn evict_chat_if_idle(&self, chat_id: ChatId) -> Result<bool, ChatServerErrors> {
+        let mut channel = self.lock_channel(chat_id).await;
+        let evicted = if channel.sender.receiver_count() == 0 {
+            self.store.delete_chat_if_unused(chat_id).await
+        } else {
+            Ok(false)
+        };
+        self.close_channel_if_unused(chat_id, &mut channel);
+        evicted
+    }
+}
+
+/// Enforces the retention settings every `prune_interval_secs` seconds,
+/// forever, and drops idle rate limits. Failures are logged and retried with
+/// the next run.
+pub async fn run_retention(chat_server: Arc<ChatServer>, settings: RetentionSettings) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(settings.prune_interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        // Piggybacks on the retention runs, the rate limits don't need a
+        // schedule of their own.
+        chat_server.sweep_rate_limits();
+        if let Err(err) = chat_server
+            .enforce_retention(&settings, &ChatTimestamp::now())
+            .await
+        {
+            tracing::error!(%err, "enforcing retention failed");
+        }
+    }
+}
@@ -0,0 +1,1513 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{
+    ChatStore,
+    memory::{ChatSnapshot, InMemoryChatStore},
+};
+use crate::{
+    chat::{
+        ChatServerErrors,
+        models::{
+            ChatId, ChatMessage, ChatMetadata, ChatName, ChatSummary, ChatTimestamp, ChatTopic,
+            Emoji, EventId, HistoryCursor, Invite, InviteId, InviteRedemption, Message,
+            MessageRevision, PrunedMessages, RateLimitPolicy, ReadReceipt, RetentionLimits,
+            RetentionPolicy, Role, UnreadMessages, UserId,
+        },
+    },
+    notes::models::{Note, NoteId},
+    settings::{FsyncPolicy, JournalSettings},
+};
+
+// Every record is framed as
+//
+//   length of the payload: u32 little endian
+//   crc32 of the payload: u32 little endian
+//   payload: json serialized `JournalRecord`
+//
+// so a record torn by a crash or a flipped bit is detected on replay.
+const HEADER_SIZE: usize = 8;
+
+const SEGMENT_PREFIX: &str = "journal-";
+const SEGMENT_SUFFIX: &str = ".log";
+// A compacted journal is written to this file first and only becomes a
+// segment once it is complete.
+const COMPACTION_FILE: &str = "compaction.tmp";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum JournalRecord {
+    // Journals written before chats had members and metadata lack all but
+    // the chat id.
+    ChatCreated {
+        chat_id: ChatId,
+        #[serde(default)]
+        creator: Option<UserId>,
+        #[serde(default)]
+        name: Option<ChatName>,
+        #[serde(default)]
+        topic: Option<ChatTopic>,
+        #[serde(default = "ChatTimestamp::epoch")]
+        created_at: ChatTimestamp,
+        #[serde(default)]
+        retention: RetentionPolicy,
+        #[serde(default)]
+        rate_limit: RateLimitPolicy,
+    },
+    MessageAppended {
+        message: ChatMessage,
+    },
+    InviteRedeemed {
+        chat_id: ChatId,
+        invite_id: InviteId,
+        user_id: UserId,
+        role: Role,
+    },
+    NoteCreated {
+        note: Note,
+    },
+    NoteUpdated {
+        note: Note,
+    },
+    RetentionUpdated {
+        chat_id: ChatId,
+        retention: RetentionPolicy,
+    },
+    RateLimitUpdated {
+        chat_id: ChatId,
+        rate_limit: RateLimitPolicy,
+    },
+    MessagesPruned {
+        chat_id: ChatId,
+        last_pruned: u64,
+    },
+    ChatDeleted {
+        chat_id: ChatId,
+    },
+    MessageEdited {
+        chat_id: ChatId,
+        event_id: EventId,
+        message: Message,
+        edited_at: ChatTimestamp,
+    },
+    MessageDeleted {
+        chat_id: ChatId,
+        event_id: EventId,
+        deleted_at: ChatTimestamp,
+    },
+    ReactionAdded {
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: Emoji,
+    },
+    ReactionRemoved {
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: Emoji,
+    },
+    ReadMarked {
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+    },
+    // Starts a compacted journal, the chats restored by the following
+    // records supersede whatever was replayed before.
+    Compacted,
+    ChatRestored {
+        snapshot: Box<ChatSnapshot>,
+    },
+}
+
+impl JournalRecord {
+    // Texts removed by these records stay in the earlier records until the
+    // journal is compacted.
+    fn removes_texts(&self) -> bool {
+        matches!(
+            self,
+            JournalRecord::MessagesPruned { .. }
+                | JournalRecord::ChatDeleted { .. }
+                | JournalRecord::MessageDeleted { .. }
+        )
+    }
+}
+
+/// Keeps the histories in memory and writes every change to an append-only
+/// journal split into segment files. On open the journal is replayed to
+/// rebuild the histories.
+///
+/// The journal is written while holding a lock, so the order of the records
+/// is the order of the histories. Writes, including the fsync if the policy
+/// asks for it, run on the blocking threads of tokio, like the replay.
+///
+/// Pruned and deleted texts stay in the journal until it is compacted, i.e.
+/// rewritten as a snapshot of the histories, see `ChatStore::compact`. That
+/// happens on open, too.
+pub struct JournalChatStore {
+    histories: Arc<InMemoryChatStore>,
+    writer: Arc<Mutex<JournalWriter>>,
+}
+
+impl JournalChatStore {
+    pub async fn open(settings: &JournalSettings) -> Result<Self, ChatServerErrors> {
+        let replayed = settings.clone();
+        let (histories, writer) = tokio::task::spawn_blocking(move || Self::replay(&replayed))
+            .await
+            .map_err(|err| {
+                ChatServerErrors::storage_failure("replaying journal".to_string(), err)
+            })??;
+        let writer = Arc::new(Mutex::new(writer));
+        if settings.fsync == FsyncPolicy::Periodic {
+            spawn_periodic_fsync(
+                Arc::downgrade(&writer),
+                Duration::from_millis(settings.fsync_interval_ms),
+            );
+        }
+
+        Ok(Self {
+            histories: Arc::new(histories),
+            writer,
+        })
+    }
+
+    fn replay(
+        settings: &JournalSettings,
+    ) -> Result<(InMemoryChatStore, JournalWriter), ChatServerErrors> {
+        fs::create_dir_all(&settings.directory).map_err(|err| {
+            ChatServerErrors::storage_failure(
+                format!(
+                    "creating journal directory {}",
+                    settings.directory.display()
+                ),
+                err,
+            )
+        })?;
+
+        let histories = InMemoryChatStore::new();
+        let segments = list_segments(&settings.directory)?;
+        let mut replayed_records = 0usize;
+        let mut removals = 0usize;
+        for (index, (_, path)) in segments.iter().enumerate() {
+            let is_last_segment = index + 1 == segments.len();
+            for record in read_segment(path, is_last_segment)? {
+                if record.removes_texts() {
+                    removals += 1;
+                }
+                match record {
+                    JournalRecord::ChatCreated {
+                        chat_id,
+                        creator,
+                        name,
+                        topic,
+                        created_at,
+                        retention,
+                        rate_limit,
+                    } => {
+                        histories.insert_chat(&ChatMetadata {
+                            chat_id,
+                            name,
+                            topic,
+                            creator,
+                            created_at,
+                            retention,
+                            rate_limit,
+                        });
+                    }
+                    JournalRecord::MessageAppended { message } => {
+                        // Older versions created chats with their first
+                        // message, without recording it.
+                        histories.insert_chat(&ChatMetadata::unnamed(
+                            message.chat_id,
+                            message.user_id,
+                            message.timestamp.clone(),
+                        ));
+                        // Renumbers the message, which yields the recorded
+                        // sequence number again. Journals written before
+                        // messages had sequence numbers lack them.
+                        histories.push_message_with(&message, |_| Ok(()))?;
+                    }
+                    JournalRecord::InviteRedeemed {
+                        chat_id,
+                        invite_id,
+                        user_id,
+                        role,
+                    } => {
+                        histories.restore_invite_use(chat_id, invite_id, user_id, role)?;
+                    }
+                    JournalRecord::NoteCreated { note } => {
+                        histories.insert_note_with(&note, || Ok(()))?;
+                    }
+                    JournalRecord::NoteUpdated { note } => {
+                        histories.replace_note_with(&note, || Ok(()))?;
+                    }
+                    JournalRecord::RetentionUpdated { chat_id, retention } => {
+                        histories.set_retention_with(chat_id, &retention, || Ok(()))?;
+                    }
+                    JournalRecord::RateLimitUpdated {
+                        chat_id,
+                        rate_limit,
+                    } => {
+                        histories.set_rate_limit_with(chat_id, &rate_limit, || Ok(()))?;
+                    }
+                    JournalRecord::MessagesPruned {
+                        chat_id,
+                        last_pruned,
+                    } => {
+                        histories.restore_pruning(chat_id, last_pruned)?;
+                    }
+                    // Deleted as recorded, journals of older versions deleted
+                    // chats by other rules.
+                    JournalRecord::ChatDeleted { chat_id } => {
+                        histories.remove_chat(chat_id);
+                    }
+                    JournalRecord::MessageEdited {
+                        chat_id,
+                        event_id,
+                        message,
+                        edited_at,
+                    } => {
+                        histories.edit_message_with(
+                            chat_id,
+                            event_id,
+                            &message,
+                            &edited_at,
+                            || Ok(()),
+                        )?;
+                    }
+                    JournalRecord::MessageDeleted {
+                        chat_id,
+                        event_id,
+                        deleted_at,
+                    } => {
+                        histories.delete_message_with(chat_id, event_id, &deleted_at, || Ok(()))?;
+                    }
+                    JournalRecord::ReactionAdded {
+                        chat_id,
+                        event_id,
+                        user_id,
+                        emoji,
+                    } => {
+                        histories
+                            .add_reaction_with(chat_id, event_id, user_id, &emoji, || Ok(()))?;
+                    }
+                    JournalRecord::ReactionRemoved {
+                        chat_id,
+                        event_id,
+                        user_id,
+                        emoji,
+                    } => {
+                        histories.remove_reaction_with(
+                            chat_id,
+                            event_id,
+                            user_id,
+                            &emoji,
+                            || Ok(()),
+                        )?;
+                    }
+                    JournalRecord::ReadMarked {
+                        chat_id,
+                        user_id,
+                        event_id,
+                    } => {
+                        histories.mark_read_with(chat_id, user_id, event_id, || Ok(()))?;
+                    }
+                    // Records before the snapshot are left over by a
+                    // compaction which didn't finish removing them.
+                    JournalRecord::Compacted => {
+                        histories.clear();
+                        removals = usize::from(replayed_records > 0);
+                    }
+                    JournalRecord::ChatRestored { snapshot } => {
+                        histories.restore_chat(*snapshot);
+                    }
+                }
+                replayed_records += 1;
+            }
+        }
+        tracing::info!(
+            segments = segments.len(),
+            replayed_records,
+            "journal replayed"
+        );
+
+        let segment_number = segments.last().map(|(number, _)| *number).unwrap_or(0);
+        let mut writer = JournalWriter::open(settings, segment_number)?;
+        writer.removals = removals;
+        writer.compact(&histories)?;
+        Ok((histories, writer))
+    }
+
+    // Changes the histories on a blocking thread, holding the writer to
+    // journal the change with.
+    async fn journaled<T: Send + 'static>(
+        &self,
+        change: impl FnOnce(&InMemoryChatStore, &mut JournalWriter) -> Result<T, ChatServerErrors>
+        + Send
+        + 'static,
+    ) -> Result<T, ChatServerErrors> {
+        let mut writer = self.writer.clone().lock_owned().await;
+        let histories = self.histories.clone();
+        tokio::task::spawn_blocking(move || change(&histories, &mut writer))
+            .await
+            .map_err(|err| ChatServerErrors::storage_failure("writing journal".to_string(), err))?
+    }
+}
+
+#[async_trait]
+impl ChatStore for JournalChatStore {
+    async fn create_chat(&self, chat: &ChatMetadata) -> Result<bool, ChatServerErrors> {
+        if self.histories.contains_chat(chat.chat_id) {
+            return Ok(false);
+        }
+        let chat = chat.clone();
+        self.journaled(move |histories, writer| {
+            // Checked again holding the writer, so concurrent calls for the
+            // same chat journal it only once.
+            if histories.contains_chat(chat.chat_id) {
+                return Ok(false);
+            }
+            writer.record(&JournalRecord::ChatCreated {
+                chat_id: chat.chat_id,
+                creator: chat.creator,
+                name: chat.name.clone(),
+                topic: chat.topic.clone(),
+                created_at: chat.created_at.clone(),
+                retention: chat.retention,
+                rate_limit: chat.rate_limit,
+            })?;
+            Ok(histories.insert_chat(&chat))
+        })
+        .await
+    }
+
+    async fn read_chat(&self, chat_id: ChatId) -> Result<Option<ChatMetadata>, ChatServerErrors> {
+        self.histories.read_chat(chat_id).await
+    }
+
+    async fn read_chats(&self) -> Result<Vec<ChatMetadata>, ChatServerErrors> {
+        self.histories.read_chats().await
+    }
+
+    async fn update_retention(
+        &self,
+        chat_id: ChatId,
+        retention: &RetentionPolicy,
+    ) -> Result<bool, ChatServerErrors> {
+        let retention = *retention;
+        self.journaled(move |histories, writer| {
+            histories.set_retention_with(chat_id, &retention, || {
+                writer.record(&JournalRecord::RetentionUpdated { chat_id, retention })
+            })
+        })
+        .await
+    }
+
+    async fn update_rate_limit(
+        &self,
+        chat_id: ChatId,
+        rate_limit: &RateLimitPolicy,
+    ) -> Result<bool, ChatServerErrors> {
+        let rate_limit = *rate_limit;
+        self.journaled(move |histories, writer| {
+            histories.set_rate_limit_with(chat_id, &rate_limit, || {
+                writer.record(&JournalRecord::RateLimitUpdated {
+                    chat_id,
+                    rate_limit,
+                })
+            })
+        })
+        .await
+    }
+
+    // The pruned messages stay in the journal until it is compacted.
+    async fn prune_messages(
+        &self,
+        chat_id: ChatId,
+        limits: &RetentionLimits,
+    ) -> Result<PrunedMessages, ChatServerErrors> {
+        let limits = limits.clone();
+        self.journaled(move |histories, writer| {
+            histories.prune_messages_with(chat_id, &limits, |last_pruned| {
+                writer.record(&JournalRecord::MessagesPruned {
+                    chat_id,
+                    last_pruned,
+                })
+            })
+        })
+        .await
+    }
+
+    async fn delete_chat_if_unused(&self, chat_id: ChatId) -> Result<bool, ChatServerErrors> {
+        self.journaled(move |histories, writer| {
+            histories.remove_chat_if_unused_with(chat_id, || {
+                writer.record(&JournalRecord::ChatDeleted { chat_id })
+            })
+        })
+        .await
+    }
+
+    async fn append_message(&self, message: &ChatMessage) -> Result<u64, ChatServerErrors> {
+        let message = message.clone();
+        self.journaled(move |histories, writer| {
+            histories.push_message_with(&message, |message| {
+                writer.record(&JournalRecord::MessageAppended {
+                    message: message.clone(),
+                })
+            })
+        })
+        .await
+    }
+
+    async fn read_message_range(
+        &self,
+        chat_id: ChatId,
+        cursor: HistoryCursor,
+        limit: usize,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        self.histories
+            .read_message_range(chat_id, cursor, limit)
+            .await
+    }
+
+    async fn read_thread(
+        &self,
+        chat_id: ChatId,
+        thread_root: EventId,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        self.histories.read_thread(chat_id, thread_root).await
+    }
+
+    async fn read_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        self.histories.read_message(chat_id, event_id).await
+    }
+
+    async fn edit_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        message: &Message,
+        edited_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let (message, edited_at) = (message.clone(), edited_at.clone());
+        self.journaled(move |histories, writer| {
+            histories.edit_message_with(chat_id, event_id, &message, &edited_at, || {
+                writer.record(&JournalRecord::MessageEdited {
+                    chat_id,
+                    event_id,
+                    message: message.clone(),
+                    edited_at: edited_at.clone(),
+                })
+            })
+        })
+        .await
+    }
+
+    // Like pruned messages, the text of a deleted message stays in the
+    // journal until it is compacted.
+    async fn delete_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        deleted_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let deleted_at = deleted_at.clone();
+        self.journaled(move |histories, writer| {
+            histories.delete_message_with(chat_id, event_id, &deleted_at, || {
+                writer.record(&JournalRecord::MessageDeleted {
+                    chat_id,
+                    event_id,
+                    deleted_at: deleted_at.clone(),
+                })
+            })
+        })
+        .await
+    }
+
+    async fn read_message_revisions(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<Vec<MessageRevision>>, ChatServerErrors> {
+        self.histories
+            .read_message_revisions(chat_id, event_id)
+            .await
+    }
+
+    async fn add_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors> {
+        let emoji = emoji.clone();
+        self.journaled(move |histories, writer| {
+            histories.add_reaction_with(chat_id, event_id, user_id, &emoji, || {
+                writer.record(&JournalRecord::ReactionAdded {
+                    chat_id,
+                    event_id,
+                    user_id,
+                    emoji: emoji.clone(),
+                })
+            })
+        })
+        .await
+    }
+
+    async fn remove_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors> {
+        let emoji = emoji.clone();
+        self.journaled(move |histories, writer| {
+            histories.remove_reaction_with(chat_id, event_id, user_id, &emoji, || {
+                writer.record(&JournalRecord::ReactionRemoved {
+                    chat_id,
+                    event_id,
+                    user_id,
+                    emoji: emoji.clone(),
+                })
+            })
+        })
+        .await
+    }
+
+    async fn mark_read(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+    ) -> Result<Option<ReadReceipt>, ChatServerErrors> {
+        self.journaled(move |histories, writer| {
+            histories.mark_read_with(chat_id, user_id, event_id, || {
+                writer.record(&JournalRecord::ReadMarked {
+                    chat_id,
+                    user_id,
+                    event_id,
+                })
+            })
+        })
+        .await
+    }
+
+    async fn read_unread_messages(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<UnreadMessages>, ChatServerErrors> {
+        self.histories.read_unread_messages(user_id).await
+    }
+
+    async fn read_member_chats(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ChatSummary>, ChatServerErrors> {
+        self.histories.read_member_chats(user_id).await
+    }
+
+    async fn read_members(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Option<HashMap<UserId, Role>>, ChatServerErrors> {
+        self.histories.read_members(chat_id).await
+    }
+
+    async fn redeem_invite(
+        &self,
+        invite: &Invite,
+        user_id: UserId,
+    ) -> Result<InviteRedemption, ChatServerErrors> {
+        let invite = invite.clone();
+        self.journaled(move |histories, writer| {
+            histories.redeem_invite_with(&invite, user_id, || {
+                writer.record(&JournalRecord::InviteRedeemed {
+                    chat_id: invite.chat_id,
+                    invite_id: invite.invite_id,
+                    user_id,
+                    role: invite.role,
+                })
+            })
+        })
+        .await
+    }
+
+    async fn create_note(&self, note: &Note) -> Result<(), ChatServerErrors> {
+        let note = note.clone();
+        self.journaled(move |histories, writer| {
+            histories.insert_note_with(&note, || {
+                writer.record(&JournalRecord::NoteCreated { note: note.clone() })
+            })
+        })
+        .await
+    }
+
+    async fn read_notes(&self, chat_id: ChatId) -> Result<Option<Vec<Note>>, ChatServerErrors> {
+        self.histories.read_notes(chat_id).await
+    }
+
+    async fn read_note(
+        &self,
+        chat_id: ChatId,
+        note_id: NoteId,
+    ) -> Result<Option<Note>, ChatServerErrors> {
+        self.histories.read_note(chat_id, note_id).await
+    }
+
+    async fn update_note(&self, note: &Note) -> Result<bool, ChatServerErrors> {
+        let note = note.clone();
+        self.journaled(move |histories, writer| {
+            histories.replace_note_with(&note, || {
+                writer.record(&JournalRecord::NoteUpdated { note: note.clone() })
+            })
+        })
+        .await
+    }
+
+    async fn compact(&self) -> Result<(), ChatServerErrors> {
+        self.journaled(|histories, writer| writer.compact(histories))
+            .await
+    }
+}
+
+fn spawn_periodic_fsync(writer: Weak<Mutex<JournalWriter>>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            // The store is gone, so is our job.
+            let Some(writer) = writer.upgrade() else {
+                break;
+            };
+            let mut writer = writer.lock_owned().await;
+            match tokio::task::spawn_blocking(move || writer.sync()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => tracing::error!(?err, "periodic fsync of the journal failed"),
+                Err(err) => tracing::error!(?err, "periodic fsync of the journal panicked"),
+            }
+        }
+    });
+}
+
+struct JournalWriter {
+    directory: PathBuf,
+    segment_size: u64,
+    fsync: FsyncPolicy,
+    segment_number: u64,
+    file: File,
+    length: u64,
+    unsynced: bool,
+    // How many records removed texts since the last compaction.
+    removals: usize,
+}
+
+impl JournalWriter {
+    fn open(settings: &JournalSettings, segment_number: u64) -> Result<Self, ChatServerErrors> {
+        let path = segment_path(&settings.directory, segment_number);
+        let (file, length) = open_segment(&path).map_err(|err| {
+            ChatServerErrors::storage_failure(
+                format!("opening journal segment {}", path.display()),
+                err,
+            )
+        })?;
+        Ok(Self {
+            directory: settings.directory.clone(),
+            segment_size: settings.segment_size,
+            fsync: settings.fsync,
+            segment_number,
+            file,
+            length,
+            unsynced: false,
+            removals: 0,
+        })
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> io::Result<()> {
+        let frame = encode_record(record)?;
+        if self.length > 0 && self.length + frame.len() as u64 > self.segment_size {
+            self.roll_segment()?;
+        }
+
+        if let Err(err) = self.file.write_all(&frame) {
+            // Don't leave a partial record behind, later records would be
+            // lost on replay otherwise.
+            self.file.set_len(self.length)?;
+            return Err(err);
+        }
+        self.length += frame.len() as u64;
+        self.unsynced = true;
+        if record.removes_texts() {
+            self.removals += 1;
+        }
+
+        if self.fsync == FsyncPolicy::Always {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // Appends the record of a change of the histories.
+    fn record(&mut self, record: &JournalRecord) -> Result<(), ChatServerErrors> {
+        self.append(record)
+            .map_err(|err| ChatServerErrors::storage_failure("writing journal".to_string(), err))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if self.unsynced {
+            self.file.sync_data()?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    fn roll_segment(&mut self) -> io::Result<()> {
+        // Regardless of the policy, a finished segment is synced, so only the
+        // last segment can have a torn tail.
+        self.file.sync_data()?;
+        let segment_number = self.segment_number + 1;
+        let (file, length) = open_segment(&segment_path(&self.directory, segment_number))?;
+        sync_directory(&self.directory)?;
+        self.segment_number = segment_number;
+        self.file = file;
+        self.length = length;
+        self.unsynced = false;
+        Ok(())
+    }
+
+    /// Replaces the segments by a single one with a snapshot of the
+    /// histories, if texts were removed since the last compaction. The
+    /// histories must not change meanwhile, which holding the writer
+    /// ensures.
+    fn compact(&mut self, histories: &InMemoryChatStore) -> Result<(), ChatServerErrors> {
+        if self.removals == 0 {
+            return Ok(());
+        }
+        let compaction_path = self.directory.join(COMPACTION_FILE);
+        write_snapshot(&compaction_path, histories)?;
+
+        let failure = |err: io::Error| {
+            ChatServerErrors::storage_failure("compacting journal".to_string(), err)
+        };
+        // Renaming the complete snapshot into place is atomic, a crash leaves
+        // either the old segments or the snapshot after them.
+        let segment_number = self.segment_number + 1;
+        let path = segment_path(&self.directory, segment_number);
+        fs::rename(&compaction_path, &path).map_err(failure)?;
+        sync_directory(&self.directory).map_err(failure)?;
+        let (file, length) = open_segment(&path).map_err(failure)?;
+        self.segment_number = segment_number;
+        self.file = file;
+        self.length = length;
+        self.unsynced = false;
+
+        // The newest first, so a crash leaves the oldest ones, which replay
+        // consistently before the snapshot supersedes them.
+        for (number, path) in list_segments(&self.directory)?.into_iter().rev() {
+            if number < segment_number {
+                fs::remove_file(&path).map_err(failure)?;
+            }
+        }
+        sync_directory(&self.directory).map_err(failure)?;
+        tracing::info!(
+            removals = self.removals,
+            bytes = length,
+            "journal compacted"
+        );
+        self.removals = 0;
+        Ok(())
+    }
+}
+
+// Writes a journal consisting of the snapshots of the histories and syncs
+// it.
+fn write_snapshot(path: &Path, histories: &InMemoryChatStore) -> Result<(), ChatServerErrors> {
+    let failure = |err: io::Error| {
+        ChatServerErrors::storage_failure(
+            format!("writing journal snapshot {}", path.display()),
+            err,
+        )
+    };
+    let mut file = io::BufWriter::new(File::create(path).map_err(failure)?);
+    file.write_all(&encode_record(&JournalRecord::Compacted).map_err(failure)?)
+        .map_err(failure)?;
+    histories.snapshot_chats_with(|snapshot| {
+        let frame = encode_record(&JournalRecord::ChatRestored {
+            snapshot: Box::new(snapshot),
+        })
+        .map_err(failure)?;
+        file.write_all(&frame).map_err(failure)
+    })?;
+    file.into_inner()
+        .map_err(|err| failure(err.into_error()))?
+        .sync_all()
+        .map_err(failure)
+}
+
+fn encode_record(record: &JournalRecord) -> io::Result<Vec<u8>> {
+    let payload = serde_json::to_vec(record)?;
+    let length = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "journal record too large"))?;
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decodes the record at the start of `bytes`, yielding the record and the
+/// size of its frame, or `None` if the frame is incomplete or corrupted.
+fn decode_record(bytes: &[u8]) -> Option<(JournalRecord, usize)> {
+    let header = bytes.get(..HEADER_SIZE)?;
+    let length = u32::from_le_bytes(header[0..4].try_into().ok()?) as usize;
+    let checksum = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let payload = bytes.get(HEADER_SIZE..HEADER_SIZE.checked_add(length)?)?;
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+    let record = serde_json::from_slice(payload).ok()?;
+    Some((record, HEADER_SIZE + length))
+}
+
+/// Reads all records of a segment. A corrupted tail of the last segment is
+/// what a crash while writing leaves behind, it is cut off. Corruption
+/// anywhere else means data loss, which we refuse to paper over.
+fn read_segment(
+    path: &Path,
+    is_last_segment: bool,
+) -> Result<Vec<JournalRecord>, ChatServerErrors> {
+    let bytes = fs::read(path).map_err(|err| {
+        ChatServerErrors::storage_failure(
+            format!("reading journal segment {}", path.display()),
+            err,
+        )
+    })?;
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let Some((record, frame_size)) = decode_record(&bytes[offset..]) else {
+            break;
+        };
+        records.push(record);
+        offset += frame_size;
+    }
+
+    if offset < bytes.len() {
+        if !is_last_segment {
+            return Err(ChatServerErrors::storage_failure(
+                format!("replaying journal segment {}", path.display()),
+                format!("corrupted record at offset {offset}"),
+            ));
+        }
+        tracing::warn!(
+            segment = %path.display(),
+            offset,
+            discarded_bytes = bytes.len() - offset,
+            "corrupted journal tail, truncating"
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| {
+                file.set_len(offset as u64)?;
+                file.sync_all()
+            })
+            .map_err(|err| {
+                ChatServerErrors::storage_failure(
+                    format!("truncating journal segment {}", path.display()),
+                    err,
+                )
+            })?;
+    }
+    Ok(records)
+}
+
+fn segment_path(directory: &Path, segment_number: u64) -> PathBuf {
+    directory.join(format!(
+        "{SEGMENT_PREFIX}{segment_number:020}{SEGMENT_SUFFIX}"
+    ))
+}
+
+/// Lists the segments of the journal ordered by their number.
+fn list_segments(directory: &Path) -> Result<Vec<(u64, PathBuf)>, ChatServerErrors> {
+    let entries = fs::read_dir(directory).map_err(|err| {
+        ChatServerErrors::storage_failure(
+            format!("listing journal directory {}", directory.display()),
+            err,
+        )
+    })?;
+    let mut segments = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| {
+            ChatServerErrors::storage_failure(
+                format!("listing journal directory {}", directory.display()),
+                err,
+            )
+        })?;
+        let file_name = entry.file_name();
+        let segment_number = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+            .and_then(|number| number.parse::<u64>().ok());
+        if let Some(segment_number) = segment_number {
+            segments.push((segment_number, entry.path()));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+fn open_segment(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let length = file.metadata()?.len();
+    Ok((file, length))
+}
+
+// Makes the creation of a new segment file durable.
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek as _, SeekFrom};
+
+    use super::*;
+    use crate::{
+        chat::models::{DisplayName, EventId, Message},
+        notes::models::NoteBody,
+    };
+
+    fn test_message(chat_id: ChatId, text: &str) -> ChatMessage {
+        ChatMessage {
+            event_id: EventId::random(),
+            timestamp: ChatTimestamp::now(),
+            chat_id,
+            sequence: 0,
+            user_id: UserId::random(),
+            display_name: DisplayName::new("Hugo".to_string()),
+            message: Message::new(text.to_string()),
+            in_reply_to: None,
+            thread_root: None,
+            edited_at: None,
+            deleted_at: None,
+            reactions: Vec::new(),
+        }
+    }
+
+    fn unnamed_chat(chat_id: ChatId) -> ChatMetadata {
+        ChatMetadata::unnamed(chat_id, UserId::random(), ChatTimestamp::now())
+    }
+
+    async fn read_history(
+        store: &JournalChatStore,
+        chat_id: ChatId,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        store
+            .read_message_range(chat_id, HistoryCursor::Latest, usize::MAX)
+            .await
+    }
+
+    fn test_settings(directory: &Path, segment_size: u64) -> JournalSettings {
+        JournalSettings {
+            directory: directory.to_path_buf(),
+            segment_size,
+            fsync: FsyncPolicy::Always,
+            fsync_interval_ms: 1000,
+        }
+    }
+
+    #[tokio::test]
+    async fn the_journal_is_split_into_segments_and_replayed_completely() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 256);
+        let chat_id = ChatId::random();
+        let mut messages: Vec<_> = (1..=10)
+            .map(|i| test_message(chat_id, &format!("Nachricht {i}")))
+            .collect();
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        for message in &mut messages {
+            message.sequence = store.append_message(message).await?;
+        }
+        drop(store);
+
+        assert!(
+            list_segments(directory.path())?.len() > 1,
+            "small segments should have been rolled over"
+        );
+        let store = JournalChatStore::open(&settings).await?;
+        pretty_assertions::assert_eq!(read_history(&store, chat_id).await?, Some(messages));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redeemed_invites_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat = ChatMetadata::unnamed(ChatId::random(), UserId::random(), ChatTimestamp::now());
+        let invite = Invite {
+            invite_id: InviteId::random(),
+            chat_id: chat.chat_id,
+            role: Role::Member,
+            max_uses: 1,
+            expires_at: chrono::Utc::now() + chrono::Duration::hours(1),
+        };
+        let invitee = UserId::random();
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&chat).await?;
+        store.redeem_invite(&invite, invitee).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        let members = store.read_members(chat.chat_id).await?.unwrap_or_default();
+        assert_eq!(members.get(&invitee), Some(&Role::Member));
+        assert_eq!(
+            store.redeem_invite(&invite, UserId::random()).await?,
+            InviteRedemption::UsedUp,
+            "the use of the invite should have been replayed"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn created_and_updated_notes_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat = ChatMetadata::unnamed(ChatId::random(), UserId::random(), ChatTimestamp::now());
+        let mut note = Note {
+            note_id: NoteId::random(),
+            chat_id: chat.chat_id,
+            author: UserId::random(),
+            body: NoteBody::new("Milch".to_string()),
+            created_at: ChatTimestamp::now(),
+            updated_at: ChatTimestamp::now(),
+        };
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&chat).await?;
+        store.create_note(&note).await?;
+        note.body = NoteBody::new("Hafermilch".to_string());
+        store.update_note(&note).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        assert_eq!(store.read_notes(chat.chat_id).await?, Some(vec![note]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retention_pruning_and_evictions_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat_id = ChatId::random();
+        let evicted =
+            ChatMetadata::unnamed(ChatId::random(), UserId::random(), ChatTimestamp::now());
+        let retention = RetentionPolicy {
+            max_messages: Some(2),
+            ..Default::default()
+        };
+        let mut messages: Vec<_> = (1..=5)
+            .map(|i| test_message(chat_id, &format!("Nachricht {i}")))
+            .collect();
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        for message in &mut messages {
+            message.sequence = store.append_message(message).await?;
+        }
+        store.update_retention(chat_id, &retention).await?;
+        store
+            .prune_messages(chat_id, &retention.limits_at(&ChatTimestamp::now()))
+            .await?;
+        store.create_chat(&evicted).await?;
+        store.delete_chat_if_unused(evicted.chat_id).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        pretty_assertions::assert_eq!(
+            read_history(&store, chat_id).await?,
+            Some(messages[3..].to_vec())
+        );
+        assert_eq!(
+            store.read_chat(chat_id).await?.map(|chat| chat.retention),
+            Some(retention)
+        );
+        assert_eq!(store.read_chat(evicted.chat_id).await?, None);
+        assert_eq!(
+            store
+                .append_message(&test_message(chat_id, "weiter"))
+                .await?,
+            6,
+            "pruning should keep the sequence numbers"
+        );
+        Ok(())
+    }
+
+    fn journal_contains(directory: &Path, text: &str) -> anyhow::Result<bool> {
+        for entry in fs::read_dir(directory)? {
+            let bytes = fs::read(entry?.path())?;
+            if bytes
+                .windows(text.len())
+                .any(|window| window == text.as_bytes())
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    #[tokio::test]
+    async fn compaction_removes_pruned_and_deleted_texts() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 256);
+        let chat_id = ChatId::random();
+        let evicted =
+            ChatMetadata::unnamed(ChatId::random(), UserId::random(), ChatTimestamp::now());
+        let mut messages = vec![
+            test_message(chat_id, "Geheimnis"),
+            test_message(chat_id, "Vertraulich"),
+            test_message(chat_id, "Bleibt"),
+        ];
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        for message in &mut messages {
+            message.sequence = store.append_message(message).await?;
+        }
+        store
+            .edit_message(
+                chat_id,
+                messages[2].event_id,
+                &Message::new("Bleibt auch".to_string()),
+                &ChatTimestamp::now(),
+            )
+            .await?;
+        store
+            .prune_messages(
+                chat_id,
+                &RetentionLimits {
+                    older_than: None,
+                    max_messages: Some(2),
+                    max_bytes: None,
+                },
+            )
+            .await?;
+        store
+            .delete_message(chat_id, messages[1].event_id, &ChatTimestamp::now())
+            .await?;
+        store.create_chat(&evicted).await?;
+        store.delete_chat_if_unused(evicted.chat_id).await?;
+        assert!(journal_contains(directory.path(), "Geheimnis")?);
+
+        store.compact().await?;
+        assert_eq!(list_segments(directory.path())?.len(), 1);
+        for text in ["Geheimnis", "Vertraulich"] {
+            assert!(
+                !journal_contains(directory.path(), text)?,
+                "{text} should have been removed"
+            );
+        }
+        assert!(journal_contains(directory.path(), "Bleibt")?);
+        store
+            .append_message(&test_message(chat_id, "Danach"))
+            .await?;
+        let history = read_history(&store, chat_id).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        pretty_assertions::assert_eq!(read_history(&store, chat_id).await?, history);
+        assert_eq!(
+            store
+                .read_message_revisions(chat_id, messages[2].event_id)
+                .await?
+                .map(|revisions| revisions.len()),
+            Some(1)
+        );
+        assert_eq!(store.read_chat(evicted.chat_id).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limit_policies_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat = ChatMetadata::unnamed(ChatId::random(), UserId::random(), ChatTimestamp::now());
+        let rate_limit = RateLimitPolicy {
+            burst: None,
+            per_minute: Some(6),
+            slow_mode_secs: Some(60),
+        };
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&chat).await?;
+        store.update_rate_limit(chat.chat_id, &rate_limit).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        assert_eq!(
+            store
+                .read_chat(chat.chat_id)
+                .await?
+                .map(|chat| chat.rate_limit),
+            Some(rate_limit)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn edited_and_deleted_messages_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat_id = ChatId::random();
+        let edited = test_message(chat_id, "Tee");
+        let deleted = test_message(chat_id, "weg");
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        store.append_message(&edited).await?;
+        store.append_message(&deleted).await?;
+        let edited = store
+            .edit_message(
+                chat_id,
+                edited.event_id,
+                &Message::new("Kaffee".to_string()),
+                &ChatTimestamp::now(),
+            )
+            .await?
+            .expect("the message should have been edited");
+        let deleted = store
+            .delete_message(chat_id, deleted.event_id, &ChatTimestamp::now())
+            .await?
+            .expect("the message should have been deleted");
+        let revisions = store
+            .read_message_revisions(chat_id, edited.event_id)
+            .await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        pretty_assertions::assert_eq!(
+            read_history(&store, chat_id).await?,
+            Some(vec![edited.clone(), deleted])
+        );
+        assert_eq!(
+            store
+                .read_message_revisions(chat_id, edited.event_id)
+                .await?,
+            revisions
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reactions_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat_id = ChatId::random();
+        let message = test_message(chat_id, "Tee?");
+        let (hugo, erna) = (UserId::random(), UserId::random());
+        let thumbs_up = Emoji::new("👍".to_string());
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        store.append_message(&message).await?;
+        store
+            .add_reaction(chat_id, message.event_id, hugo, &thumbs_up)
+            .await?;
+        store
+            .add_reaction(chat_id, message.event_id, erna, &thumbs_up)
+            .await?;
+        store
+            .remove_reaction(chat_id, message.event_id, hugo, &thumbs_up)
+            .await?;
+        let expected = store.read_message(chat_id, message.event_id).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        let replayed = store.read_message(chat_id, message.event_id).await?;
+        assert_eq!(replayed, expected);
+        assert_eq!(
+            replayed.map(|message| message.reactions.len()),
+            Some(1),
+            "erna's reaction should be kept"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_markers_are_replayed() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat_id = ChatId::random();
+        let first = test_message(chat_id, "Tee?");
+        let reader = first.user_id;
+        let second = test_message(chat_id, "Gern");
+        let third = test_message(chat_id, "Kekse?");
+
+        let store = JournalChatStore::open(&settings).await?;
+        store
+            .create_chat(&ChatMetadata::unnamed(
+                chat_id,
+                reader,
+                ChatTimestamp::now(),
+            ))
+            .await?;
+        for message in [&first, &second, &third] {
+            store.append_message(message).await?;
+        }
+        store.mark_read(chat_id, reader, second.event_id).await?;
+        let expected = store.read_unread_messages(reader).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        assert_eq!(store.read_unread_messages(reader).await?, expected);
+        assert_eq!(
+            expected,
+            vec![UnreadMessages {
+                chat_id,
+                last_read: Some(second.event_id),
+                unread: 1,
+            }]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_corrupted_tail_is_discarded_and_the_journal_stays_writable() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat_id = ChatId::random();
+        let mut first = test_message(chat_id, "heil");
+        let mut second = test_message(chat_id, "kaputt");
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        first.sequence = store.append_message(&first).await?;
+        second.sequence = store.append_message(&second).await?;
+        drop(store);
+
+        // Flip a byte in the payload of the last record.
+        let (_, path) = list_segments(directory.path())?
+            .pop()
+            .expect("a segment should exist");
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::End(-3))?;
+        file.write_all(b"X")?;
+        drop(file);
+
+        let store = JournalChatStore::open(&settings).await?;
+        assert_eq!(
+            read_history(&store, chat_id).await?,
+            Some(vec![first.clone()])
+        );
+
+        let mut third = test_message(chat_id, "weiter");
+        third.sequence = store.append_message(&third).await?;
+        drop(store);
+
+        let store = JournalChatStore::open(&settings).await?;
+        assert_eq!(
+            read_history(&store, chat_id).await?,
+            Some(vec![first, third])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chats_created_before_chats_had_metadata_are_still_readable() -> anyhow::Result<()> {
+        let chat_id = ChatId::random();
+        let record: JournalRecord = serde_json::from_str(&format!(
+            r#"{{"type":"ChatCreated","chat_id":"{chat_id}"}}"#
+        ))?;
+        assert_eq!(
+            record,
+            JournalRecord::ChatCreated {
+                chat_id,
+                creator: None,
+                name: None,
+                topic: None,
+                created_at: ChatTimestamp::epoch(),
+                retention: RetentionPolicy::default(),
+                rate_limit: RateLimitPolicy::default(),
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_torn_record_at_the_end_is_discarded() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 1024 * 1024);
+        let chat_id = ChatId::random();
+        let mut message = test_message(chat_id, "heil");
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        message.sequence = store.append_message(&message).await?;
+        drop(store);
+
+        let (_, path) = list_segments(directory.path())?
+            .pop()
+            .expect("a segment should exist");
+        let frame = encode_record(&JournalRecord::MessageAppended {
+            message: test_message(chat_id, "halb"),
+        })?;
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        file.write_all(&frame[..frame.len() / 2])?;
+        drop(file);
+
+        let store = JournalChatStore::open(&settings).await?;
+        assert_eq!(read_history(&store, chat_id).await?, Some(vec![message]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corruption_in_an_earlier_segment_is_reported() -> anyhow::Result<()> {
+        let directory = tempfile::tempdir()?;
+        let settings = test_settings(directory.path(), 256);
+        let chat_id = ChatId::random();
+
+        let store = JournalChatStore::open(&settings).await?;
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+        for i in 1..=10 {
+            store
+                .append_message(&test_message(chat_id, &format!("Nachricht {i}")))
+                .await?;
+        }
+        drop(store);
+
+        let (_, path) = list_segments(directory.path())?
+            .into_iter()
+            .next()
+            .expect("a segment should exist");
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::Start(HEADER_SIZE as u64 + 1))?;
+        file.write_all(b"X")?;
+        drop(file);
+
+        assert!(
+            JournalChatStore::open(&settings).await.is_err(),
+            "corruption in the middle of the journal should not be ignored"
+        );
+        Ok(())
+    }
+}
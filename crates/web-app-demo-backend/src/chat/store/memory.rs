@@ -0,0 +1,839 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use dashmap::{DashMap, mapref::entry::Entry};
+use serde::{Deserialize, Serialize};
+
+use super::ChatStore;
+use crate::{
+    chat::{
+        ChatServerErrors,
+        models::{
+            ChatId, ChatMessage, ChatMetadata, ChatSummary, ChatTimestamp, Emoji, EventId,
+            HistoryCursor, Invite, InviteId, InviteRedemption, Message, MessagePreview,
+            MessageRevision, PrunedMessages, RateLimitPolicy, ReadReceipt, RetentionLimits,
+            RetentionPolicy, Role, UnreadMessages, UserId,
+        },
+    },
+    notes::models::{Note, NoteId},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ChatState {
+    metadata: ChatMetadata,
+    messages: Vec<ChatMessage>,
+    last_sequence: u64,
+    // The earlier texts of the edited messages.
+    revisions: HashMap<EventId, Vec<MessageRevision>>,
+    members: HashMap<UserId, Role>,
+    read_markers: HashMap<UserId, ReadReceipt>,
+    invite_uses: HashMap<InviteId, u32>,
+    notes: Vec<Note>,
+}
+
+impl ChatState {
+    // Messages are usually looked up near the end of a history, so they are
+    // searched from there.
+    fn position(&self, event_id: EventId) -> Option<usize> {
+        self.messages
+            .iter()
+            .rposition(|message| message.event_id == event_id)
+    }
+
+    fn new(metadata: ChatMetadata) -> Self {
+        let members = metadata
+            .creator
+            .map(|creator| HashMap::from([(creator, Role::Owner)]))
+            .unwrap_or_default();
+        Self {
+            metadata,
+            messages: Vec::new(),
+            last_sequence: 0,
+            revisions: HashMap::new(),
+            members,
+            read_markers: HashMap::new(),
+            invite_uses: HashMap::new(),
+            notes: Vec::new(),
+        }
+    }
+}
+
+/// Everything kept of a chat, for stores persisting chats as a whole.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ChatSnapshot(ChatState);
+
+/// Keeps every history in memory, so everything is lost on restart.
+#[derive(Default)]
+pub struct InMemoryChatStore {
+    // We intentionally use a std::sync::Mutex, as we never expect a
+    // lock to be held while awaiting a future.
+    chats: DashMap<ChatId, Arc<Mutex<ChatState>>>,
+}
+
+// Yields how many of the oldest messages violate a limit, see
+// `ChatStore::prune_messages`.
+fn messages_to_prune(messages: &[ChatMessage], limits: &RetentionLimits) -> usize {
+    let by_age = limits.older_than.as_ref().map_or(0, |older_than| {
+        messages
+            .iter()
+            .rposition(|message| message.timestamp < *older_than)
+            .map_or(0, |position| position + 1)
+    });
+    let by_count = limits.max_messages.map_or(0, |max_messages| {
+        messages
+            .len()
+            .saturating_sub(usize::try_from(max_messages).unwrap_or(usize::MAX))
+    });
+    let by_size = limits.max_bytes.map_or(0, |max_bytes| {
+        let mut bytes = 0;
+        messages
+            .iter()
+            .rposition(|message| {
+                bytes += message.message.as_str().len() as u64;
+                bytes > max_bytes
+            })
+            .map_or(0, |position| position + 1)
+    });
+    by_age.max(by_count).max(by_size)
+}
+
+fn lock(chat: &Mutex<ChatState>) -> Result<MutexGuard<'_, ChatState>, ChatServerErrors> {
+    chat.lock()
+        .map_err(|_| ChatServerErrors::lock_poisened("accessing chat".to_string()))
+}
+
+// The synchronous methods are meant for stores building upon this one.
+impl InMemoryChatStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn chat(&self, chat_id: ChatId) -> Option<Arc<Mutex<ChatState>>> {
+        self.chats.get(&chat_id).map(|r| r.value().clone())
+    }
+
+    pub fn contains_chat(&self, chat_id: ChatId) -> bool {
+        self.chats.contains_key(&chat_id)
+    }
+
+    /// Yields whether the chat was created. A chat without creator, e.g.
+    /// replayed from an old journal, has no members.
+    pub fn insert_chat(&self, chat: &ChatMetadata) -> bool {
+        match self.chats.entry(chat.chat_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(Mutex::new(ChatState::new(chat.clone()))));
+                true
+            }
+        }
+    }
+
+    /// Appends the message like `ChatStore::append_message`. `journal` is
+    /// called with the message carrying its sequence number, like in
+    /// `redeem_invite_with`.
+    pub fn push_message_with(
+        &self,
+        message: &ChatMessage,
+        journal: impl FnOnce(&ChatMessage) -> Result<(), ChatServerErrors>,
+    ) -> Result<u64, ChatServerErrors> {
+        let chat = self
+            .chat(message.chat_id)
+            .ok_or_else(|| ChatServerErrors::chat_not_found(message.chat_id))?;
+        let mut chat = lock(&chat)?;
+        let message = ChatMessage {
+            sequence: chat.last_sequence + 1,
+            ..message.clone()
+        };
+        journal(&message)?;
+        chat.last_sequence = message.sequence;
+        chat.messages.push(message);
+        Ok(chat.last_sequence)
+    }
+
+    /// Redeems the invite like `ChatStore::redeem_invite`. `journal` is
+    /// called while the chat is locked, before a use is recorded, so a failing
+    /// journal leaves the chat untouched.
+    pub fn redeem_invite_with(
+        &self,
+        invite: &Invite,
+        user_id: UserId,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<InviteRedemption, ChatServerErrors> {
+        let chat = self
+            .chat(invite.chat_id)
+            .ok_or_else(|| ChatServerErrors::chat_not_found(invite.chat_id))?;
+        let mut chat = lock(&chat)?;
+        if let Some(role) = chat.members.get(&user_id) {
+            return Ok(InviteRedemption::AlreadyMember(*role));
+        }
+        let uses = chat
+            .invite_uses
+            .get(&invite.invite_id)
+            .copied()
+            .unwrap_or(0);
+        if uses >= invite.max_uses {
+            return Ok(InviteRedemption::UsedUp);
+        }
+        journal()?;
+        chat.invite_uses.insert(invite.invite_id, uses + 1);
+        chat.members.insert(user_id, invite.role);
+        Ok(InviteRedemption::Joined(invite.role))
+    }
+
+    /// Records a use of an invite, e.g. when replaying a journal.
+    pub fn restore_invite_use(
+        &self,
+        chat_id: ChatId,
+        invite_id: InviteId,
+        user_id: UserId,
+        role: Role,
+    ) -> Result<(), ChatServerErrors> {
+        let chat = self
+            .chat(chat_id)
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))?;
+        let mut chat = lock(&chat)?;
+        *chat.invite_uses.entry(invite_id).or_default() += 1;
+        chat.members.insert(user_id, role);
+        Ok(())
+    }
+
+    /// Adds the note like `ChatStore::create_note`, `journal` is called like
+    /// in `redeem_invite_with`.
+    pub fn insert_note_with(
+        &self,
+        note: &Note,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<(), ChatServerErrors> {
+        let chat = self
+            .chat(note.chat_id)
+            .ok_or_else(|| ChatServerErrors::chat_not_found(note.chat_id))?;
+        let mut chat = lock(&chat)?;
+        journal()?;
+        chat.notes.push(note.clone());
+        Ok(())
+    }
+
+    /// Replaces the retention policy like `ChatStore::update_retention`,
+    /// `journal` is only called for an existing chat.
+    pub fn set_retention_with(
+        &self,
+        chat_id: ChatId,
+        retention: &RetentionPolicy,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<bool, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(false);
+        };
+        let mut chat = lock(&chat)?;
+        journal()?;
+        chat.metadata.retention = *retention;
+        Ok(true)
+    }
+
+    /// Replaces the rate limit policy like `ChatStore::update_rate_limit`,
+    /// `journal` is only called for an existing chat.
+    pub fn set_rate_limit_with(
+        &self,
+        chat_id: ChatId,
+        rate_limit: &RateLimitPolicy,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<bool, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(false);
+        };
+        let mut chat = lock(&chat)?;
+        journal()?;
+        chat.metadata.rate_limit = *rate_limit;
+        Ok(true)
+    }
+
+    /// Prunes the history like `ChatStore::prune_messages`. `journal` is
+    /// called with the sequence number of the last pruned message, if any
+    /// are pruned.
+    pub fn prune_messages_with(
+        &self,
+        chat_id: ChatId,
+        limits: &RetentionLimits,
+        journal: impl FnOnce(u64) -> Result<(), ChatServerErrors>,
+    ) -> Result<PrunedMessages, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(PrunedMessages::default());
+        };
+        let mut chat = lock(&chat)?;
+        let count = messages_to_prune(&chat.messages, limits);
+        let Some(last_pruned) = count.checked_sub(1).map(|last| &chat.messages[last]) else {
+            return Ok(PrunedMessages::default());
+        };
+        journal(last_pruned.sequence)?;
+        let chat = &mut *chat;
+        Ok(chat
+            .messages
+            .drain(..count)
+            .fold(PrunedMessages::default(), |pruned, message| {
+                chat.revisions.remove(&message.event_id);
+                PrunedMessages {
+                    messages: pruned.messages + 1,
+                    bytes: pruned.bytes + message.message.as_str().len() as u64,
+                }
+            }))
+    }
+
+    /// Prunes the messages up to the one with the sequence number
+    /// `last_pruned`, e.g. when replaying a journal.
+    pub fn restore_pruning(
+        &self,
+        chat_id: ChatId,
+        last_pruned: u64,
+    ) -> Result<(), ChatServerErrors> {
+        let chat = self
+            .chat(chat_id)
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))?;
+        let mut chat = lock(&chat)?;
+        let chat = &mut *chat;
+        chat.messages.retain(|message| {
+            let kept = message.sequence > last_pruned;
+            if !kept {
+                chat.revisions.remove(&message.event_id);
+            }
+            kept
+        });
+        Ok(())
+    }
+
+    /// Edits the message like `ChatStore::edit_message`, `journal` is only
+    /// called for a message being edited.
+    pub fn edit_message_with(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        message: &Message,
+        edited_at: &ChatTimestamp,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let mut chat = lock(&chat)?;
+        let Some(position) = chat.position(event_id) else {
+            return Ok(None);
+        };
+        if chat.messages[position].is_deleted() {
+            return Ok(None);
+        }
+        journal()?;
+        let chat = &mut *chat;
+        let edited = &mut chat.messages[position];
+        let replaced = std::mem::replace(&mut edited.message, message.clone());
+        let replaced_at = edited.edited_at.replace(edited_at.clone());
+        chat.revisions
+            .entry(event_id)
+            .or_default()
+            .push(MessageRevision {
+                message: replaced,
+                timestamp: replaced_at.unwrap_or_else(|| edited.timestamp.clone()),
+            });
+        Ok(Some(edited.clone()))
+    }
+
+    /// Deletes the message like `ChatStore::delete_message`, `journal` is
+    /// only called for a message being deleted.
+    pub fn delete_message_with(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        deleted_at: &ChatTimestamp,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let mut chat = lock(&chat)?;
+        let Some(position) = chat.position(event_id) else {
+            return Ok(None);
+        };
+        if chat.messages[position].is_deleted() {
+            return Ok(None);
+        }
+        journal()?;
+        chat.revisions.remove(&event_id);
+        let deleted = &mut chat.messages[position];
+        deleted.message = Message::new(String::new());
+        deleted.deleted_at = Some(deleted_at.clone());
+        deleted.reactions.clear();
+        Ok(Some(deleted.clone()))
+    }
+
+    /// Adds the reaction like `ChatStore::add_reaction`, `journal` is only
+    /// called for a reaction being added.
+    pub fn add_reaction_with(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<bool, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(false);
+        };
+        let mut chat = lock(&chat)?;
+        let Some(position) = chat.position(event_id) else {
+            return Ok(false);
+        };
+        let message = &mut chat.messages[position];
+        if message.is_deleted() || message.has_reacted(emoji, user_id) {
+            return Ok(false);
+        }
+        journal()?;
+        Ok(message.add_reaction(emoji, user_id))
+    }
+
+    /// Removes the reaction like `ChatStore::remove_reaction`, `journal` is
+    /// only called for a reaction being removed.
+    pub fn remove_reaction_with(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<bool, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(false);
+        };
+        let mut chat = lock(&chat)?;
+        let Some(position) = chat.position(event_id) else {
+            return Ok(false);
+        };
+        let message = &mut chat.messages[position];
+        if !message.has_reacted(emoji, user_id) {
+            return Ok(false);
+        }
+        journal()?;
+        Ok(message.remove_reaction(emoji, user_id))
+    }
+
+    /// Moves the read marker like `ChatStore::mark_read`, `journal` is only
+    /// called for a marker being moved.
+    pub fn mark_read_with(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<Option<ReadReceipt>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let mut chat = lock(&chat)?;
+        let Some(position) = chat.position(event_id) else {
+            return Ok(None);
+        };
+        let sequence = chat.messages[position].sequence;
+        if chat
+            .read_markers
+            .get(&user_id)
+            .is_some_and(|marker| marker.sequence >= sequence)
+        {
+            return Ok(None);
+        }
+        journal()?;
+        let receipt = ReadReceipt {
+            user_id,
+            event_id,
+            sequence,
+        };
+        chat.read_markers.insert(user_id, receipt.clone());
+        Ok(Some(receipt))
+    }
+
+    /// Deletes the chat like `ChatStore::delete_chat_if_unused`, `journal`
+    /// is only called for a chat being deleted.
+    pub fn remove_chat_if_unused_with(
+        &self,
+        chat_id: ChatId,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<bool, ChatServerErrors> {
+        let Entry::Occupied(entry) = self.chats.entry(chat_id) else {
+            return Ok(false);
+        };
+        // Others get hold of the chat only through the map, which is locked
+        // by the entry. If somebody already holds the chat, they are about
+        // to use it, so it isn't deleted below them.
+        if Arc::strong_count(entry.get()) > 1 {
+            return Ok(false);
+        }
+        {
+            let chat = lock(entry.get())?;
+            let creator = chat.metadata.creator;
+            if chat.last_sequence > 0
+                || !chat.notes.is_empty()
+                || chat.members.keys().any(|&member| Some(member) != creator)
+            {
+                return Ok(false);
+            }
+        }
+        journal()?;
+        entry.remove();
+        Ok(true)
+    }
+
+    /// Deletes the chat regardless of what it has, for restoring deletions.
+    pub fn remove_chat(&self, chat_id: ChatId) {
+        self.chats.remove(&chat_id);
+    }
+
+    /// Calls `journal` with a snapshot of every chat, one chat at a time.
+    pub fn snapshot_chats_with(
+        &self,
+        mut journal: impl FnMut(ChatSnapshot) -> Result<(), ChatServerErrors>,
+    ) -> Result<(), ChatServerErrors> {
+        let chats: Vec<_> = self.chats.iter().map(|chat| chat.value().clone()).collect();
+        for chat in chats {
+            let snapshot = ChatSnapshot(lock(&chat)?.clone());
+            journal(snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the chat of the snapshot by it, e.g. when replaying a
+    /// journal.
+    pub fn restore_chat(&self, ChatSnapshot(chat): ChatSnapshot) {
+        self.chats
+            .insert(chat.metadata.chat_id, Arc::new(Mutex::new(chat)));
+    }
+
+    /// Deletes every chat, e.g. when replaying a journal superseded by
+    /// snapshots.
+    pub fn clear(&self) {
+        self.chats.clear();
+    }
+
+    /// Updates the note like `ChatStore::update_note`, `journal` is only
+    /// called for an existing note.
+    pub fn replace_note_with(
+        &self,
+        note: &Note,
+        journal: impl FnOnce() -> Result<(), ChatServerErrors>,
+    ) -> Result<bool, ChatServerErrors> {
+        let Some(chat) = self.chat(note.chat_id) else {
+            return Ok(false);
+        };
+        let mut chat = lock(&chat)?;
+        let Some(existing) = chat
+            .notes
+            .iter_mut()
+            .find(|existing| existing.note_id == note.note_id)
+        else {
+            return Ok(false);
+        };
+        journal()?;
+        existing.body = note.body.clone();
+        existing.updated_at = note.updated_at.clone();
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl ChatStore for InMemoryChatStore {
+    async fn create_chat(&self, chat: &ChatMetadata) -> Result<bool, ChatServerErrors> {
+        Ok(self.insert_chat(chat))
+    }
+
+    async fn read_chat(&self, chat_id: ChatId) -> Result<Option<ChatMetadata>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let metadata = lock(&chat)?.metadata.clone();
+        Ok(Some(metadata))
+    }
+
+    async fn read_chats(&self) -> Result<Vec<ChatMetadata>, ChatServerErrors> {
+        // Collected first, so no chat is locked while the map is.
+        let chats: Vec<_> = self.chats.iter().map(|r| r.value().clone()).collect();
+        chats
+            .iter()
+            .map(|chat| Ok(lock(chat)?.metadata.clone()))
+            .collect()
+    }
+
+    async fn update_retention(
+        &self,
+        chat_id: ChatId,
+        retention: &RetentionPolicy,
+    ) -> Result<bool, ChatServerErrors> {
+        self.set_retention_with(chat_id, retention, || Ok(()))
+    }
+
+    async fn update_rate_limit(
+        &self,
+        chat_id: ChatId,
+        rate_limit: &RateLimitPolicy,
+    ) -> Result<bool, ChatServerErrors> {
+        self.set_rate_limit_with(chat_id, rate_limit, || Ok(()))
+    }
+
+    async fn prune_messages(
+        &self,
+        chat_id: ChatId,
+        limits: &RetentionLimits,
+    ) -> Result<PrunedMessages, ChatServerErrors> {
+        self.prune_messages_with(chat_id, limits, |_| Ok(()))
+    }
+
+    async fn delete_chat_if_unused(&self, chat_id: ChatId) -> Result<bool, ChatServerErrors> {
+        self.remove_chat_if_unused_with(chat_id, || Ok(()))
+    }
+
+    async fn append_message(&self, message: &ChatMessage) -> Result<u64, ChatServerErrors> {
+        self.push_message_with(message, |_| Ok(()))
+    }
+
+    async fn read_message_range(
+        &self,
+        chat_id: ChatId,
+        cursor: HistoryCursor,
+        limit: usize,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let chat = lock(&chat)?;
+        let messages = &chat.messages;
+        let position = |event_id| {
+            chat.position(event_id)
+                .ok_or_else(|| ChatServerErrors::event_not_found(chat_id, event_id))
+        };
+        let range = match cursor {
+            HistoryCursor::Latest => messages.len().saturating_sub(limit)..messages.len(),
+            HistoryCursor::Before(event_id) => {
+                let end = position(event_id)?;
+                end.saturating_sub(limit)..end
+            }
+            HistoryCursor::After(event_id) => {
+                let start = position(event_id)? + 1;
+                start..start.saturating_add(limit).min(messages.len())
+            }
+            HistoryCursor::Since(sequence) => {
+                let start = messages.partition_point(|message| message.sequence <= sequence);
+                start..start.saturating_add(limit).min(messages.len())
+            }
+        };
+        Ok(Some(messages[range].to_vec()))
+    }
+
+    async fn read_thread(
+        &self,
+        chat_id: ChatId,
+        thread_root: EventId,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let chat = lock(&chat)?;
+        Ok(Some(
+            chat.messages
+                .iter()
+                .filter(|message| {
+                    message.event_id == thread_root || message.thread_root == Some(thread_root)
+                })
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    async fn read_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let chat = lock(&chat)?;
+        Ok(chat
+            .position(event_id)
+            .map(|position| chat.messages[position].clone()))
+    }
+
+    async fn edit_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        message: &Message,
+        edited_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        self.edit_message_with(chat_id, event_id, message, edited_at, || Ok(()))
+    }
+
+    async fn delete_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        deleted_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        self.delete_message_with(chat_id, event_id, deleted_at, || Ok(()))
+    }
+
+    async fn read_message_revisions(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<Vec<MessageRevision>>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let chat = lock(&chat)?;
+        if chat.position(event_id).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(
+            chat.revisions.get(&event_id).cloned().unwrap_or_default(),
+        ))
+    }
+
+    async fn add_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors> {
+        self.add_reaction_with(chat_id, event_id, user_id, emoji, || Ok(()))
+    }
+
+    async fn remove_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors> {
+        self.remove_reaction_with(chat_id, event_id, user_id, emoji, || Ok(()))
+    }
+
+    async fn mark_read(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+    ) -> Result<Option<ReadReceipt>, ChatServerErrors> {
+        self.mark_read_with(chat_id, user_id, event_id, || Ok(()))
+    }
+
+    async fn read_unread_messages(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<UnreadMessages>, ChatServerErrors> {
+        // Collected first, so no chat is locked while the map is.
+        let chats: Vec<_> = self.chats.iter().map(|r| r.value().clone()).collect();
+        let mut unread = Vec::new();
+        for chat in &chats {
+            let chat = lock(chat)?;
+            if !chat.members.contains_key(&user_id) {
+                continue;
+            }
+            let marker = chat.read_markers.get(&user_id);
+            let last_read = marker.map_or(0, |marker| marker.sequence);
+            unread.push(UnreadMessages {
+                chat_id: chat.metadata.chat_id,
+                last_read: marker.map(|marker| marker.event_id),
+                unread: chat
+                    .messages
+                    .iter()
+                    .filter(|message| {
+                        message.sequence > last_read
+                            && message.user_id != user_id
+                            && !message.is_deleted()
+                    })
+                    .count() as u64,
+            });
+        }
+        unread.sort_by_key(|unread| unread.chat_id);
+        Ok(unread)
+    }
+
+    async fn read_member_chats(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ChatSummary>, ChatServerErrors> {
+        // Collected first, so no chat is locked while the map is.
+        let chats: Vec<_> = self.chats.iter().map(|r| r.value().clone()).collect();
+        let mut summaries = Vec::new();
+        for chat in &chats {
+            let chat = lock(chat)?;
+            if !chat.members.contains_key(&user_id) {
+                continue;
+            }
+            summaries.push(ChatSummary {
+                metadata: chat.metadata.clone(),
+                last_activity: chat.messages.last().map_or_else(
+                    || chat.metadata.created_at.clone(),
+                    |message| message.timestamp.clone(),
+                ),
+                last_message: chat
+                    .messages
+                    .iter()
+                    .rev()
+                    .find(|message| !message.is_deleted())
+                    .map(MessagePreview::of),
+            });
+        }
+        Ok(summaries)
+    }
+
+    async fn read_members(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Option<HashMap<UserId, Role>>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let members = lock(&chat)?.members.clone();
+        Ok(Some(members))
+    }
+
+    async fn redeem_invite(
+        &self,
+        invite: &Invite,
+        user_id: UserId,
+    ) -> Result<InviteRedemption, ChatServerErrors> {
+        self.redeem_invite_with(invite, user_id, || Ok(()))
+    }
+
+    async fn create_note(&self, note: &Note) -> Result<(), ChatServerErrors> {
+        self.insert_note_with(note, || Ok(()))
+    }
+
+    async fn read_notes(&self, chat_id: ChatId) -> Result<Option<Vec<Note>>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let notes = lock(&chat)?.notes.clone();
+        Ok(Some(notes))
+    }
+
+    async fn read_note(
+        &self,
+        chat_id: ChatId,
+        note_id: NoteId,
+    ) -> Result<Option<Note>, ChatServerErrors> {
+        let Some(chat) = self.chat(chat_id) else {
+            return Ok(None);
+        };
+        let note = lock(&chat)?
+            .notes
+            .iter()
+            .find(|note| note.note_id == note_id)
+            .cloned();
+        Ok(note)
+    }
+
+    async fn update_note(&self, note: &Note) -> Result<bool, ChatServerErrors> {
+        self.replace_note_with(note, || Ok(()))
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use super::{
+    ChatServerErrors,
+    models::{
+        ChatId, ChatMessage, ChatMetadata, ChatSummary, ChatTimestamp, Emoji, EventId,
+        HistoryCursor, Invite, InviteRedemption, Message, MessageRevision, PrunedMessages,
+        RateLimitPolicy, ReadReceipt, RetentionLimits, RetentionPolicy, Role, UnreadMessages,
+        UserId,
+    },
+};
+use crate::{
+    notes::models::{Note, NoteId},
+    settings::StorageSettings,
+};
+
+pub mod journal;
+pub mod memory;
+pub mod postgres;
+pub mod sql;
+pub mod sqlite;
+
+/// Storage of the chat histories used by `ChatServer`.
+///
+/// Implementations have to be safe to use concurrently, `ChatServer` doesn't
+/// serialize calls for the same chat.
+#[async_trait]
+pub trait ChatStore: Send + Sync {
+    /// Creates the chat with an empty history and its creator as owner, if it
+    /// doesn't exist yet. Yields whether the chat was created.
+    async fn create_chat(&self, chat: &ChatMetadata) -> Result<bool, ChatServerErrors>;
+
+    /// Reads the metadata of a chat. Yields `None` for an unknown chat.
+    async fn read_chat(&self, chat_id: ChatId) -> Result<Option<ChatMetadata>, ChatServerErrors>;
+
+    /// Reads the metadata of every chat.
+    async fn read_chats(&self) -> Result<Vec<ChatMetadata>, ChatServerErrors>;
+
+    /// Replaces the retention policy of a chat. Yields whether the chat
+    /// exists.
+    async fn update_retention(
+        &self,
+        chat_id: ChatId,
+        retention: &RetentionPolicy,
+    ) -> Result<bool, ChatServerErrors>;
+
+    /// Replaces the rate limit policy of a chat. Yields whether the chat
+    /// exists.
+    async fn update_rate_limit(
+        &self,
+        chat_id: ChatId,
+        rate_limit: &RateLimitPolicy,
+    ) -> Result<bool, ChatServerErrors>;
+
+    /// Removes the oldest messages of a chat up to the newest one violating a
+    /// limit, i.e. the newest message older than `older_than`, the one
+    /// `max_messages` messages before the latest and the newest one at which
+    /// the message texts from the latest on exceed `max_bytes` bytes. The
+    /// sequence numbers of the kept messages don't change. Nothing is pruned
+    /// in an unknown chat.
+    async fn prune_messages(
+        &self,
+        chat_id: ChatId,
+        limits: &RetentionLimits,
+    ) -> Result<PrunedMessages, ChatServerErrors>;
+
+    /// Deletes a chat nobody used, i.e. which never had a message and has no
+    /// notes and no members but its creator. Yields whether the chat was
+    /// deleted.
+    async fn delete_chat_if_unused(&self, chat_id: ChatId) -> Result<bool, ChatServerErrors>;
+
+    /// Appends the message to the history of its chat. Yields the sequence
+    /// number assigned to the message, the one it carries is ignored. Fails
+    /// with `ChatNotFound` for an unknown chat, appending never creates one.
+    async fn append_message(&self, message: &ChatMessage) -> Result<u64, ChatServerErrors>;
+
+    /// Reads up to `limit` messages of a chat next to the cursor, in the order
+    /// they were appended. Yields `None` for an unknown chat and fails with
+    /// `EventNotFound` if the message of the cursor isn't in the history. A
+    /// sequence number beyond the history is no error, nothing was appended
+    /// since.
+    async fn read_message_range(
+        &self,
+        chat_id: ChatId,
+        cursor: HistoryCursor,
+        limit: usize,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors>;
+
+    /// Reads the thread started by a message, i.e. the message, if it is still
+    /// in the history, followed by the replies with it as `thread_root`, in
+    /// the order they were appended. Yields `None` for an unknown chat.
+    async fn read_thread(
+        &self,
+        chat_id: ChatId,
+        thread_root: EventId,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors>;
+
+    /// Reads a single message, including tombstones. Yields `None` for an
+    /// unknown message or chat.
+    async fn read_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors>;
+
+    /// Replaces the text of a message, keeping the replaced one as revision.
+    /// Yields the edited message, or `None` if the message is unknown or
+    /// deleted.
+    async fn edit_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        message: &Message,
+        edited_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors>;
+
+    /// Replaces a message by a tombstone, dropping its text, revisions and
+    /// reactions.
+    /// Yields the tombstone, or `None` if the message is unknown or deleted
+    /// already.
+    async fn delete_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        deleted_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors>;
+
+    /// Reads the texts a message had before its edits, oldest first. Yields
+    /// `None` for an unknown message or chat.
+    async fn read_message_revisions(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<Vec<MessageRevision>>, ChatServerErrors>;
+
+    /// Adds the reaction of the user with the emoji to a message. Yields
+    /// whether it was added, i.e. the message exists, isn't deleted and the
+    /// user didn't react with the emoji yet.
+    async fn add_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors>;
+
+    /// Removes the reaction of the user with the emoji from a message.
+    /// Yields whether the user had reacted with the emoji.
+    async fn remove_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors>;
+
+    /// Moves the read marker of the user to a message, unless it is there or
+    /// beyond already. Yields the new receipt if the marker moved, `None` if
+    /// it didn't or the message is unknown.
+    async fn mark_read(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+    ) -> Result<Option<ReadReceipt>, ChatServerErrors>;
+
+    /// Counts the unread messages in every chat the user is a member of,
+    /// ordered by chat id.
+    async fn read_unread_messages(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<UnreadMessages>, ChatServerErrors>;
+
+    /// Summarizes every chat the user is a member of, in no particular
+    /// order.
+    async fn read_member_chats(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ChatSummary>, ChatServerErrors>;
+
+    /// Reads the members of a chat with their roles. Yields `None` for an
+    /// unknown chat.
+    async fn read_members(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Option<HashMap<UserId, Role>>, ChatServerErrors>;
+
+    /// Adds the user with the role of the invite to the members of its chat,
+    /// unless the user is a member already. Only uses adding a member count
+    /// against the maximum uses of the invite. The expiry of the invite is
+    /// not checked here.
+    async fn redeem_invite(
+        &self,
+        invite: &Invite,
+        user_id: UserId,
+    ) -> Result<InviteRedemption, ChatServerErrors>;
+
+    /// Adds the note to its chat, failing with `ChatNotFound` for an unknown
+    /// chat.
+    async fn create_note(&self, note: &Note) -> Result<(), ChatServerErrors>;
+
+    /// Reads the notes of a chat in the order they were created. Yields `None`
+    /// for an unknown chat.
+    async fn read_notes(&self, chat_id: ChatId) -> Result<Option<Vec<Note>>, ChatServerErrors>;
+
+    /// Reads a single note. Yields `None` for an unknown note or chat.
+    async fn read_note(
+        &self,
+        chat_id: ChatId,
+        note_id: NoteId,
+    ) -> Result<Option<Note>, ChatServerErrors>;
+
+    /// Replaces the body and update time of a note. Yields whether the note
+    /// exists.
+    async fn update_note(&self, note: &Note) -> Result<bool, ChatServerErrors>;
+
+    /// Removes what is left on disk of pruned and deleted messages and of
+    /// deleted chats. Stores removing them right away have nothing to do.
+    async fn compact(&self) -> Result<(), ChatServerErrors> {
+        Ok(())
+    }
+}
+
+/// Opens the store selected by the settings.
+pub async fn open(settings: &StorageSettings) -> Result<Box<dyn ChatStore>, ChatServerErrors> {
+    Ok(match settings {
+        StorageSettings::Memory => Box::new(memory::InMemoryChatStore::new()),
+        StorageSettings::Postgres(postgres_settings) => {
+            Box::new(postgres::PostgresChatStore::connect(postgres_settings).await?)
+        }
+        StorageSettings::Sqlite(sqlite_settings) => {
+            Box::new(sqlite::SqliteChatStore::open(sqlite_settings).await?)
+        }
+        StorageSettings::Journal(journal_settings) => {
+            Box::new(journal::JournalChatStore::open(journal_settings).await?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,51 @@
+use sqlx::{
+    Postgres,
+    postgres::{PgPoolOptions, PgQueryResult},
+};
+
+use super::sql::{RowsAffected, SqlChatStore, SqlDialect};
+use crate::{chat::ChatServerErrors, settings::PostgresSettings};
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Keeps the histories in a postgres database. The schema is migrated on
+/// connect.
+pub type PostgresChatStore = SqlChatStore<Postgres>;
+
+impl PostgresChatStore {
+    pub async fn connect(settings: &PostgresSettings) -> Result<Self, ChatServerErrors> {
+        let pool = PgPoolOptions::new()
+            .max_connections(settings.max_connections)
+            .connect(settings.url.expose())
+            .await
+            .map_err(|err| {
+                ChatServerErrors::storage_failure("connecting to postgres".to_string(), err)
+            })?;
+        MIGRATOR.run(&pool).await.map_err(|err| {
+            ChatServerErrors::storage_failure("migrating the postgres schema".to_string(), err)
+        })?;
+        Ok(Self::new(pool))
+    }
+}
+
+impl SqlDialect for Postgres {
+    // Locking the message, so concurrent edits keep every replaced text.
+    const KEEP_REVISION: &'static str =
+        "INSERT INTO message_revisions (chat_id, event_id, message, timestamp)
+         SELECT chat_id, event_id, message, COALESCE(edited_at, timestamp) FROM chat_messages
+         WHERE chat_id = $1 AND event_id = $2 AND deleted_at IS NULL FOR UPDATE";
+
+    // Sharing the lock on the message, so it isn't deleted before the
+    // reaction is added.
+    const ADD_REACTION: &'static str =
+        "INSERT INTO message_reactions (chat_id, event_id, user_id, emoji)
+         SELECT chat_id, event_id, $3, $4 FROM chat_messages
+         WHERE chat_id = $1 AND event_id = $2 AND deleted_at IS NULL FOR SHARE
+         ON CONFLICT DO NOTHING";
+}
+
+impl RowsAffected for PgQueryResult {
+    fn rows_affected(&self) -> u64 {
+        PgQueryResult::rows_affected(self)
+    }
+}
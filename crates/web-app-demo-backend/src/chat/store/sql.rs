@@ -0,0 +1,1089 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    ColumnIndex, Database, Decode, Encode, Executor, IntoArguments, Pool, Row, Type,
+    prelude::FromRow,
+};
+use uuid::Uuid;
+
+use super::ChatStore;
+use crate::{
+    chat::{
+        ChatServerErrors,
+        models::{
+            ChatId, ChatMessage, ChatMetadata, ChatName, ChatSummary, ChatTimestamp, ChatTopic,
+            DisplayName, Emoji, EventId, HistoryCursor, Invite, InviteRedemption, Message,
+            MessagePreview, MessageRevision, PrunedMessages, RateLimitPolicy, ReadReceipt,
+            RetentionLimits, RetentionPolicy, Role, UnreadMessages, UserId,
+        },
+    },
+    notes::models::{Note, NoteBody, NoteId},
+};
+
+/// The statements which differ between the databases a `SqlChatStore` can
+/// keep the histories in. All other statements are shared, with their
+/// parameters numbered like `$1`.
+pub trait SqlDialect: Database<QueryResult: RowsAffected> {
+    /// Copies the current text of a message which isn't deleted into its
+    /// revisions, binding the chat and the event. Has to keep concurrent
+    /// edits of the message from losing a replaced text.
+    const KEEP_REVISION: &'static str;
+
+    /// Reacts to a message which isn't deleted, binding the chat, the event,
+    /// the user and the emoji. Has to keep the message from being deleted
+    /// before the reaction is added.
+    const ADD_REACTION: &'static str;
+}
+
+/// The outcome of a statement, which the databases report with types of
+/// their own.
+pub trait RowsAffected {
+    fn rows_affected(&self) -> u64;
+}
+
+/// A type read from and written to the columns of a database.
+pub trait Column<DB: Database>: for<'r> Decode<'r, DB> + for<'q> Encode<'q, DB> + Type<DB> {}
+
+impl<DB: Database, T> Column<DB> for T where
+    T: for<'r> Decode<'r, DB> + for<'q> Encode<'q, DB> + Type<DB>
+{
+}
+
+/// Keeps the histories in a SQL database, see `PostgresChatStore` and
+/// `SqliteChatStore` for the databases supported.
+pub struct SqlChatStore<DB: Database> {
+    pool: Pool<DB>,
+}
+
+impl<DB: Database> SqlChatStore<DB> {
+    pub(super) fn new(pool: Pool<DB>) -> Self {
+        Self { pool }
+    }
+}
+
+impl<DB> SqlChatStore<DB>
+where
+    DB: SqlDialect,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    DB::Arguments: IntoArguments<DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'a> &'a str: ColumnIndex<DB::Row> + Encode<'a, DB> + Type<DB>,
+    for<'a> Option<&'a str>: Encode<'a, DB> + Type<DB>,
+    bool: Column<DB>,
+    i64: Column<DB>,
+    Option<i64>: Column<DB>,
+    String: Column<DB>,
+    Option<String>: Column<DB>,
+    Uuid: Column<DB>,
+    Option<Uuid>: Column<DB>,
+    DateTime<Utc>: Column<DB>,
+    Option<DateTime<Utc>>: Column<DB>,
+{
+    async fn chat_exists(&self, chat_id: ChatId) -> Result<bool, ChatServerErrors> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM chats WHERE chat_id = $1)")
+            .bind(chat_id.as_uuid())
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|err| ChatServerErrors::storage_failure("looking up chat".to_string(), err))
+    }
+
+    // Fills in the reactions of messages of a chat, ordered by their sequence
+    // numbers.
+    async fn read_reactions(
+        &self,
+        chat_id: ChatId,
+        messages: &mut [ChatMessage],
+    ) -> Result<(), ChatServerErrors> {
+        let (Some(first), Some(last)) = (messages.first(), messages.last()) else {
+            return Ok(());
+        };
+        let reactions: Vec<(Uuid, Uuid, String)> = sqlx::query_as(
+            "SELECT r.event_id, r.user_id, r.emoji FROM message_reactions r
+             JOIN chat_messages m ON m.chat_id = r.chat_id AND m.event_id = r.event_id
+             WHERE m.chat_id = $1 AND m.sequence BETWEEN $2 AND $3 ORDER BY r.position",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(i64::try_from(first.sequence).unwrap_or(i64::MAX))
+        .bind(i64::try_from(last.sequence).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading reactions".to_string(), err))?;
+        let positions: HashMap<_, _> = messages
+            .iter()
+            .enumerate()
+            .map(|(position, message)| (message.event_id, position))
+            .collect();
+        for (event_id, user_id, emoji) in reactions {
+            if let Some(position) = positions.get(&EventId::from_uuid(event_id)) {
+                messages[*position].add_reaction(&Emoji::new(emoji), UserId::from_uuid(user_id));
+            }
+        }
+        Ok(())
+    }
+
+    // Yields whether the chat was created, its creator becomes the owner.
+    async fn insert_chat(
+        connection: &mut DB::Connection,
+        chat: &ChatMetadata,
+    ) -> Result<bool, ChatServerErrors> {
+        let created = sqlx::query(
+            "INSERT INTO chats (chat_id, name, topic, creator, created_at,
+                                retention_max_age_secs, retention_max_messages, retention_max_bytes,
+                                rate_limit_burst, rate_limit_per_minute, slow_mode_secs)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT DO NOTHING",
+        )
+        .bind(chat.chat_id.as_uuid())
+        .bind(chat.name.as_ref().map(ChatName::as_str))
+        .bind(chat.topic.as_ref().map(ChatTopic::as_str))
+        .bind(chat.creator.map(|creator| creator.as_uuid()))
+        .bind(chat.created_at.as_datetime())
+        .bind(bind_limit(chat.retention.max_age_secs))
+        .bind(bind_limit(chat.retention.max_messages))
+        .bind(bind_limit(chat.retention.max_bytes))
+        .bind(chat.rate_limit.burst.map(i64::from))
+        .bind(chat.rate_limit.per_minute.map(i64::from))
+        .bind(bind_limit(chat.rate_limit.slow_mode_secs))
+        .execute(&mut *connection)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("creating chat".to_string(), err))?
+        .rows_affected()
+            == 1;
+        if let (true, Some(creator)) = (created, chat.creator) {
+            sqlx::query("INSERT INTO chat_members (chat_id, user_id, role) VALUES ($1, $2, $3)")
+                .bind(chat.chat_id.as_uuid())
+                .bind(creator.as_uuid())
+                .bind(Role::Owner.as_str())
+                .execute(&mut *connection)
+                .await
+                .map_err(|err| {
+                    ChatServerErrors::storage_failure("adding owner".to_string(), err)
+                })?;
+        }
+        Ok(created)
+    }
+
+    async fn member_role(
+        connection: &mut DB::Connection,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<Option<Role>, ChatServerErrors> {
+        let role: Option<String> =
+            sqlx::query_scalar("SELECT role FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+                .bind(chat_id.as_uuid())
+                .bind(user_id.as_uuid())
+                .fetch_optional(&mut *connection)
+                .await
+                .map_err(|err| {
+                    ChatServerErrors::storage_failure("reading member".to_string(), err)
+                })?;
+        role.map(|role| {
+            role.parse::<Role>()
+                .map_err(|err| ChatServerErrors::storage_failure("reading member".to_string(), err))
+        })
+        .transpose()
+    }
+}
+
+struct ChatMessageRow(ChatMessage);
+
+impl<R> FromRow<'_, R> for ChatMessageRow
+where
+    R: Row,
+    for<'a> &'a str: ColumnIndex<R>,
+    i64: Column<R::Database>,
+    String: Column<R::Database>,
+    Uuid: Column<R::Database>,
+    Option<Uuid>: Column<R::Database>,
+    DateTime<Utc>: Column<R::Database>,
+    Option<DateTime<Utc>>: Column<R::Database>,
+{
+    fn from_row(row: &R) -> Result<Self, sqlx::Error> {
+        Ok(ChatMessageRow(ChatMessage {
+            event_id: EventId::from_uuid(row.try_get::<Uuid, _>("event_id")?),
+            timestamp: ChatTimestamp::from_datetime(row.try_get::<DateTime<Utc>, _>("timestamp")?),
+            chat_id: ChatId::from_uuid(row.try_get::<Uuid, _>("chat_id")?),
+            sequence: row
+                .try_get::<i64, _>("sequence")?
+                .try_into()
+                .map_err(|err| sqlx::Error::Decode(Box::new(err)))?,
+            user_id: UserId::from_uuid(row.try_get::<Uuid, _>("user_id")?),
+            display_name: DisplayName::new(row.try_get::<String, _>("display_name")?),
+            message: Message::new(row.try_get::<String, _>("message")?),
+            edited_at: row
+                .try_get::<Option<DateTime<Utc>>, _>("edited_at")?
+                .map(ChatTimestamp::from_datetime),
+            deleted_at: row
+                .try_get::<Option<DateTime<Utc>>, _>("deleted_at")?
+                .map(ChatTimestamp::from_datetime),
+            in_reply_to: row
+                .try_get::<Option<Uuid>, _>("in_reply_to")?
+                .map(EventId::from_uuid),
+            thread_root: row
+                .try_get::<Option<Uuid>, _>("thread_root")?
+                .map(EventId::from_uuid),
+            reactions: Vec::new(),
+        }))
+    }
+}
+
+struct ChatMetadataRow(ChatMetadata);
+
+impl<R> FromRow<'_, R> for ChatMetadataRow
+where
+    R: Row,
+    for<'a> &'a str: ColumnIndex<R>,
+    Option<i64>: Column<R::Database>,
+    Option<String>: Column<R::Database>,
+    Uuid: Column<R::Database>,
+    Option<Uuid>: Column<R::Database>,
+    DateTime<Utc>: Column<R::Database>,
+{
+    fn from_row(row: &R) -> Result<Self, sqlx::Error> {
+        Ok(ChatMetadataRow(ChatMetadata {
+            chat_id: ChatId::from_uuid(row.try_get::<Uuid, _>("chat_id")?),
+            name: row.try_get::<Option<String>, _>("name")?.map(ChatName::new),
+            topic: row
+                .try_get::<Option<String>, _>("topic")?
+                .map(ChatTopic::new),
+            creator: row
+                .try_get::<Option<Uuid>, _>("creator")?
+                .map(UserId::from_uuid),
+            created_at: ChatTimestamp::from_datetime(
+                row.try_get::<DateTime<Utc>, _>("created_at")?,
+            ),
+            retention: RetentionPolicy {
+                max_age_secs: try_get_limit(row, "retention_max_age_secs")?,
+                max_messages: try_get_limit(row, "retention_max_messages")?,
+                max_bytes: try_get_limit(row, "retention_max_bytes")?,
+            },
+            rate_limit: RateLimitPolicy {
+                burst: try_get_count(row, "rate_limit_burst")?,
+                per_minute: try_get_count(row, "rate_limit_per_minute")?,
+                slow_mode_secs: try_get_limit(row, "slow_mode_secs")?,
+            },
+        }))
+    }
+}
+
+fn try_get_limit<R>(row: &R, column: &str) -> Result<Option<u64>, sqlx::Error>
+where
+    R: Row,
+    for<'a> &'a str: ColumnIndex<R>,
+    Option<i64>: Column<R::Database>,
+{
+    row.try_get::<Option<i64>, _>(column)?
+        .map(u64::try_from)
+        .transpose()
+        .map_err(|err| sqlx::Error::Decode(Box::new(err)))
+}
+
+// Counts are only written from `u32`s.
+fn try_get_count<R>(row: &R, column: &str) -> Result<Option<u32>, sqlx::Error>
+where
+    R: Row,
+    for<'a> &'a str: ColumnIndex<R>,
+    Option<i64>: Column<R::Database>,
+{
+    Ok(try_get_limit(row, column)?.map(|count| u32::try_from(count).unwrap_or(u32::MAX)))
+}
+
+// Limits beyond the range of the column are as good as none.
+fn bind_limit(limit: Option<u64>) -> Option<i64> {
+    limit.map(|limit| i64::try_from(limit).unwrap_or(i64::MAX))
+}
+
+struct NoteRow(Note);
+
+impl<R> FromRow<'_, R> for NoteRow
+where
+    R: Row,
+    for<'a> &'a str: ColumnIndex<R>,
+    String: Column<R::Database>,
+    Uuid: Column<R::Database>,
+    DateTime<Utc>: Column<R::Database>,
+{
+    fn from_row(row: &R) -> Result<Self, sqlx::Error> {
+        Ok(NoteRow(Note {
+            note_id: NoteId::from_uuid(row.try_get::<Uuid, _>("note_id")?),
+            chat_id: ChatId::from_uuid(row.try_get::<Uuid, _>("chat_id")?),
+            author: UserId::from_uuid(row.try_get::<Uuid, _>("author")?),
+            body: NoteBody::new(row.try_get::<String, _>("body")?),
+            created_at: ChatTimestamp::from_datetime(
+                row.try_get::<DateTime<Utc>, _>("created_at")?,
+            ),
+            updated_at: ChatTimestamp::from_datetime(
+                row.try_get::<DateTime<Utc>, _>("updated_at")?,
+            ),
+        }))
+    }
+}
+
+#[async_trait]
+impl<DB> ChatStore for SqlChatStore<DB>
+where
+    DB: SqlDialect,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    DB::Arguments: IntoArguments<DB>,
+    usize: ColumnIndex<DB::Row>,
+    for<'a> &'a str: ColumnIndex<DB::Row> + Encode<'a, DB> + Type<DB>,
+    for<'a> Option<&'a str>: Encode<'a, DB> + Type<DB>,
+    bool: Column<DB>,
+    i64: Column<DB>,
+    Option<i64>: Column<DB>,
+    String: Column<DB>,
+    Option<String>: Column<DB>,
+    Uuid: Column<DB>,
+    Option<Uuid>: Column<DB>,
+    DateTime<Utc>: Column<DB>,
+    Option<DateTime<Utc>>: Column<DB>,
+{
+    async fn create_chat(&self, chat: &ChatMetadata) -> Result<bool, ChatServerErrors> {
+        let mut transaction = self.pool.begin().await.map_err(|err| {
+            ChatServerErrors::storage_failure("starting transaction".to_string(), err)
+        })?;
+        let created = Self::insert_chat(&mut transaction, chat).await?;
+        transaction.commit().await.map_err(|err| {
+            ChatServerErrors::storage_failure("committing transaction".to_string(), err)
+        })?;
+        Ok(created)
+    }
+
+    async fn read_chat(&self, chat_id: ChatId) -> Result<Option<ChatMetadata>, ChatServerErrors> {
+        let chat: Option<ChatMetadataRow> = sqlx::query_as(
+            "SELECT chat_id, name, topic, creator, created_at,
+                    retention_max_age_secs, retention_max_messages, retention_max_bytes,
+                    rate_limit_burst, rate_limit_per_minute, slow_mode_secs
+             FROM chats WHERE chat_id = $1",
+        )
+        .bind(chat_id.as_uuid())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading chat".to_string(), err))?;
+        Ok(chat.map(|row| row.0))
+    }
+
+    async fn read_chats(&self) -> Result<Vec<ChatMetadata>, ChatServerErrors> {
+        let chats: Vec<ChatMetadataRow> = sqlx::query_as(
+            "SELECT chat_id, name, topic, creator, created_at,
+                    retention_max_age_secs, retention_max_messages, retention_max_bytes,
+                    rate_limit_burst, rate_limit_per_minute, slow_mode_secs
+             FROM chats",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading chats".to_string(), err))?;
+        Ok(chats.into_iter().map(|row| row.0).collect())
+    }
+
+    async fn update_retention(
+        &self,
+        chat_id: ChatId,
+        retention: &RetentionPolicy,
+    ) -> Result<bool, ChatServerErrors> {
+        let updated = sqlx::query(
+            "UPDATE chats SET retention_max_age_secs = $2, retention_max_messages = $3,
+                              retention_max_bytes = $4
+             WHERE chat_id = $1",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(bind_limit(retention.max_age_secs))
+        .bind(bind_limit(retention.max_messages))
+        .bind(bind_limit(retention.max_bytes))
+        .execute(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("updating retention".to_string(), err))?
+        .rows_affected();
+        Ok(updated == 1)
+    }
+
+    async fn update_rate_limit(
+        &self,
+        chat_id: ChatId,
+        rate_limit: &RateLimitPolicy,
+    ) -> Result<bool, ChatServerErrors> {
+        let updated = sqlx::query(
+            "UPDATE chats SET rate_limit_burst = $2, rate_limit_per_minute = $3,
+                              slow_mode_secs = $4
+             WHERE chat_id = $1",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(rate_limit.burst.map(i64::from))
+        .bind(rate_limit.per_minute.map(i64::from))
+        .bind(bind_limit(rate_limit.slow_mode_secs))
+        .execute(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("updating rate limit".to_string(), err))?
+        .rows_affected();
+        Ok(updated == 1)
+    }
+
+    async fn prune_messages(
+        &self,
+        chat_id: ChatId,
+        limits: &RetentionLimits,
+    ) -> Result<PrunedMessages, ChatServerErrors> {
+        let reading_failed =
+            |err| ChatServerErrors::storage_failure("reading messages to prune".to_string(), err);
+        // The newest message violating a limit, it is pruned with all before.
+        let mut last_pruned: Option<i64> = None;
+        if let Some(older_than) = &limits.older_than {
+            let newest_too_old: Option<i64> = sqlx::query_scalar(
+                "SELECT MAX(sequence) FROM chat_messages WHERE chat_id = $1 AND timestamp < $2",
+            )
+            .bind(chat_id.as_uuid())
+            .bind(older_than.as_datetime())
+            .fetch_one(&self.pool)
+            .await
+            .map_err(reading_failed)?;
+            last_pruned = last_pruned.max(newest_too_old);
+        }
+        if let Some(max_messages) = bind_limit(limits.max_messages) {
+            let newest_too_many: Option<i64> = sqlx::query_scalar(
+                "SELECT sequence FROM chat_messages WHERE chat_id = $1
+                 ORDER BY sequence DESC LIMIT 1 OFFSET $2",
+            )
+            .bind(chat_id.as_uuid())
+            .bind(max_messages)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(reading_failed)?;
+            last_pruned = last_pruned.max(newest_too_many);
+        }
+        if let Some(max_bytes) = bind_limit(limits.max_bytes) {
+            let newest_too_large: Option<i64> = sqlx::query_scalar(
+                "SELECT MAX(sequence) FROM (
+                     SELECT sequence, SUM(octet_length(message)) OVER (ORDER BY sequence DESC) AS bytes
+                     FROM chat_messages WHERE chat_id = $1
+                 ) AS newest WHERE bytes > $2",
+            )
+            .bind(chat_id.as_uuid())
+            .bind(max_bytes)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(reading_failed)?;
+            last_pruned = last_pruned.max(newest_too_large);
+        }
+        let Some(last_pruned) = last_pruned else {
+            return Ok(PrunedMessages::default());
+        };
+        let sizes: Vec<i64> = sqlx::query_scalar(
+            "DELETE FROM chat_messages WHERE chat_id = $1 AND sequence <= $2
+             RETURNING CAST(octet_length(message) AS BIGINT)",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(last_pruned)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("pruning messages".to_string(), err))?;
+        Ok(PrunedMessages {
+            messages: sizes.len() as u64,
+            bytes: sizes.iter().sum::<i64>().try_into().unwrap_or_default(),
+        })
+    }
+
+    async fn delete_chat_if_unused(&self, chat_id: ChatId) -> Result<bool, ChatServerErrors> {
+        // Members and invite uses are deleted with the chat. Pruning keeps
+        // `last_sequence`, so it tells whether the chat ever had a message.
+        let deleted = sqlx::query(
+            "DELETE FROM chats WHERE chat_id = $1 AND last_sequence = 0
+             AND NOT EXISTS (SELECT 1 FROM notes WHERE chat_id = $1)
+             AND NOT EXISTS (
+                 SELECT 1 FROM chat_members m
+                 WHERE m.chat_id = $1 AND m.user_id IS DISTINCT FROM chats.creator
+             )",
+        )
+        .bind(chat_id.as_uuid())
+        .execute(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("deleting chat".to_string(), err))?
+        .rows_affected();
+        Ok(deleted == 1)
+    }
+
+    async fn append_message(&self, message: &ChatMessage) -> Result<u64, ChatServerErrors> {
+        let mut transaction = self.pool.begin().await.map_err(|err| {
+            ChatServerErrors::storage_failure("starting transaction".to_string(), err)
+        })?;
+        // Updating the chat locks it, so concurrent appends to the same chat
+        // can't take the same sequence number.
+        let sequence: i64 = sqlx::query_scalar(
+            "UPDATE chats SET last_sequence = last_sequence + 1 WHERE chat_id = $1
+             RETURNING last_sequence",
+        )
+        .bind(message.chat_id.as_uuid())
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("numbering message".to_string(), err))?
+        .ok_or_else(|| ChatServerErrors::chat_not_found(message.chat_id))?;
+        sqlx::query(
+            "INSERT INTO chat_messages
+             (chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+              in_reply_to, thread_root)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(message.chat_id.as_uuid())
+        .bind(message.event_id.as_uuid())
+        .bind(sequence)
+        .bind(message.timestamp.as_datetime())
+        .bind(message.user_id.as_uuid())
+        .bind(message.display_name.as_str())
+        .bind(message.message.as_str())
+        .bind(message.in_reply_to.map(|event_id| event_id.as_uuid()))
+        .bind(message.thread_root.map(|event_id| event_id.as_uuid()))
+        .execute(&mut *transaction)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("appending message".to_string(), err))?;
+        transaction.commit().await.map_err(|err| {
+            ChatServerErrors::storage_failure("committing transaction".to_string(), err)
+        })?;
+        Ok(sequence.try_into().unwrap_or_default())
+    }
+
+    async fn read_message_range(
+        &self,
+        chat_id: ChatId,
+        cursor: HistoryCursor,
+        limit: usize,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        if !self.chat_exists(chat_id).await? {
+            return Ok(None);
+        }
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        let cursor_position = |event_id: EventId| async move {
+            let position: Option<i64> = sqlx::query_scalar(
+                "SELECT position FROM chat_messages WHERE chat_id = $1 AND event_id = $2",
+            )
+            .bind(chat_id.as_uuid())
+            .bind(event_id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|err| ChatServerErrors::storage_failure("reading cursor".to_string(), err))?;
+            position.ok_or_else(|| ChatServerErrors::event_not_found(chat_id, event_id))
+        };
+        // Pages before a cursor are read backwards and reversed afterwards.
+        let (query, bound, reversed) = match cursor {
+            HistoryCursor::Latest => (
+                "SELECT chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                        edited_at, deleted_at, in_reply_to, thread_root
+                 FROM chat_messages WHERE chat_id = $1 AND position <= $2
+                 ORDER BY position DESC LIMIT $3",
+                // Without a cursor every message qualifies.
+                i64::MAX,
+                true,
+            ),
+            HistoryCursor::Before(event_id) => (
+                "SELECT chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                        edited_at, deleted_at, in_reply_to, thread_root
+                 FROM chat_messages WHERE chat_id = $1 AND position < $2
+                 ORDER BY position DESC LIMIT $3",
+                cursor_position(event_id).await?,
+                true,
+            ),
+            HistoryCursor::After(event_id) => (
+                "SELECT chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                        edited_at, deleted_at, in_reply_to, thread_root
+                 FROM chat_messages WHERE chat_id = $1 AND position > $2
+                 ORDER BY position LIMIT $3",
+                cursor_position(event_id).await?,
+                false,
+            ),
+            HistoryCursor::Since(sequence) => (
+                "SELECT chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                        edited_at, deleted_at, in_reply_to, thread_root
+                 FROM chat_messages WHERE chat_id = $1 AND sequence > $2
+                 ORDER BY position LIMIT $3",
+                i64::try_from(sequence).unwrap_or(i64::MAX),
+                false,
+            ),
+        };
+        let rows: Vec<ChatMessageRow> = sqlx::query_as(query)
+            .bind(chat_id.as_uuid())
+            .bind(bound)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|err| {
+                ChatServerErrors::storage_failure("reading messages".to_string(), err)
+            })?;
+        let mut messages: Vec<_> = rows.into_iter().map(|row| row.0).collect();
+        if reversed {
+            messages.reverse();
+        }
+        self.read_reactions(chat_id, &mut messages).await?;
+        Ok(Some(messages))
+    }
+
+    async fn read_thread(
+        &self,
+        chat_id: ChatId,
+        thread_root: EventId,
+    ) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+        if !self.chat_exists(chat_id).await? {
+            return Ok(None);
+        }
+        let rows: Vec<ChatMessageRow> = sqlx::query_as(
+            "SELECT chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                    edited_at, deleted_at, in_reply_to, thread_root
+             FROM chat_messages
+             WHERE chat_id = $1 AND (event_id = $2 OR thread_root = $2) ORDER BY position",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(thread_root.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading thread".to_string(), err))?;
+        let mut messages: Vec<_> = rows.into_iter().map(|row| row.0).collect();
+        self.read_reactions(chat_id, &mut messages).await?;
+        Ok(Some(messages))
+    }
+
+    async fn read_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let message: Option<ChatMessageRow> = sqlx::query_as(
+            "SELECT chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                    edited_at, deleted_at, in_reply_to, thread_root
+             FROM chat_messages WHERE chat_id = $1 AND event_id = $2",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(event_id.as_uuid())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading message".to_string(), err))?;
+        let mut messages: Vec<_> = message.into_iter().map(|row| row.0).collect();
+        self.read_reactions(chat_id, &mut messages).await?;
+        Ok(messages.pop())
+    }
+
+    async fn edit_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        message: &Message,
+        edited_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let mut transaction = self.pool.begin().await.map_err(|err| {
+            ChatServerErrors::storage_failure("starting transaction".to_string(), err)
+        })?;
+        let revised = sqlx::query(DB::KEEP_REVISION)
+            .bind(chat_id.as_uuid())
+            .bind(event_id.as_uuid())
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| ChatServerErrors::storage_failure("keeping revision".to_string(), err))?
+            .rows_affected();
+        if revised == 0 {
+            return Ok(None);
+        }
+        let edited: ChatMessageRow = sqlx::query_as(
+            "UPDATE chat_messages SET message = $3, edited_at = $4
+             WHERE chat_id = $1 AND event_id = $2
+             RETURNING chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                       edited_at, deleted_at, in_reply_to, thread_root",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(event_id.as_uuid())
+        .bind(message.as_str())
+        .bind(edited_at.as_datetime())
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("editing message".to_string(), err))?;
+        transaction.commit().await.map_err(|err| {
+            ChatServerErrors::storage_failure("committing transaction".to_string(), err)
+        })?;
+        let mut edited = [edited.0];
+        self.read_reactions(chat_id, &mut edited).await?;
+        let [edited] = edited;
+        Ok(Some(edited))
+    }
+
+    async fn delete_message(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        deleted_at: &ChatTimestamp,
+    ) -> Result<Option<ChatMessage>, ChatServerErrors> {
+        let mut transaction = self.pool.begin().await.map_err(|err| {
+            ChatServerErrors::storage_failure("starting transaction".to_string(), err)
+        })?;
+        let tombstone: Option<ChatMessageRow> = sqlx::query_as(
+            "UPDATE chat_messages SET message = '', deleted_at = $3
+             WHERE chat_id = $1 AND event_id = $2 AND deleted_at IS NULL
+             RETURNING chat_id, event_id, sequence, timestamp, user_id, display_name, message,
+                       edited_at, deleted_at, in_reply_to, thread_root",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(event_id.as_uuid())
+        .bind(deleted_at.as_datetime())
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("deleting message".to_string(), err))?;
+        if tombstone.is_none() {
+            return Ok(None);
+        }
+        sqlx::query("DELETE FROM message_revisions WHERE chat_id = $1 AND event_id = $2")
+            .bind(chat_id.as_uuid())
+            .bind(event_id.as_uuid())
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| {
+                ChatServerErrors::storage_failure("deleting revisions".to_string(), err)
+            })?;
+        sqlx::query("DELETE FROM message_reactions WHERE chat_id = $1 AND event_id = $2")
+            .bind(chat_id.as_uuid())
+            .bind(event_id.as_uuid())
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| {
+                ChatServerErrors::storage_failure("deleting reactions".to_string(), err)
+            })?;
+        transaction.commit().await.map_err(|err| {
+            ChatServerErrors::storage_failure("committing transaction".to_string(), err)
+        })?;
+        Ok(tombstone.map(|row| row.0))
+    }
+
+    async fn read_message_revisions(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+    ) -> Result<Option<Vec<MessageRevision>>, ChatServerErrors> {
+        if self.read_message(chat_id, event_id).await?.is_none() {
+            return Ok(None);
+        }
+        let revisions: Vec<(String, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT message, timestamp FROM message_revisions
+             WHERE chat_id = $1 AND event_id = $2 ORDER BY position",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(event_id.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading revisions".to_string(), err))?;
+        Ok(Some(
+            revisions
+                .into_iter()
+                .map(|(message, timestamp)| MessageRevision {
+                    message: Message::new(message),
+                    timestamp: ChatTimestamp::from_datetime(timestamp),
+                })
+                .collect(),
+        ))
+    }
+
+    async fn add_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors> {
+        let added = sqlx::query(DB::ADD_REACTION)
+            .bind(chat_id.as_uuid())
+            .bind(event_id.as_uuid())
+            .bind(user_id.as_uuid())
+            .bind(emoji.as_str())
+            .execute(&self.pool)
+            .await
+            .map_err(|err| ChatServerErrors::storage_failure("adding reaction".to_string(), err))?
+            .rows_affected()
+            == 1;
+        Ok(added)
+    }
+
+    async fn remove_reaction(
+        &self,
+        chat_id: ChatId,
+        event_id: EventId,
+        user_id: UserId,
+        emoji: &Emoji,
+    ) -> Result<bool, ChatServerErrors> {
+        let removed = sqlx::query(
+            "DELETE FROM message_reactions
+             WHERE chat_id = $1 AND event_id = $2 AND user_id = $3 AND emoji = $4",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(event_id.as_uuid())
+        .bind(user_id.as_uuid())
+        .bind(emoji.as_str())
+        .execute(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("removing reaction".to_string(), err))?
+        .rows_affected()
+            == 1;
+        Ok(removed)
+    }
+
+    async fn mark_read(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        event_id: EventId,
+    ) -> Result<Option<ReadReceipt>, ChatServerErrors> {
+        let sequence: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO read_markers (chat_id, user_id, event_id, sequence)
+             SELECT chat_id, $2, event_id, sequence FROM chat_messages
+             WHERE chat_id = $1 AND event_id = $3
+             ON CONFLICT (chat_id, user_id) DO UPDATE
+             SET event_id = excluded.event_id, sequence = excluded.sequence
+             WHERE read_markers.sequence < excluded.sequence
+             RETURNING sequence",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(user_id.as_uuid())
+        .bind(event_id.as_uuid())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("marking read".to_string(), err))?;
+        Ok(sequence.map(|sequence| ReadReceipt {
+            user_id,
+            event_id,
+            sequence: sequence.try_into().unwrap_or_default(),
+        }))
+    }
+
+    async fn read_unread_messages(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<UnreadMessages>, ChatServerErrors> {
+        let rows: Vec<(Uuid, Option<Uuid>, i64)> = sqlx::query_as(
+            "SELECT m.chat_id, r.event_id,
+                    (SELECT COUNT(*) FROM chat_messages c
+                     WHERE c.chat_id = m.chat_id AND c.sequence > COALESCE(r.sequence, 0)
+                       AND c.user_id <> m.user_id AND c.deleted_at IS NULL)
+             FROM chat_members m
+             LEFT JOIN read_markers r ON r.chat_id = m.chat_id AND r.user_id = m.user_id
+             WHERE m.user_id = $1 ORDER BY m.chat_id",
+        )
+        .bind(user_id.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| {
+            ChatServerErrors::storage_failure("counting unread messages".to_string(), err)
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|(chat_id, last_read, unread)| UnreadMessages {
+                chat_id: ChatId::from_uuid(chat_id),
+                last_read: last_read.map(EventId::from_uuid),
+                unread: unread.try_into().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn read_member_chats(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ChatSummary>, ChatServerErrors> {
+        let chats = sqlx::query(
+            "SELECT c.chat_id, c.name, c.topic, c.creator, c.created_at,
+                    c.retention_max_age_secs, c.retention_max_messages, c.retention_max_bytes,
+                    c.rate_limit_burst, c.rate_limit_per_minute, c.slow_mode_secs,
+                    (SELECT timestamp FROM chat_messages
+                     WHERE chat_id = c.chat_id ORDER BY sequence DESC LIMIT 1) AS last_message_at
+             FROM chat_members m JOIN chats c ON c.chat_id = m.chat_id
+             WHERE m.user_id = $1",
+        )
+        .bind(user_id.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading chats".to_string(), err))?;
+        // The latest message of every chat which isn't deleted.
+        let latest: Vec<ChatMessageRow> = sqlx::query_as(
+            "SELECT p.chat_id, p.event_id, p.sequence, p.timestamp, p.user_id, p.display_name,
+                    p.message, p.edited_at, p.deleted_at, p.in_reply_to, p.thread_root
+             FROM chat_members m JOIN chat_messages p ON p.chat_id = m.chat_id
+             WHERE m.user_id = $1 AND p.sequence = (
+                 SELECT MAX(sequence) FROM chat_messages
+                 WHERE chat_id = p.chat_id AND deleted_at IS NULL
+             )",
+        )
+        .bind(user_id.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading messages".to_string(), err))?;
+        let mut previews: HashMap<_, _> = latest
+            .into_iter()
+            .map(|row| (row.0.chat_id, MessagePreview::of(&row.0)))
+            .collect();
+        chats
+            .iter()
+            .map(|row| {
+                let metadata = ChatMetadataRow::from_row(row)?.0;
+                let last_message_at = row.try_get::<Option<DateTime<Utc>>, _>("last_message_at")?;
+                Ok(ChatSummary {
+                    last_activity: last_message_at
+                        .map_or_else(|| metadata.created_at.clone(), ChatTimestamp::from_datetime),
+                    last_message: previews.remove(&metadata.chat_id),
+                    metadata,
+                })
+            })
+            .collect::<Result<_, sqlx::Error>>()
+            .map_err(|err| ChatServerErrors::storage_failure("reading chats".to_string(), err))
+    }
+
+    async fn read_members(
+        &self,
+        chat_id: ChatId,
+    ) -> Result<Option<HashMap<UserId, Role>>, ChatServerErrors> {
+        if !self.chat_exists(chat_id).await? {
+            return Ok(None);
+        }
+        let members: Vec<(Uuid, String)> =
+            sqlx::query_as("SELECT user_id, role FROM chat_members WHERE chat_id = $1")
+                .bind(chat_id.as_uuid())
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|err| {
+                    ChatServerErrors::storage_failure("reading members".to_string(), err)
+                })?;
+        members
+            .into_iter()
+            .map(|(user_id, role)| {
+                let role = role.parse::<Role>().map_err(|err| {
+                    ChatServerErrors::storage_failure("reading members".to_string(), err)
+                })?;
+                Ok((UserId::from_uuid(user_id), role))
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    async fn redeem_invite(
+        &self,
+        invite: &Invite,
+        user_id: UserId,
+    ) -> Result<InviteRedemption, ChatServerErrors> {
+        let mut transaction = self.pool.begin().await.map_err(|err| {
+            ChatServerErrors::storage_failure("starting transaction".to_string(), err)
+        })?;
+        let chat_exists: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM chats WHERE chat_id = $1)")
+                .bind(invite.chat_id.as_uuid())
+                .fetch_one(&mut *transaction)
+                .await
+                .map_err(|err| {
+                    ChatServerErrors::storage_failure("looking up chat".to_string(), err)
+                })?;
+        if !chat_exists {
+            return Err(ChatServerErrors::chat_not_found(invite.chat_id));
+        }
+        if let Some(role) = Self::member_role(&mut transaction, invite.chat_id, user_id).await? {
+            return Ok(InviteRedemption::AlreadyMember(role));
+        }
+        if invite.max_uses == 0 {
+            return Ok(InviteRedemption::UsedUp);
+        }
+        // Counting the use and checking the maximum in one statement, so
+        // concurrent redemptions can't both take the last use.
+        let uses: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO invite_uses (invite_id, chat_id, uses) VALUES ($1, $2, 1)
+             ON CONFLICT (invite_id) DO UPDATE SET uses = invite_uses.uses + 1
+             WHERE invite_uses.uses < $3
+             RETURNING uses",
+        )
+        .bind(invite.invite_id.as_uuid())
+        .bind(invite.chat_id.as_uuid())
+        .bind(i64::from(invite.max_uses))
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("counting invite use".to_string(), err))?;
+        if uses.is_none() {
+            return Ok(InviteRedemption::UsedUp);
+        }
+        let added = sqlx::query(
+            "INSERT INTO chat_members (chat_id, user_id, role) VALUES ($1, $2, $3)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(invite.chat_id.as_uuid())
+        .bind(user_id.as_uuid())
+        .bind(invite.role.as_str())
+        .execute(&mut *transaction)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("adding member".to_string(), err))?
+        .rows_affected()
+            == 1;
+        if !added {
+            // Joined concurrently by other means, dropping the transaction
+            // rolls back the use of the invite.
+            let role = Self::member_role(&mut transaction, invite.chat_id, user_id).await?;
+            return Ok(role.map_or(InviteRedemption::UsedUp, InviteRedemption::AlreadyMember));
+        }
+        transaction.commit().await.map_err(|err| {
+            ChatServerErrors::storage_failure("committing transaction".to_string(), err)
+        })?;
+        Ok(InviteRedemption::Joined(invite.role))
+    }
+
+    async fn create_note(&self, note: &Note) -> Result<(), ChatServerErrors> {
+        if !self.chat_exists(note.chat_id).await? {
+            return Err(ChatServerErrors::chat_not_found(note.chat_id));
+        }
+        sqlx::query(
+            "INSERT INTO notes (chat_id, note_id, author, body, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(note.chat_id.as_uuid())
+        .bind(note.note_id.as_uuid())
+        .bind(note.author.as_uuid())
+        .bind(note.body.as_str())
+        .bind(note.created_at.as_datetime())
+        .bind(note.updated_at.as_datetime())
+        .execute(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("creating note".to_string(), err))?;
+        Ok(())
+    }
+
+    async fn read_notes(&self, chat_id: ChatId) -> Result<Option<Vec<Note>>, ChatServerErrors> {
+        if !self.chat_exists(chat_id).await? {
+            return Ok(None);
+        }
+        let notes: Vec<NoteRow> = sqlx::query_as(
+            "SELECT chat_id, note_id, author, body, created_at, updated_at
+             FROM notes WHERE chat_id = $1 ORDER BY position",
+        )
+        .bind(chat_id.as_uuid())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading notes".to_string(), err))?;
+        Ok(Some(notes.into_iter().map(|row| row.0).collect()))
+    }
+
+    async fn read_note(
+        &self,
+        chat_id: ChatId,
+        note_id: NoteId,
+    ) -> Result<Option<Note>, ChatServerErrors> {
+        let note: Option<NoteRow> = sqlx::query_as(
+            "SELECT chat_id, note_id, author, body, created_at, updated_at
+             FROM notes WHERE chat_id = $1 AND note_id = $2",
+        )
+        .bind(chat_id.as_uuid())
+        .bind(note_id.as_uuid())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("reading note".to_string(), err))?;
+        Ok(note.map(|row| row.0))
+    }
+
+    async fn update_note(&self, note: &Note) -> Result<bool, ChatServerErrors> {
+        let updated = sqlx::query(
+            "UPDATE notes SET body = $1, updated_at = $2 WHERE chat_id = $3 AND note_id = $4",
+        )
+        .bind(note.body.as_str())
+        .bind(note.updated_at.as_datetime())
+        .bind(note.chat_id.as_uuid())
+        .bind(note.note_id.as_uuid())
+        .execute(&self.pool)
+        .await
+        .map_err(|err| ChatServerErrors::storage_failure("updating note".to_string(), err))?
+        .rows_affected()
+            == 1;
+        Ok(updated)
+    }
+}
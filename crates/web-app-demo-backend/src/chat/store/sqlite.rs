@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use sqlx::{
+    Sqlite,
+    sqlite::{
+        SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteQueryResult,
+        SqliteSynchronous,
+    },
+};
+
+use super::sql::{RowsAffected, SqlChatStore, SqlDialect};
+use crate::{chat::ChatServerErrors, settings::SqliteSettings};
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// Keeps the histories in a local sqlite database file, for deployments
+/// without a database server. The file is created and migrated on open.
+pub type SqliteChatStore = SqlChatStore<Sqlite>;
+
+impl SqliteChatStore {
+    pub async fn open(settings: &SqliteSettings) -> Result<Self, ChatServerErrors> {
+        // WAL allows readers alongside the writer, synchronous FULL syncs
+        // every commit to disk, so acknowledged messages survive a crash.
+        let options = SqliteConnectOptions::new()
+            .filename(&settings.path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Full)
+            .foreign_keys(true)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(settings.max_connections)
+            .connect_with(options)
+            .await
+            .map_err(|err| {
+                ChatServerErrors::storage_failure(
+                    format!("opening sqlite database {}", settings.path.display()),
+                    err,
+                )
+            })?;
+        MIGRATOR.run(&pool).await.map_err(|err| {
+            ChatServerErrors::storage_failure("migrating the sqlite schema".to_string(), err)
+        })?;
+        Ok(Self::new(pool))
+    }
+}
+
+impl SqlDialect for Sqlite {
+    // Writing first takes the database lock, so concurrent edits keep every
+    // replaced text.
+    const KEEP_REVISION: &'static str =
+        "INSERT INTO message_revisions (chat_id, event_id, message, timestamp)
+         SELECT chat_id, event_id, message, COALESCE(edited_at, timestamp) FROM chat_messages
+         WHERE chat_id = $1 AND event_id = $2 AND deleted_at IS NULL";
+
+    // Writing takes the database lock, so the message isn't deleted before
+    // the reaction is added.
+    const ADD_REACTION: &'static str =
+        "INSERT INTO message_reactions (chat_id, event_id, user_id, emoji)
+         SELECT chat_id, event_id, $3, $4 FROM chat_messages
+         WHERE chat_id = $1 AND event_id = $2 AND deleted_at IS NULL
+         ON CONFLICT DO NOTHING";
+}
+
+impl RowsAffected for SqliteQueryResult {
+    fn rows_affected(&self) -> u64 {
+        SqliteQueryResult::rows_affected(self)
+    }
+}
@@ -0,0 +1,1301 @@
+// Every store implementation has to pass the same tests, so they are written
+// against `dyn ChatStore` and instantiated for every implementation below.
+//
+// The postgres tests need a database, so they are ignored unless run with
+// `--ignored` and `POSTGRES_TEST_URL` set, e.g. to
+// `postgres://postgres@localhost:5432/postgres` for a container started with
+// `docker run --rm -p 5432:5432 -e POSTGRES_HOST_AUTH_METHOD=trust postgres`.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+use super::*;
+use crate::{
+    chat::models::{
+        ChatMetadata, ChatName, ChatSummary, ChatTimestamp, ChatTopic, DisplayName, Emoji, EventId,
+        HistoryCursor, Invite, InviteId, InviteRedemption, Message, MessagePreview,
+        MessageRevision, PrunedMessages, RateLimitPolicy, Reaction, ReadReceipt, RetentionLimits,
+        RetentionPolicy, Role, UnreadMessages, UserId,
+    },
+    notes::models::{Note, NoteBody, NoteId},
+    settings::{FsyncPolicy, JournalSettings, PostgresSettings, SqliteSettings},
+    util::secret::Secret,
+};
+
+const POSTGRES_URL_ENV_VAR: &str = "POSTGRES_TEST_URL";
+
+fn test_message(chat_id: ChatId, text: &str) -> ChatMessage {
+    ChatMessage {
+        event_id: EventId::random(),
+        timestamp: ChatTimestamp::now(),
+        chat_id,
+        sequence: 0,
+        user_id: UserId::random(),
+        display_name: DisplayName::new("Hugo".to_string()),
+        message: Message::new(text.to_string()),
+        in_reply_to: None,
+        thread_root: None,
+        edited_at: None,
+        deleted_at: None,
+        reactions: Vec::new(),
+    }
+}
+
+async fn read_history(
+    store: &dyn ChatStore,
+    chat_id: ChatId,
+) -> Result<Option<Vec<ChatMessage>>, ChatServerErrors> {
+    store
+        .read_message_range(chat_id, HistoryCursor::Latest, usize::MAX)
+        .await
+}
+
+fn unnamed_chat(chat_id: ChatId) -> ChatMetadata {
+    ChatMetadata::unnamed(chat_id, UserId::random(), ChatTimestamp::now())
+}
+
+// Messages are only appended to existing chats.
+async fn create_chats(store: &dyn ChatStore, chat_ids: &[ChatId]) -> anyhow::Result<()> {
+    for &chat_id in chat_ids {
+        store.create_chat(&unnamed_chat(chat_id)).await?;
+    }
+    Ok(())
+}
+
+fn test_invite(chat_id: ChatId, role: Role, max_uses: u32) -> Invite {
+    Invite {
+        invite_id: InviteId::random(),
+        chat_id,
+        role,
+        max_uses,
+        expires_at: chrono::Utc::now() + chrono::Duration::hours(1),
+    }
+}
+
+async fn reading_an_unknown_chat_yields_none(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let history = read_history(store, ChatId::random()).await?;
+    assert_eq!(history, None, "an unknown chat should have no history");
+    Ok(())
+}
+
+async fn an_ensured_chat_has_an_empty_history(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    store.create_chat(&unnamed_chat(chat_id)).await?;
+    store.create_chat(&unnamed_chat(chat_id)).await?;
+    let history = read_history(store, chat_id).await?;
+    assert_eq!(history, Some(vec![]), "an ensured chat should be empty");
+    Ok(())
+}
+
+async fn appended_messages_are_read_back_in_order(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    let other_chat_id = ChatId::random();
+    create_chats(store, &[chat_id, other_chat_id]).await?;
+    let mut messages: Vec<_> = (1..=5)
+        .map(|i| test_message(chat_id, &format!("Nachricht {i}")))
+        .collect();
+    for message in &mut messages {
+        message.sequence = store.append_message(message).await?;
+        store
+            .append_message(&test_message(other_chat_id, "woanders"))
+            .await?;
+    }
+    let history = read_history(store, chat_id).await?;
+    pretty_assertions::assert_eq!(history, Some(messages));
+    Ok(())
+}
+
+async fn a_created_chat_keeps_its_metadata(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat = ChatMetadata {
+        chat_id: ChatId::random(),
+        name: Some(ChatName::new("Kaffeeklatsch".to_string())),
+        topic: Some(ChatTopic::new("Kuchen".to_string())),
+        creator: Some(UserId::random()),
+        created_at: ChatTimestamp::now(),
+        retention: RetentionPolicy {
+            max_age_secs: Some(3600),
+            max_messages: None,
+            max_bytes: Some(1 << 20),
+        },
+        rate_limit: RateLimitPolicy {
+            burst: Some(5),
+            per_minute: None,
+            slow_mode_secs: Some(30),
+        },
+    };
+    assert_eq!(store.read_chat(chat.chat_id).await?, None);
+    store.create_chat(&chat).await?;
+    store.create_chat(&unnamed_chat(chat.chat_id)).await?;
+    assert_eq!(store.read_chat(chat.chat_id).await?, Some(chat));
+    Ok(())
+}
+
+async fn the_creator_of_a_chat_is_its_owner(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    let creator = UserId::random();
+    assert_eq!(store.read_members(chat_id).await?, None);
+    assert!(
+        store
+            .create_chat(&ChatMetadata::unnamed(
+                chat_id,
+                creator,
+                ChatTimestamp::now()
+            ))
+            .await?
+    );
+    assert!(!store.create_chat(&unnamed_chat(chat_id)).await?);
+    let members = store.read_members(chat_id).await?;
+    assert_eq!(members, Some(HashMap::from([(creator, Role::Owner)])));
+    Ok(())
+}
+
+async fn appending_to_an_unknown_chat_fails(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let message = test_message(ChatId::random(), "erste");
+    let result = store.append_message(&message).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::ChatNotFound { chat_id }) if chat_id == message.chat_id),
+        "appending should neither create a chat nor make its author a member: {result:?}"
+    );
+    assert_eq!(store.read_chat(message.chat_id).await?, None);
+    assert_eq!(store.read_members(message.chat_id).await?, None);
+    Ok(())
+}
+
+async fn an_invite_adds_members_until_it_is_used_up(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat = unnamed_chat(ChatId::random());
+    store.create_chat(&chat).await?;
+    let invite = test_invite(chat.chat_id, Role::ReadOnly, 2);
+    let (first, second, third) = (UserId::random(), UserId::random(), UserId::random());
+
+    assert_eq!(
+        store.redeem_invite(&invite, first).await?,
+        InviteRedemption::Joined(Role::ReadOnly)
+    );
+    assert_eq!(
+        store.redeem_invite(&invite, second).await?,
+        InviteRedemption::Joined(Role::ReadOnly)
+    );
+    assert_eq!(
+        store.redeem_invite(&invite, third).await?,
+        InviteRedemption::UsedUp
+    );
+
+    let members = store.read_members(chat.chat_id).await?.unwrap_or_default();
+    assert_eq!(members.get(&first), Some(&Role::ReadOnly));
+    assert_eq!(members.get(&second), Some(&Role::ReadOnly));
+    assert_eq!(members.get(&third), None);
+    Ok(())
+}
+
+async fn members_redeeming_an_invite_keep_their_role(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat = unnamed_chat(ChatId::random());
+    let owner = chat.creator.expect("unnamed chats have a creator");
+    store.create_chat(&chat).await?;
+    let invite = test_invite(chat.chat_id, Role::Member, 1);
+
+    assert_eq!(
+        store.redeem_invite(&invite, owner).await?,
+        InviteRedemption::AlreadyMember(Role::Owner)
+    );
+    // Members redeeming the invite don't use it up.
+    assert_eq!(
+        store.redeem_invite(&invite, UserId::random()).await?,
+        InviteRedemption::Joined(Role::Member)
+    );
+    Ok(())
+}
+
+async fn an_invite_to_an_unknown_chat_fails(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let invite = test_invite(ChatId::random(), Role::Member, 1);
+    assert!(matches!(
+        store.redeem_invite(&invite, UserId::random()).await,
+        Err(ChatServerErrors::ChatNotFound { .. })
+    ));
+    Ok(())
+}
+
+fn test_note(chat_id: ChatId, text: &str) -> Note {
+    Note {
+        note_id: NoteId::random(),
+        chat_id,
+        author: UserId::random(),
+        body: NoteBody::new(text.to_string()),
+        created_at: ChatTimestamp::now(),
+        updated_at: ChatTimestamp::now(),
+    }
+}
+
+async fn notes_are_read_back_in_order_and_can_be_updated(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    store.create_chat(&unnamed_chat(chat_id)).await?;
+    assert_eq!(store.read_notes(chat_id).await?, Some(vec![]));
+    let mut notes: Vec<_> = (1..=3)
+        .map(|i| test_note(chat_id, &format!("Notiz {i}")))
+        .collect();
+    for note in &notes {
+        store.create_note(note).await?;
+    }
+
+    notes[1].body = NoteBody::new("geändert".to_string());
+    notes[1].updated_at = ChatTimestamp::now();
+    assert!(store.update_note(&notes[1]).await?);
+    assert!(
+        !store.update_note(&test_note(chat_id, "unbekannt")).await?,
+        "an unknown note can't be updated"
+    );
+
+    assert_eq!(store.read_notes(chat_id).await?, Some(notes.clone()));
+    assert_eq!(
+        store.read_note(chat_id, notes[1].note_id).await?,
+        Some(notes[1].clone())
+    );
+    assert_eq!(store.read_note(chat_id, NoteId::random()).await?, None);
+    Ok(())
+}
+
+async fn a_note_in_an_unknown_chat_fails(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    assert!(matches!(
+        store.create_note(&test_note(chat_id, "verloren")).await,
+        Err(ChatServerErrors::ChatNotFound { .. })
+    ));
+    assert_eq!(store.read_notes(chat_id).await?, None);
+    Ok(())
+}
+
+async fn ranges_of_the_history_are_read_next_to_the_cursor(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let mut messages: Vec<_> = (1..=6)
+        .map(|i| test_message(chat_id, &format!("Nachricht {i}")))
+        .collect();
+    for message in &mut messages {
+        message.sequence = store.append_message(message).await?;
+    }
+
+    let latest = store
+        .read_message_range(chat_id, HistoryCursor::Latest, 2)
+        .await?;
+    assert_eq!(latest, Some(messages[4..6].to_vec()));
+    let before = store
+        .read_message_range(chat_id, HistoryCursor::Before(messages[4].event_id), 3)
+        .await?;
+    assert_eq!(before, Some(messages[1..4].to_vec()));
+    let before_start = store
+        .read_message_range(chat_id, HistoryCursor::Before(messages[1].event_id), 3)
+        .await?;
+    assert_eq!(before_start, Some(messages[0..1].to_vec()));
+    let after = store
+        .read_message_range(chat_id, HistoryCursor::After(messages[1].event_id), 2)
+        .await?;
+    assert_eq!(after, Some(messages[2..4].to_vec()));
+    let after_end = store
+        .read_message_range(chat_id, HistoryCursor::After(messages[5].event_id), 2)
+        .await?;
+    assert_eq!(after_end, Some(vec![]));
+    Ok(())
+}
+
+async fn a_range_next_to_an_unknown_cursor_fails(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let message = test_message(ChatId::random(), "einsam");
+    create_chats(store, &[message.chat_id]).await?;
+    store.append_message(&message).await?;
+    let result = store
+        .read_message_range(message.chat_id, HistoryCursor::After(EventId::random()), 10)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ChatServerErrors::EventNotFound { .. })
+    ));
+    Ok(())
+}
+
+async fn messages_are_numbered_per_chat_and_read_since_a_number(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    let other_chat_id = ChatId::random();
+    create_chats(store, &[chat_id, other_chat_id]).await?;
+    let mut messages: Vec<_> = (1..=5)
+        .map(|i| test_message(chat_id, &format!("Nachricht {i}")))
+        .collect();
+    for message in &mut messages {
+        message.sequence = store.append_message(message).await?;
+        store
+            .append_message(&test_message(other_chat_id, "woanders"))
+            .await?;
+    }
+    let sequences: Vec<_> = messages.iter().map(|message| message.sequence).collect();
+    assert_eq!(sequences, vec![1, 2, 3, 4, 5]);
+
+    let since = store
+        .read_message_range(chat_id, HistoryCursor::Since(2), 2)
+        .await?;
+    assert_eq!(since, Some(messages[2..4].to_vec()));
+    let since_the_last = store
+        .read_message_range(chat_id, HistoryCursor::Since(5), 2)
+        .await?;
+    assert_eq!(since_the_last, Some(vec![]));
+    Ok(())
+}
+
+async fn retention_policies_are_updated_and_chats_listed(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let chat = unnamed_chat(ChatId::random());
+    store.create_chat(&chat).await?;
+    let retention = RetentionPolicy {
+        max_age_secs: Some(RetentionPolicy::UNLIMITED),
+        max_messages: Some(100),
+        max_bytes: Some(1 << 30),
+    };
+    assert!(store.update_retention(chat.chat_id, &retention).await?);
+    let expected = ChatMetadata { retention, ..chat };
+    assert_eq!(store.read_chat(chat.chat_id).await?, Some(expected.clone()));
+    let chats = store.read_chats().await?;
+    assert!(chats.contains(&expected), "{chats:?} should list the chat");
+    assert!(!store.update_retention(ChatId::random(), &retention).await?);
+    Ok(())
+}
+
+async fn rate_limit_policies_are_updated(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat = unnamed_chat(ChatId::random());
+    store.create_chat(&chat).await?;
+    let rate_limit = RateLimitPolicy {
+        burst: Some(3),
+        per_minute: Some(12),
+        slow_mode_secs: Some(10),
+    };
+    assert!(store.update_rate_limit(chat.chat_id, &rate_limit).await?);
+    let expected = ChatMetadata {
+        rate_limit,
+        ..chat.clone()
+    };
+    assert_eq!(store.read_chat(chat.chat_id).await?, Some(expected.clone()));
+    let chats = store.read_chats().await?;
+    assert!(chats.contains(&expected), "{chats:?} should list the chat");
+
+    assert!(
+        store
+            .update_rate_limit(chat.chat_id, &RateLimitPolicy::default())
+            .await?
+    );
+    assert_eq!(store.read_chat(chat.chat_id).await?, Some(chat));
+    assert!(
+        !store
+            .update_rate_limit(ChatId::random(), &rate_limit)
+            .await?
+    );
+    Ok(())
+}
+
+async fn histories_are_pruned_up_to_the_newest_message_violating_a_limit(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let now = ChatTimestamp::now().as_datetime();
+    let mut messages = vec![];
+    // Message i is i seconds old and 2 * i bytes long.
+    for i in (1..=6).rev() {
+        let mut message = test_message(chat_id, &"ü".repeat(i));
+        message.timestamp = ChatTimestamp::from_datetime(now - chrono::Duration::seconds(i as i64));
+        message.sequence = store.append_message(&message).await?;
+        messages.push(message);
+    }
+    let unlimited = RetentionLimits {
+        older_than: None,
+        max_messages: None,
+        max_bytes: None,
+    };
+    assert_eq!(
+        store.prune_messages(chat_id, &unlimited).await?,
+        PrunedMessages::default()
+    );
+
+    let pruned = store
+        .prune_messages(
+            chat_id,
+            &RetentionLimits {
+                max_messages: Some(4),
+                ..unlimited.clone()
+            },
+        )
+        .await?;
+    assert_eq!(
+        pruned,
+        PrunedMessages {
+            messages: 2,
+            bytes: 12 + 10,
+        }
+    );
+    let pruned = store
+        .prune_messages(
+            chat_id,
+            &RetentionLimits {
+                older_than: Some(messages[3].timestamp.clone()),
+                ..unlimited.clone()
+            },
+        )
+        .await?;
+    assert_eq!(
+        pruned,
+        PrunedMessages {
+            messages: 1,
+            bytes: 8,
+        }
+    );
+    let pruned = store
+        .prune_messages(
+            chat_id,
+            &RetentionLimits {
+                max_bytes: Some(4 + 2),
+                ..unlimited.clone()
+            },
+        )
+        .await?;
+    assert_eq!(
+        pruned,
+        PrunedMessages {
+            messages: 1,
+            bytes: 6,
+        }
+    );
+    let history = read_history(store, chat_id).await?;
+    pretty_assertions::assert_eq!(history, Some(messages[4..].to_vec()));
+
+    let next = test_message(chat_id, "danach");
+    assert_eq!(store.append_message(&next).await?, 7);
+    assert_eq!(
+        store
+            .prune_messages(
+                ChatId::random(),
+                &RetentionLimits {
+                    max_messages: Some(0),
+                    ..unlimited
+                },
+            )
+            .await?,
+        PrunedMessages::default()
+    );
+    Ok(())
+}
+
+async fn only_unused_chats_are_deleted(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let with_message = test_message(ChatId::random(), "bleibt");
+    create_chats(store, &[with_message.chat_id]).await?;
+    store.append_message(&with_message).await?;
+    let with_note = test_note(ChatId::random(), "bleibt");
+    store.create_chat(&unnamed_chat(with_note.chat_id)).await?;
+    store.create_note(&with_note).await?;
+    let pruned = test_message(ChatId::random(), "weg");
+    create_chats(store, &[pruned.chat_id]).await?;
+    store.append_message(&pruned).await?;
+    store
+        .prune_messages(
+            pruned.chat_id,
+            &RetentionLimits {
+                older_than: None,
+                max_messages: Some(0),
+                max_bytes: None,
+            },
+        )
+        .await?;
+    let with_member = ChatId::random();
+    store.create_chat(&unnamed_chat(with_member)).await?;
+    store
+        .redeem_invite(&test_invite(with_member, Role::Member, 1), UserId::random())
+        .await?;
+    let empty = ChatId::random();
+    store.create_chat(&unnamed_chat(empty)).await?;
+
+    assert!(!store.delete_chat_if_unused(with_message.chat_id).await?);
+    assert!(!store.delete_chat_if_unused(with_note.chat_id).await?);
+    assert!(
+        !store.delete_chat_if_unused(pruned.chat_id).await?,
+        "chats emptied by pruning were used"
+    );
+    assert!(!store.delete_chat_if_unused(with_member).await?);
+    assert!(store.delete_chat_if_unused(empty).await?);
+    assert!(!store.delete_chat_if_unused(empty).await?);
+    assert_eq!(store.read_chat(empty).await?, None);
+    assert_eq!(store.read_members(empty).await?, None);
+    assert!(store.read_chat(with_message.chat_id).await?.is_some());
+    assert!(store.read_chat(with_note.chat_id).await?.is_some());
+    Ok(())
+}
+
+async fn messages_are_edited_with_revisions_and_deleted_as_tombstones(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let mut message = test_message(chat_id, "Tee");
+    message.sequence = store.append_message(&message).await?;
+    let first_edit = ChatTimestamp::now();
+    let second_edit = ChatTimestamp::now();
+
+    let edited = store
+        .edit_message(
+            chat_id,
+            message.event_id,
+            &Message::new("Kaffee".to_string()),
+            &first_edit,
+        )
+        .await?;
+    assert_eq!(
+        edited.map(|edited| edited.message),
+        Some(Message::new("Kaffee".to_string()))
+    );
+    let edited = store
+        .edit_message(
+            chat_id,
+            message.event_id,
+            &Message::new("Kakao".to_string()),
+            &second_edit,
+        )
+        .await?;
+    let expected = ChatMessage {
+        message: Message::new("Kakao".to_string()),
+        edited_at: Some(second_edit.clone()),
+        ..message.clone()
+    };
+    assert_eq!(edited, Some(expected.clone()));
+    assert_eq!(
+        store.read_message(chat_id, message.event_id).await?,
+        Some(expected.clone())
+    );
+    assert_eq!(read_history(store, chat_id).await?, Some(vec![expected]));
+    assert_eq!(
+        store
+            .read_message_revisions(chat_id, message.event_id)
+            .await?,
+        Some(vec![
+            MessageRevision {
+                message: Message::new("Tee".to_string()),
+                timestamp: message.timestamp.clone(),
+            },
+            MessageRevision {
+                message: Message::new("Kaffee".to_string()),
+                timestamp: first_edit,
+            },
+        ])
+    );
+
+    let deleted_at = ChatTimestamp::now();
+    let tombstone = ChatMessage {
+        message: Message::new(String::new()),
+        edited_at: Some(second_edit),
+        deleted_at: Some(deleted_at.clone()),
+        ..message.clone()
+    };
+    assert_eq!(
+        store
+            .delete_message(chat_id, message.event_id, &deleted_at)
+            .await?,
+        Some(tombstone.clone())
+    );
+    assert_eq!(read_history(store, chat_id).await?, Some(vec![tombstone]));
+    assert_eq!(
+        store
+            .read_message_revisions(chat_id, message.event_id)
+            .await?,
+        Some(vec![])
+    );
+    assert_eq!(
+        store
+            .delete_message(chat_id, message.event_id, &deleted_at)
+            .await?,
+        None,
+        "a message should be deleted only once"
+    );
+    assert_eq!(
+        store
+            .edit_message(
+                chat_id,
+                message.event_id,
+                &Message::new("wieder da".to_string()),
+                &deleted_at,
+            )
+            .await?,
+        None,
+        "a deleted message should not be editable"
+    );
+
+    let unknown = EventId::random();
+    assert_eq!(store.read_message(chat_id, unknown).await?, None);
+    assert_eq!(store.read_message_revisions(chat_id, unknown).await?, None);
+    assert_eq!(
+        store
+            .edit_message(
+                chat_id,
+                unknown,
+                &Message::new("nichts".to_string()),
+                &deleted_at
+            )
+            .await?,
+        None
+    );
+    assert_eq!(
+        store.delete_message(chat_id, unknown, &deleted_at).await?,
+        None
+    );
+    Ok(())
+}
+
+async fn pruned_messages_take_their_revisions_along(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let message = test_message(chat_id, "Tee");
+    store.append_message(&message).await?;
+    store
+        .edit_message(
+            chat_id,
+            message.event_id,
+            &Message::new("Kaffee".to_string()),
+            &ChatTimestamp::now(),
+        )
+        .await?;
+    let limits = RetentionLimits {
+        older_than: None,
+        max_messages: Some(0),
+        max_bytes: None,
+    };
+    assert_eq!(store.prune_messages(chat_id, &limits).await?.messages, 1);
+    assert_eq!(
+        store
+            .read_message_revisions(chat_id, message.event_id)
+            .await?,
+        None
+    );
+    Ok(())
+}
+
+fn emoji(emoji: &str) -> Emoji {
+    Emoji::new(emoji.to_string())
+}
+
+async fn reactions_are_aggregated_per_emoji(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let mut first = test_message(chat_id, "Tee?");
+    first.sequence = store.append_message(&first).await?;
+    let mut second = test_message(chat_id, "Kaffee?");
+    second.sequence = store.append_message(&second).await?;
+    let (hugo, erna) = (UserId::random(), UserId::random());
+
+    for (event_id, user_id, with) in [
+        (first.event_id, hugo, "👍"),
+        (first.event_id, erna, "👍"),
+        (first.event_id, hugo, "🎉"),
+        (second.event_id, erna, "❤️"),
+    ] {
+        assert!(
+            store
+                .add_reaction(chat_id, event_id, user_id, &emoji(with))
+                .await?
+        );
+    }
+    assert!(
+        !store
+            .add_reaction(chat_id, first.event_id, hugo, &emoji("👍"))
+            .await?
+    );
+    assert!(
+        !store
+            .add_reaction(chat_id, EventId::random(), hugo, &emoji("👍"))
+            .await?
+    );
+
+    first.reactions = vec![
+        Reaction {
+            emoji: emoji("👍"),
+            count: 2,
+            users: vec![hugo, erna],
+        },
+        Reaction {
+            emoji: emoji("🎉"),
+            count: 1,
+            users: vec![hugo],
+        },
+    ];
+    second.reactions = vec![Reaction {
+        emoji: emoji("❤️"),
+        count: 1,
+        users: vec![erna],
+    }];
+    assert_eq!(
+        read_history(store, chat_id).await?,
+        Some(vec![first.clone(), second.clone()])
+    );
+    assert_eq!(
+        store.read_message(chat_id, second.event_id).await?,
+        Some(second.clone())
+    );
+    let edited = store
+        .edit_message(
+            chat_id,
+            second.event_id,
+            &Message::new("Kakao?".to_string()),
+            &ChatTimestamp::now(),
+        )
+        .await?;
+    assert_eq!(
+        edited.map(|edited| edited.reactions),
+        Some(second.reactions)
+    );
+
+    assert!(
+        store
+            .remove_reaction(chat_id, first.event_id, hugo, &emoji("👍"))
+            .await?
+    );
+    assert!(
+        !store
+            .remove_reaction(chat_id, first.event_id, hugo, &emoji("👍"))
+            .await?
+    );
+    assert!(
+        store
+            .remove_reaction(chat_id, first.event_id, hugo, &emoji("🎉"))
+            .await?
+    );
+    assert_eq!(
+        store
+            .read_message(chat_id, first.event_id)
+            .await?
+            .map(|message| message.reactions),
+        Some(vec![Reaction {
+            emoji: emoji("👍"),
+            count: 1,
+            users: vec![erna],
+        }])
+    );
+    Ok(())
+}
+
+async fn deleted_messages_lose_their_reactions(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let message = test_message(chat_id, "Tee?");
+    store.append_message(&message).await?;
+    let user_id = UserId::random();
+    store
+        .add_reaction(chat_id, message.event_id, user_id, &emoji("👍"))
+        .await?;
+
+    let tombstone = store
+        .delete_message(chat_id, message.event_id, &ChatTimestamp::now())
+        .await?;
+    assert_eq!(tombstone.map(|tombstone| tombstone.reactions), Some(vec![]));
+    assert!(
+        !store
+            .add_reaction(chat_id, message.event_id, user_id, &emoji("🎉"))
+            .await?
+    );
+    assert_eq!(
+        store
+            .read_message(chat_id, message.event_id)
+            .await?
+            .map(|message| message.reactions),
+        Some(vec![])
+    );
+    Ok(())
+}
+
+async fn threads_are_read_by_their_first_message(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    create_chats(store, &[chat_id]).await?;
+    let mut root = test_message(chat_id, "Tee?");
+    root.sequence = store.append_message(&root).await?;
+    let mut first = ChatMessage {
+        in_reply_to: Some(root.event_id),
+        thread_root: Some(root.event_id),
+        ..test_message(chat_id, "Gern")
+    };
+    first.sequence = store.append_message(&first).await?;
+    store
+        .append_message(&test_message(chat_id, "anderes Thema"))
+        .await?;
+    let mut second = ChatMessage {
+        in_reply_to: Some(first.event_id),
+        thread_root: Some(root.event_id),
+        ..test_message(chat_id, "Ich auch")
+    };
+    second.sequence = store.append_message(&second).await?;
+
+    assert_eq!(
+        store.read_thread(chat_id, root.event_id).await?,
+        Some(vec![root, first.clone(), second.clone()])
+    );
+    assert_eq!(
+        store.read_message(chat_id, second.event_id).await?,
+        Some(second)
+    );
+    assert_eq!(
+        store.read_thread(chat_id, first.event_id).await?,
+        Some(vec![first]),
+        "only the first message of a thread should collect its replies"
+    );
+    assert_eq!(
+        store
+            .read_thread(ChatId::random(), EventId::random())
+            .await?,
+        None
+    );
+    Ok(())
+}
+
+async fn read_markers_only_move_forward(store: &dyn ChatStore) -> anyhow::Result<()> {
+    let chat_id = ChatId::random();
+    let reader = UserId::random();
+    store
+        .create_chat(&ChatMetadata::unnamed(
+            chat_id,
+            reader,
+            ChatTimestamp::now(),
+        ))
+        .await?;
+    let mut first = test_message(chat_id, "Tee?");
+    first.sequence = store.append_message(&first).await?;
+    let mut second = test_message(chat_id, "Gern");
+    second.sequence = store.append_message(&second).await?;
+
+    assert_eq!(
+        store.mark_read(chat_id, reader, second.event_id).await?,
+        Some(ReadReceipt {
+            user_id: reader,
+            event_id: second.event_id,
+            sequence: second.sequence,
+        })
+    );
+    assert_eq!(
+        store.mark_read(chat_id, reader, first.event_id).await?,
+        None,
+        "the marker should not move backwards"
+    );
+    assert_eq!(
+        store.mark_read(chat_id, reader, second.event_id).await?,
+        None
+    );
+    assert_eq!(
+        store.mark_read(chat_id, reader, EventId::random()).await?,
+        None
+    );
+    assert_eq!(
+        store
+            .mark_read(ChatId::random(), reader, first.event_id)
+            .await?,
+        None
+    );
+    Ok(())
+}
+
+async fn unread_messages_of_others_are_counted_per_chat(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let reader = UserId::random();
+    let mut chat_ids = [ChatId::random(), ChatId::random()];
+    chat_ids.sort();
+    for chat_id in chat_ids {
+        store
+            .create_chat(&ChatMetadata::unnamed(
+                chat_id,
+                reader,
+                ChatTimestamp::now(),
+            ))
+            .await?;
+    }
+    let [chat_id, quiet_chat_id] = chat_ids;
+    let read = test_message(chat_id, "Tee?");
+    store.append_message(&read).await?;
+    store.mark_read(chat_id, reader, read.event_id).await?;
+    store
+        .append_message(&ChatMessage {
+            user_id: reader,
+            ..test_message(chat_id, "Gern")
+        })
+        .await?;
+    let deleted = test_message(chat_id, "Kaffee?");
+    store.append_message(&deleted).await?;
+    store
+        .delete_message(chat_id, deleted.event_id, &ChatTimestamp::now())
+        .await?;
+    store
+        .append_message(&test_message(chat_id, "Kekse?"))
+        .await?;
+    store
+        .append_message(&test_message(chat_id, "Kuchen?"))
+        .await?;
+    // Unread messages of chats the user isn't a member of don't count.
+    let other_chat_id = ChatId::random();
+    create_chats(store, &[other_chat_id]).await?;
+    store
+        .append_message(&test_message(other_chat_id, "Hallo"))
+        .await?;
+
+    assert_eq!(
+        store.read_unread_messages(reader).await?,
+        vec![
+            UnreadMessages {
+                chat_id,
+                last_read: Some(read.event_id),
+                unread: 2,
+            },
+            UnreadMessages {
+                chat_id: quiet_chat_id,
+                last_read: None,
+                unread: 0,
+            },
+        ]
+    );
+    assert_eq!(store.read_unread_messages(UserId::random()).await?, vec![]);
+    Ok(())
+}
+
+async fn member_chats_are_summarized_with_their_latest_message(
+    store: &dyn ChatStore,
+) -> anyhow::Result<()> {
+    let user_id = UserId::random();
+    let quiet_chat = ChatMetadata::unnamed(ChatId::random(), user_id, ChatTimestamp::now());
+    store.create_chat(&quiet_chat).await?;
+    let busy_chat = ChatMetadata::unnamed(ChatId::random(), user_id, ChatTimestamp::now());
+    store.create_chat(&busy_chat).await?;
+    let mut previewed = test_message(busy_chat.chat_id, "Tee?");
+    previewed.sequence = store.append_message(&previewed).await?;
+    let deleted = test_message(busy_chat.chat_id, "Kaffee?");
+    store.append_message(&deleted).await?;
+    store
+        .delete_message(busy_chat.chat_id, deleted.event_id, &ChatTimestamp::now())
+        .await?;
+    // Chats the user isn't a member of aren't listed.
+    let other_chat_id = ChatId::random();
+    create_chats(store, &[other_chat_id]).await?;
+    store
+        .append_message(&test_message(other_chat_id, "Hallo"))
+        .await?;
+
+    let mut summaries = store.read_member_chats(user_id).await?;
+    summaries.sort_by_key(|summary| summary.last_message.is_some());
+    assert_eq!(
+        summaries,
+        vec![
+            ChatSummary {
+                last_activity: quiet_chat.created_at.clone(),
+                metadata: quiet_chat,
+                last_message: None,
+            },
+            ChatSummary {
+                metadata: busy_chat,
+                last_activity: deleted.timestamp,
+                last_message: Some(MessagePreview::of(&previewed)),
+            },
+        ]
+    );
+    assert_eq!(store.read_member_chats(UserId::random()).await?, vec![]);
+    Ok(())
+}
+
+macro_rules! store_tests {
+    ($module:ident, $open_store:expr $(, #[$attribute:meta])*) => {
+        mod $module {
+            use super::*;
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn reading_an_unknown_chat_yields_none() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::reading_an_unknown_chat_yields_none(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn an_ensured_chat_has_an_empty_history() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::an_ensured_chat_has_an_empty_history(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn appended_messages_are_read_back_in_order() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::appended_messages_are_read_back_in_order(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn a_created_chat_keeps_its_metadata() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::a_created_chat_keeps_its_metadata(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn the_creator_of_a_chat_is_its_owner() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::the_creator_of_a_chat_is_its_owner(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn appending_to_an_unknown_chat_fails() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::appending_to_an_unknown_chat_fails(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn an_invite_adds_members_until_it_is_used_up() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::an_invite_adds_members_until_it_is_used_up(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn members_redeeming_an_invite_keep_their_role() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::members_redeeming_an_invite_keep_their_role(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn an_invite_to_an_unknown_chat_fails() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::an_invite_to_an_unknown_chat_fails(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn notes_are_read_back_in_order_and_can_be_updated() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::notes_are_read_back_in_order_and_can_be_updated(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn a_note_in_an_unknown_chat_fails() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::a_note_in_an_unknown_chat_fails(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn ranges_of_the_history_are_read_next_to_the_cursor() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::ranges_of_the_history_are_read_next_to_the_cursor(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn a_range_next_to_an_unknown_cursor_fails() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::a_range_next_to_an_unknown_cursor_fails(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn messages_are_numbered_per_chat_and_read_since_a_number() -> anyhow::Result<()>
+            {
+                let store = $open_store.await?;
+                super::messages_are_numbered_per_chat_and_read_since_a_number(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn retention_policies_are_updated_and_chats_listed() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::retention_policies_are_updated_and_chats_listed(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn histories_are_pruned_up_to_the_newest_message_violating_a_limit()
+            -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::histories_are_pruned_up_to_the_newest_message_violating_a_limit(
+                    store.as_ref(),
+                )
+                .await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn only_unused_chats_are_deleted() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::only_unused_chats_are_deleted(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn messages_are_edited_with_revisions_and_deleted_as_tombstones()
+            -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::messages_are_edited_with_revisions_and_deleted_as_tombstones(store.as_ref())
+                    .await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn pruned_messages_take_their_revisions_along() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::pruned_messages_take_their_revisions_along(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn reactions_are_aggregated_per_emoji() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::reactions_are_aggregated_per_emoji(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn deleted_messages_lose_their_reactions() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::deleted_messages_lose_their_reactions(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn threads_are_read_by_their_first_message() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::threads_are_read_by_their_first_message(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn read_markers_only_move_forward() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::read_markers_only_move_forward(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn unread_messages_of_others_are_counted_per_chat() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::unread_messages_of_others_are_counted_per_chat(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn member_chats_are_summarized_with_their_latest_message() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::member_chats_are_summarized_with_their_latest_message(store.as_ref()).await
+            }
+
+            #[tokio::test]
+            $(#[$attribute])*
+            async fn rate_limit_policies_are_updated() -> anyhow::Result<()> {
+                let store = $open_store.await?;
+                super::rate_limit_policies_are_updated(store.as_ref()).await
+            }
+        }
+    };
+}
+
+struct TestStore {
+    store: Box<dyn ChatStore>,
+    // Keeps the files of the store until the test is done.
+    _directory: Option<tempfile::TempDir>,
+}
+
+impl TestStore {
+    fn as_ref(&self) -> &dyn ChatStore {
+        self.store.as_ref()
+    }
+}
+
+async fn open_memory_store() -> anyhow::Result<TestStore> {
+    Ok(TestStore {
+        store: open(&StorageSettings::Memory).await?,
+        _directory: None,
+    })
+}
+
+async fn open_postgres_store() -> anyhow::Result<TestStore> {
+    let url = std::env::var(POSTGRES_URL_ENV_VAR)
+        .with_context(|| format!("{POSTGRES_URL_ENV_VAR} has to point to a test database"))?;
+    let settings = StorageSettings::Postgres(PostgresSettings {
+        url: Secret::new(url),
+        max_connections: 2,
+    });
+    Ok(TestStore {
+        store: open(&settings).await?,
+        _directory: None,
+    })
+}
+
+async fn open_sqlite_store() -> anyhow::Result<TestStore> {
+    let directory = tempfile::tempdir()?;
+    let settings = StorageSettings::Sqlite(SqliteSettings {
+        path: directory.path().join("chats.sqlite"),
+        max_connections: 2,
+    });
+    Ok(TestStore {
+        store: open(&settings).await?,
+        _directory: Some(directory),
+    })
+}
+
+async fn open_journal_store() -> anyhow::Result<TestStore> {
+    let directory = tempfile::tempdir()?;
+    let settings = StorageSettings::Journal(JournalSettings {
+        directory: directory.path().join("journal"),
+        segment_size: 4096,
+        fsync: FsyncPolicy::Never,
+        fsync_interval_ms: 1000,
+    });
+    Ok(TestStore {
+        store: open(&settings).await?,
+        _directory: Some(directory),
+    })
+}
+
+store_tests!(memory, open_memory_store());
+store_tests!(journal, open_journal_store());
+store_tests!(
+    postgres,
+    open_postgres_store(),
+    #[ignore = "needs a postgres database, see POSTGRES_TEST_URL"]
+);
+store_tests!(sqlite, open_sqlite_store());
+
+#[tokio::test]
+#[ignore = "needs a postgres database, see POSTGRES_TEST_URL"]
+async fn postgres_histories_survive_reconnecting() -> anyhow::Result<()> {
+    let store = open_postgres_store().await?;
+    let mut message = test_message(ChatId::random(), "bleibt");
+    create_chats(store.as_ref(), &[message.chat_id]).await?;
+    message.sequence = store.as_ref().append_message(&message).await?;
+    drop(store);
+
+    let store = open_postgres_store().await?;
+    let history = read_history(store.as_ref(), message.chat_id).await?;
+    assert_eq!(history, Some(vec![message]));
+    Ok(())
+}
+
+#[tokio::test]
+async fn sqlite_histories_survive_reopening() -> anyhow::Result<()> {
+    let directory = tempfile::tempdir()?;
+    let settings = StorageSettings::Sqlite(SqliteSettings {
+        path: directory.path().join("chats.sqlite"),
+        max_connections: 2,
+    });
+    let mut message = test_message(ChatId::random(), "bleibt");
+
+    let store = open(&settings).await?;
+    create_chats(store.as_ref(), &[message.chat_id]).await?;
+    message.sequence = store.append_message(&message).await?;
+    drop(store);
+
+    let store = open(&settings).await?;
+    let history = read_history(store.as_ref(), message.chat_id).await?;
+    assert_eq!(history, Some(vec![message]));
+    Ok(())
+}
@@ -1,9 +1,16 @@
+use std::sync::Arc;
+
 use anyhow::Context;
 use futures::{FutureExt as _, future::try_join_all};
-use tokio_stream::StreamExt;
+use tokio_stream::{StreamExt, wrappers::errors::BroadcastStreamRecvError};
 
 use super::models::*;
+use super::ratelimit::TokenBucket;
 use super::*;
+use crate::{
+    chat::store::memory::InMemoryChatStore,
+    settings::{RateLimit, RateLimitSettings, RetentionSettings},
+};
 
 macro_rules! expect_no_message_available {
     ($receiver:expr, $mesg:expr) => {
@@ -16,7 +23,10 @@ macro_rules! expect_message {
         let result = $receiver.try_next().now_or_never();
         if let Some(channel_event) = result {
             match channel_event {
-                Ok(Some(message)) => message,
+                Ok(Some(ChatEvent::Message(message))) => message,
+                Ok(Some(event)) => {
+                    panic!("{}: expected a chat message but got {event:?}", $mesg)
+                }
                 Ok(None) => {
                     panic!("{}: stream ended unexpectedly", $mesg)
                 }
@@ -35,19 +45,36 @@ fn test_message(chat_id: ChatId, user_id: UserId, event_id: EventId) -> ChatMess
         event_id,
         timestamp: ChatTimestamp::epoch(),
         chat_id,
+        sequence: 0,
         user_id,
         display_name: DisplayName::new(format!("{}", user_id)),
         message: Message::new(format!("{}", user_id)),
+        in_reply_to: None,
+        thread_root: None,
+        edited_at: None,
+        deleted_at: None,
+        reactions: Vec::new(),
     }
 }
 
-#[test]
-fn fetching_the_history_of_an_unknown_chat_should_fail_with_the_correct_chat_id_in_the_error_message()
+fn in_memory_chat_server() -> ChatServer {
+    ChatServer::new(ChatSettings::default(), Box::new(InMemoryChatStore::new()))
+}
+
+#[tokio::test]
+async fn fetching_the_history_of_an_unknown_chat_should_fail_with_the_correct_chat_id_in_the_error_message()
  {
-    let sut = ChatServer::new();
+    let sut = in_memory_chat_server();
     let chat_id = ChatId::random();
 
-    let result = sut.get_chat_history(chat_id);
+    let result = sut
+        .get_chat_history(
+            chat_id,
+            UserId::random(),
+            HistoryCursor::Latest,
+            MAX_HISTORY_LIMIT,
+        )
+        .await;
     // Hopefully assert_matches is stabilized soon.
     assert!(
         {
@@ -62,42 +89,126 @@ fn fetching_the_history_of_an_unknown_chat_should_fail_with_the_correct_chat_id_
     );
 }
 
-#[test]
-fn sending_a_message_to_an_unknown_chat_should_succeed_and_create_the_chat() -> anyhow::Result<()> {
-    let sut = ChatServer::new();
+#[tokio::test]
+async fn only_members_who_may_post_can_send_messages() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
     let chat_id = ChatId::random();
-    let user_id = UserId::random();
-    let event_id = EventId::random();
-    let message = test_message(chat_id, user_id, event_id);
+    let owner = UserId::random();
 
-    sut.send_message(message)
-        .context("sending a message to an unknown chat should succeed")?;
-    sut.get_chat_history(chat_id)
-        .context("sending a message should create the chat if necessary")?;
+    let result = sut
+        .send_message(test_message(chat_id, owner, EventId::random()))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::ChatNotFound { .. })),
+        "sending to an unknown chat should fail instead of creating it: {result:?}"
+    );
+    assert_eq!(sut.store().read_chat(chat_id).await?, None);
 
+    sut.join_chat(chat_id, owner, None).await?;
+    let stranger = UserId::random();
+    let result = sut
+        .send_message(test_message(chat_id, stranger, EventId::random()))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotAMember { user_id, .. }) if user_id == stranger),
+        "a non-member should not be able to send: {result:?}"
+    );
+    let reader = UserId::random();
+    add_member(&sut, chat_id, owner, reader, Role::ReadOnly).await?;
+    let result = sut
+        .send_message(test_message(chat_id, reader, EventId::random()))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "a read-only member should not be able to send: {result:?}"
+    );
+    let member = UserId::random();
+    add_member(&sut, chat_id, owner, member, Role::Member).await?;
+    sut.send_message(test_message(chat_id, member, EventId::random()))
+        .await
+        .context("members should be able to send")?;
     Ok(())
 }
 
-#[test]
-fn joining_an_unknown_chat_should_succeed_and_create_the_chat() -> anyhow::Result<()> {
-    let sut = ChatServer::new();
+#[tokio::test]
+async fn joining_an_unknown_chat_should_succeed_and_create_the_chat() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
     let chat_id = ChatId::random();
-    let _stream = sut.join_chat(chat_id);
-    sut.get_chat_history(chat_id)
+    let user_id = UserId::random();
+    let role = sut.join_chat(chat_id, user_id, None).await?.role;
+    assert_eq!(role, Role::Owner, "the creator should own the chat");
+    sut.get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await
         .context("joining an unknown chat should create the chat if necessary")?;
     Ok(())
 }
 
-#[test]
-fn user_should_receive_messages_for_the_chat_they_joined() -> anyhow::Result<()> {
-    let sut = ChatServer::new();
+#[tokio::test]
+async fn without_implicit_creation_joining_an_unknown_chat_should_fail() -> anyhow::Result<()> {
+    let sut = ChatServer::new(
+        ChatSettings {
+            implicit_creation: false,
+            ..ChatSettings::default()
+        },
+        Box::new(InMemoryChatStore::new()),
+    );
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+
+    let result = sut.join_chat(chat_id, user_id, None).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::ChatNotFound { .. })),
+        "joining an unknown chat should fail"
+    );
+    let result = sut
+        .send_message(test_message(chat_id, user_id, EventId::random()))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::ChatNotFound { .. })),
+        "sending to an unknown chat should fail"
+    );
+
+    let chat = sut
+        .create_chat(ChatName::new("Kaffeeklatsch".to_string()), None, user_id)
+        .await?;
+    let role = sut.join_chat(chat.chat_id, user_id, None).await?.role;
+    assert_eq!(role, Role::Owner, "the creator should own the chat");
+    Ok(())
+}
+
+#[tokio::test]
+async fn joining_a_chat_of_others_should_fail() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let _owner_stream = sut.join_chat(chat_id, UserId::random(), None).await?;
+
+    let stranger = UserId::random();
+    let result = sut.join_chat(chat_id, stranger, None).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotAMember { user_id, .. }) if user_id == stranger),
+        "a non-member should not be able to join"
+    );
+    let result = sut
+        .get_chat_history(chat_id, stranger, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotAMember { .. })),
+        "a non-member should not be able to read the history"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn user_should_receive_messages_for_the_chat_they_joined() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
     let chat_id = ChatId::random();
     let user_id = UserId::random();
     let event_id = EventId::random();
     let message = test_message(chat_id, user_id, event_id);
 
-    let mut receiver = sut.join_chat(chat_id);
+    let mut receiver = sut.join_chat(chat_id, user_id, None).await?.events;
     sut.send_message(message)
+        .await
         .context("sending a message should succeed")?;
 
     let received_message = expect_message!(receiver, "receiving the sent message");
@@ -114,21 +225,45 @@ fn user_should_receive_messages_for_the_chat_they_joined() -> anyhow::Result<()>
     Ok(())
 }
 
-fn send_test_message(
+// Creates the chat with the sender as owner, unless it exists already.
+async fn send_test_message(
     sut: &ChatServer,
     chat: models::ChatId,
     user: models::UserId,
     event_id: models::EventId,
 ) -> anyhow::Result<()> {
+    sut.store()
+        .create_chat(&ChatMetadata::unnamed(chat, user, ChatTimestamp::now()))
+        .await?;
     let message = test_message(chat, user, event_id);
-    sut.send_message(message)?;
+    sut.send_message(message).await?;
+    Ok(())
+}
+
+async fn add_member(
+    sut: &ChatServer,
+    chat_id: ChatId,
+    owner: UserId,
+    member: UserId,
+    role: Role,
+) -> anyhow::Result<()> {
+    let invite = sut
+        .create_invite(
+            chat_id,
+            owner,
+            role,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, member).await?;
     Ok(())
 }
 
 #[tokio::test]
 async fn concurrently_sending_messages_to_multiple_different_chats_the_correct_chats_should_receive_the_messages_and_the_histories_should_be_correct()
 -> anyhow::Result<()> {
-    let sut = Arc::new(ChatServer::new());
+    let sut = Arc::new(in_memory_chat_server());
 
     let user1 = models::UserId::random();
     let user2 = models::UserId::random();
@@ -138,12 +273,15 @@ async fn concurrently_sending_messages_to_multiple_different_chats_the_correct_c
 
     let mut receiver_for_chat1 = {
         let sut = sut.clone();
-        sut.join_chat(chat1)
+        sut.join_chat(chat1, user1, None).await?.events
     };
     let mut receiver_for_chat2 = {
         let sut = sut.clone();
-        sut.join_chat(chat2)
+        sut.join_chat(chat2, user1, None).await?.events
     };
+    for chat in [chat1, chat2] {
+        add_member(&sut, chat, user1, user2, Role::Member).await?;
+    }
 
     let event1_chat1 = models::EventId::random();
     let event2_chat1 = models::EventId::random();
@@ -166,7 +304,7 @@ async fn concurrently_sending_messages_to_multiple_different_chats_the_correct_c
             let barrier = barrier.clone();
             tokio::spawn(async move {
                 barrier.wait().await;
-                send_test_message(&sut, chat, user, event)
+                send_test_message(&sut, chat, user, event).await
             })
         }),
     )
@@ -214,8 +352,10 @@ async fn concurrently_sending_messages_to_multiple_different_chats_the_correct_c
     );
 
     let chat1_history = sut
-        .get_chat_history(chat1)
-        .context("history of chat1 not available")?;
+        .get_chat_history(chat1, user1, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await
+        .context("history of chat1 not available")?
+        .messages;
     assert_eq!(
         chat1_history.len(),
         2,
@@ -230,8 +370,10 @@ async fn concurrently_sending_messages_to_multiple_different_chats_the_correct_c
     });
 
     let chat2_history = sut
-        .get_chat_history(chat2)
-        .context("history of chat2 not available")?;
+        .get_chat_history(chat2, user1, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await
+        .context("history of chat2 not available")?
+        .messages;
     assert_eq!(
         chat2_history.len(),
         2,
@@ -248,14 +390,15 @@ async fn concurrently_sending_messages_to_multiple_different_chats_the_correct_c
     Ok(())
 }
 
-#[test]
-fn removing_the_last_receiver_should_cleanup_the_broadcast_map() {
-    let sut = ChatServer::new();
+#[tokio::test]
+async fn removing_the_last_receiver_should_cleanup_the_broadcast_map() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
     let chat_id = models::ChatId::random();
+    let user_id = models::UserId::random();
 
-    let receiver1 = sut.join_chat(chat_id);
+    let receiver1 = sut.join_chat(chat_id, user_id, None).await?.events;
 
-    let receiver2 = sut.join_chat(chat_id);
+    let receiver2 = sut.join_chat(chat_id, user_id, None).await?.events;
     assert_eq!(
         sut.broadcasts.len(),
         1,
@@ -263,7 +406,7 @@ fn removing_the_last_receiver_should_cleanup_the_broadcast_map() {
     );
     drop(receiver2);
     // dropped receiver2, now parting the chat
-    sut.part_chat(chat_id);
+    sut.part_chat(chat_id, user_id).await;
 
     assert_eq!(
         sut.broadcasts.len(),
@@ -272,10 +415,1257 @@ fn removing_the_last_receiver_should_cleanup_the_broadcast_map() {
     );
     drop(receiver1);
     // dropped recever1, now parting the chat
-    sut.part_chat(chat_id);
+    sut.part_chat(chat_id, user_id).await;
     assert_eq!(
         sut.broadcasts.len(),
         0,
         "having now receiver any more and having called `part_chat` triggering the cleanup, there should be no broadcast map entry any more"
     );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn the_history_is_paged_backwards_and_forwards() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let event_ids: Vec<_> = (0..5).map(|_| EventId::random()).collect();
+    for event_id in &event_ids {
+        send_test_message(&sut, chat_id, user_id, *event_id).await?;
+    }
+    let page_event_ids =
+        |page: &HistoryPage| page.messages.iter().map(|m| m.event_id).collect::<Vec<_>>();
+
+    let latest = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, 2)
+        .await?;
+    assert_eq!(page_event_ids(&latest), event_ids[3..5]);
+    assert_eq!(latest.next_cursor, Some(event_ids[3]));
+
+    let older = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Before(event_ids[3]), 2)
+        .await?;
+    assert_eq!(page_event_ids(&older), event_ids[1..3]);
+    assert_eq!(older.next_cursor, Some(event_ids[1]));
+
+    let oldest = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Before(event_ids[1]), 2)
+        .await?;
+    assert_eq!(page_event_ids(&oldest), event_ids[0..1]);
+    assert_eq!(oldest.next_cursor, None, "there are no older messages");
+
+    let newer = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::After(event_ids[0]), 3)
+        .await?;
+    assert_eq!(page_event_ids(&newer), event_ids[1..4]);
+    assert_eq!(newer.next_cursor, Some(event_ids[3]));
+
+    let newest = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::After(event_ids[3]), 3)
+        .await?;
+    assert_eq!(page_event_ids(&newest), event_ids[4..5]);
+    assert_eq!(newest.next_cursor, None, "there are no newer messages");
+    Ok(())
+}
+
+#[tokio::test]
+async fn joining_a_chat_yields_its_history_followed_by_new_messages() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let earlier = EventId::random();
+    send_test_message(&sut, chat_id, user_id, earlier).await?;
+
+    let mut joined = sut.join_chat(chat_id, user_id, None).await?;
+    let history: Vec<_> = joined.history.messages.iter().map(|m| m.event_id).collect();
+    assert_eq!(history, vec![earlier]);
+    expect_no_message_available!(
+        joined.events,
+        "messages of the history should not be received again"
+    );
+
+    let later = EventId::random();
+    send_test_message(&sut, chat_id, user_id, later).await?;
+    let received = expect_message!(joined.events, "receiving the later message");
+    assert_eq!(received.event_id, later);
+    Ok(())
+}
+
+#[tokio::test]
+async fn joining_while_messages_are_sent_neither_misses_nor_repeats_a_message() -> anyhow::Result<()>
+{
+    let sut = Arc::new(ChatServer::new(
+        ChatSettings {
+            broadcast_capacity: 1024,
+            ..ChatSettings::default()
+        },
+        Box::new(InMemoryChatStore::new()),
+    ));
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let event_ids: Vec<_> = (0..200).map(|_| EventId::random()).collect();
+    // Owns the chat before anybody sends to it.
+    drop(sut.join_chat(chat_id, user_id, None).await?);
+    sut.part_chat(chat_id, user_id).await;
+
+    let sender = {
+        let sut = sut.clone();
+        let event_ids = event_ids.clone();
+        tokio::spawn(async move {
+            for event_id in event_ids {
+                send_test_message(&sut, chat_id, user_id, event_id).await?;
+                tokio::task::yield_now().await;
+            }
+            anyhow::Ok(())
+        })
+    };
+    for _ in 0..50 {
+        tokio::task::yield_now().await;
+    }
+    let mut joined = sut.join_chat(chat_id, user_id, None).await?;
+    sender.await??;
+
+    let mut received: Vec<_> = joined.history.messages.iter().map(|m| m.event_id).collect();
+    while received.last() != event_ids.last() {
+        match joined.events.try_next().await {
+            Ok(Some(ChatEvent::Message(message))) => received.push(message.event_id),
+            event => panic!("expected a chat message but got {event:?}"),
+        }
+    }
+    let skipped = event_ids.len() - received.len();
+    assert_eq!(
+        received,
+        event_ids[skipped..],
+        "history and received messages should continue each other"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn resuming_a_chat_yields_the_missed_messages() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let mut receiver = sut.join_chat(chat_id, user_id, None).await?.events;
+    let received = EventId::random();
+    send_test_message(&sut, chat_id, user_id, received).await?;
+    let last_received = expect_message!(receiver, "receiving the first message");
+    assert_eq!(last_received.sequence, 1);
+    drop(receiver);
+    sut.part_chat(chat_id, user_id).await;
+
+    let missed: Vec<_> = (0..3).map(|_| EventId::random()).collect();
+    for event_id in &missed {
+        send_test_message(&sut, chat_id, user_id, *event_id).await?;
+    }
+
+    let resumed = sut
+        .join_chat(chat_id, user_id, Some(last_received.sequence))
+        .await?;
+    let replayed: Vec<_> = resumed
+        .history
+        .messages
+        .iter()
+        .map(|m| (m.sequence, m.event_id))
+        .collect();
+    assert_eq!(
+        replayed,
+        vec![(2, missed[0]), (3, missed[1]), (4, missed[2])],
+        "exactly the missed messages should be replayed"
+    );
+    assert_eq!(resumed.history.next_cursor, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn resuming_after_missing_more_than_a_page_yields_the_latest_page() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    for _ in 0..MAX_HISTORY_LIMIT + 2 {
+        send_test_message(&sut, chat_id, user_id, EventId::random()).await?;
+    }
+
+    let resumed = sut.join_chat(chat_id, user_id, Some(1)).await?;
+    let history = resumed.history;
+    assert_eq!(history.messages.len(), MAX_HISTORY_LIMIT);
+    assert_eq!(
+        history.messages.first().map(|m| m.sequence),
+        Some(3),
+        "the page should end with the latest message"
+    );
+    assert!(
+        history.next_cursor.is_some(),
+        "the gap should be left to paging back"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn a_lagging_member_can_read_the_skipped_messages() -> anyhow::Result<()> {
+    let sut = ChatServer::new(
+        ChatSettings {
+            broadcast_capacity: 2,
+            ..ChatSettings::default()
+        },
+        Box::new(InMemoryChatStore::new()),
+    );
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let mut joined = sut.join_chat(chat_id, user_id, None).await?;
+    let event_ids: Vec<_> = (0..5).map(|_| EventId::random()).collect();
+    for event_id in &event_ids {
+        send_test_message(&sut, chat_id, user_id, *event_id).await?;
+    }
+
+    let lagged = joined.events.next().await;
+    assert!(
+        matches!(lagged, Some(Err(BroadcastStreamRecvError::Lagged(3)))),
+        "the member should have skipped three messages but got {lagged:?}"
+    );
+    let skipped = sut
+        .read_missed_messages(chat_id, joined.last_sequence)
+        .await?;
+    let skipped: Vec<_> = skipped.messages.iter().map(|m| m.event_id).collect();
+    assert_eq!(skipped, event_ids);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retention_prunes_by_the_policy_of_a_chat_or_the_defaults() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let user_id = UserId::random();
+    let (with_policy, with_defaults) = (ChatId::random(), ChatId::random());
+    for _ in 0..5 {
+        send_test_message(&sut, with_policy, user_id, EventId::random()).await?;
+        send_test_message(&sut, with_defaults, user_id, EventId::random()).await?;
+    }
+    sut.set_retention(
+        with_policy,
+        user_id,
+        RetentionPolicy {
+            max_messages: Some(1),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let settings = RetentionSettings {
+        defaults: RetentionPolicy {
+            max_messages: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let report = sut
+        .enforce_retention(&settings, &ChatTimestamp::now())
+        .await?;
+    assert_eq!(report.pruned_chats, 2);
+    assert_eq!(report.pruned.messages, 4 + 2);
+    assert_eq!(report.evicted_chats, 0);
+    let sut = &sut;
+    let kept = |chat_id| async move {
+        let history = sut
+            .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+            .await?;
+        anyhow::Ok(
+            history
+                .messages
+                .iter()
+                .map(|m| m.sequence)
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(kept(with_policy).await?, vec![5]);
+    assert_eq!(kept(with_defaults).await?, vec![3, 4, 5]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retention_evicts_old_empty_chats_nobody_is_connected_to() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let user_id = UserId::random();
+    let idle = sut
+        .create_chat(ChatName::new("leer".to_string()), None, user_id)
+        .await?;
+    let joined = sut
+        .create_chat(ChatName::new("besucht".to_string()), None, user_id)
+        .await?;
+    let with_messages = ChatId::random();
+    send_test_message(&sut, with_messages, user_id, EventId::random()).await?;
+    let receiver = sut.join_chat(joined.chat_id, user_id, None).await?.events;
+    let settings = RetentionSettings {
+        evict_empty_chats_after_secs: Some(60),
+        ..Default::default()
+    };
+    let later = ChatTimestamp::from_datetime(chrono::Utc::now() + chrono::Duration::hours(1));
+
+    let report = sut
+        .enforce_retention(&settings, &ChatTimestamp::now())
+        .await?;
+    assert_eq!(report.evicted_chats, 0, "the chats should be too young");
+    let report = sut.enforce_retention(&settings, &later).await?;
+    assert_eq!(report.evicted_chats, 1);
+    assert!(sut.store().read_chat(idle.chat_id).await?.is_none());
+    assert!(sut.store().read_chat(with_messages).await?.is_some());
+    assert!(sut.store().read_chat(joined.chat_id).await?.is_some());
+
+    drop(receiver);
+    sut.part_chat(joined.chat_id, user_id).await;
+    let report = sut.enforce_retention(&settings, &later).await?;
+    assert_eq!(report.evicted_chats, 1);
+    assert!(sut.store().read_chat(joined.chat_id).await?.is_none());
+    assert_eq!(
+        sut.broadcasts.len(),
+        0,
+        "evicting should leave no broadcast map entries behind"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn chats_may_lift_the_default_retention_limits() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let user_id = UserId::random();
+    let chat_id = ChatId::random();
+    for _ in 0..5 {
+        send_test_message(&sut, chat_id, user_id, EventId::random()).await?;
+    }
+    let retention: RetentionPolicy = serde_json::from_value(serde_json::json!({
+        "max_messages": "unlimited",
+    }))?;
+    assert_eq!(retention.max_messages, Some(RetentionPolicy::UNLIMITED));
+    assert_eq!(
+        serde_json::to_value(retention)?,
+        serde_json::json!({"max_age_secs": null, "max_messages": "unlimited", "max_bytes": null})
+    );
+    sut.set_retention(chat_id, user_id, retention).await?;
+    let settings = RetentionSettings {
+        defaults: RetentionPolicy {
+            max_messages: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let report = sut
+        .enforce_retention(&settings, &ChatTimestamp::now())
+        .await?;
+    assert_eq!(report.pruned.messages, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn retention_never_evicts_chats_emptied_by_pruning() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let user_id = UserId::random();
+    let chat_id = ChatId::random();
+    send_test_message(&sut, chat_id, user_id, EventId::random()).await?;
+    let settings = RetentionSettings {
+        defaults: RetentionPolicy {
+            max_age_secs: Some(60),
+            ..Default::default()
+        },
+        evict_empty_chats_after_secs: Some(60),
+        ..Default::default()
+    };
+    let later = ChatTimestamp::from_datetime(chrono::Utc::now() + chrono::Duration::hours(1));
+
+    let report = sut.enforce_retention(&settings, &later).await?;
+    assert_eq!(report.pruned.messages, 1);
+    assert_eq!(report.evicted_chats, 0);
+    assert!(sut.store().read_chat(chat_id).await?.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_owners_may_change_the_retention_policy() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let owner = UserId::random();
+    let chat = sut
+        .create_chat(ChatName::new("Regeln".to_string()), None, owner)
+        .await?;
+    let member = UserId::random();
+    let invite = sut
+        .create_invite(
+            chat.chat_id,
+            owner,
+            Role::Member,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, member).await?;
+    let retention = RetentionPolicy {
+        max_age_secs: Some(3600),
+        ..Default::default()
+    };
+
+    let result = sut.set_retention(chat.chat_id, member, retention).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "a member should not be able to change the retention policy: {result:?}"
+    );
+    let changed = sut.set_retention(chat.chat_id, owner, retention).await?;
+    assert_eq!(changed, ChatMetadata { retention, ..chat });
+    Ok(())
+}
+
+#[tokio::test]
+async fn edits_and_deletions_are_pushed_to_the_members() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let event_id = EventId::random();
+    send_test_message(&sut, chat_id, user_id, event_id).await?;
+    let mut receiver = sut.join_chat(chat_id, user_id, None).await?.events;
+
+    let edited = sut
+        .edit_message(
+            chat_id,
+            event_id,
+            user_id,
+            Message::new("korrigiert".to_string()),
+        )
+        .await?;
+    assert_eq!(edited.message, Message::new("korrigiert".to_string()));
+    assert!(edited.edited_at.is_some());
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::MessageEdited(edited.clone())));
+
+    let tombstone = sut.delete_message(chat_id, event_id, user_id).await?;
+    assert!(tombstone.is_deleted());
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::MessageDeleted(tombstone.clone())));
+    let history = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await?;
+    assert_eq!(history.messages, vec![tombstone]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn lagging_members_can_tell_whether_they_may_have_skipped_amendments() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let event_id = EventId::random();
+    let joined = sut.join_chat(chat_id, user_id, None).await?;
+    let mut published = joined.published;
+    let mut receiver = joined.events;
+
+    send_test_message(&sut, chat_id, user_id, event_id).await?;
+    sut.notify_typing(chat_id, user_id).await;
+    assert!(
+        !sut.amended_since(chat_id, published).await,
+        "new messages and typing can be caught up with or don't matter"
+    );
+    for _ in 0..2 {
+        receiver.try_next().await?;
+        published += 1;
+    }
+
+    sut.edit_message(
+        chat_id,
+        event_id,
+        user_id,
+        Message::new("korrigiert".to_string()),
+    )
+    .await?;
+    assert!(sut.amended_since(chat_id, published).await);
+    receiver.try_next().await?;
+    assert!(!sut.amended_since(chat_id, published + 1).await);
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_the_author_may_edit_or_delete_a_message() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let author = UserId::random();
+    let event_id = EventId::random();
+    send_test_message(&sut, chat_id, author, event_id).await?;
+    let member = UserId::random();
+    let invite = sut
+        .create_invite(
+            chat_id,
+            author,
+            Role::Member,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, member).await?;
+
+    let result = sut
+        .edit_message(chat_id, event_id, member, Message::new("fremd".to_string()))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "another member should not be able to edit the message: {result:?}"
+    );
+    let result = sut.delete_message(chat_id, event_id, member).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "another member should not be able to delete the message: {result:?}"
+    );
+    let revisions = sut.get_message_revisions(chat_id, event_id, member).await?;
+    assert_eq!(revisions, vec![], "the message should be unchanged");
+    Ok(())
+}
+
+#[tokio::test]
+async fn deleted_messages_cannot_be_edited() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let event_id = EventId::random();
+    send_test_message(&sut, chat_id, user_id, event_id).await?;
+    sut.delete_message(chat_id, event_id, user_id).await?;
+
+    let result = sut
+        .edit_message(chat_id, event_id, user_id, Message::new("doch".to_string()))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::MessageDeleted { .. })),
+        "editing a deleted message should fail: {result:?}"
+    );
+    let result = sut.delete_message(chat_id, event_id, user_id).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::MessageDeleted { .. })),
+        "deleting a message twice should fail: {result:?}"
+    );
+    let result = sut
+        .delete_message(chat_id, EventId::random(), user_id)
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::EventNotFound { .. })),
+        "deleting an unknown message should fail: {result:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn reactions_are_pushed_to_the_members_and_kept_in_the_history() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let event_id = EventId::random();
+    send_test_message(&sut, chat_id, user_id, event_id).await?;
+    let mut receiver = sut.join_chat(chat_id, user_id, None).await?.events;
+    let reaction = ReactionChange {
+        event_id,
+        user_id,
+        emoji: Emoji::new("👍".to_string()),
+    };
+
+    sut.add_reaction(chat_id, event_id, user_id, reaction.emoji.clone())
+        .await?;
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::ReactionAdded(reaction.clone())));
+    sut.add_reaction(chat_id, event_id, user_id, reaction.emoji.clone())
+        .await?;
+    expect_no_message_available!(receiver, "reacting twice should change nothing");
+    let history = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await?;
+    assert_eq!(
+        history.messages[0].reactions,
+        vec![Reaction {
+            emoji: reaction.emoji.clone(),
+            count: 1,
+            users: vec![user_id],
+        }]
+    );
+
+    sut.remove_reaction(chat_id, event_id, user_id, reaction.emoji.clone())
+        .await?;
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::ReactionRemoved(reaction)));
+    let history = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await?;
+    assert_eq!(history.messages[0].reactions, vec![]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_only_members_and_deleted_messages_get_no_reactions() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let author = UserId::random();
+    let event_id = EventId::random();
+    send_test_message(&sut, chat_id, author, event_id).await?;
+    let reader = UserId::random();
+    let invite = sut
+        .create_invite(
+            chat_id,
+            author,
+            Role::ReadOnly,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, reader).await?;
+    let thumbs_up = Emoji::new("👍".to_string());
+
+    let result = sut
+        .add_reaction(chat_id, event_id, reader, thumbs_up.clone())
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "read-only members should not be able to react: {result:?}"
+    );
+    let result = sut
+        .add_reaction(chat_id, EventId::random(), author, thumbs_up.clone())
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::EventNotFound { .. })),
+        "reacting to an unknown message should fail: {result:?}"
+    );
+    sut.delete_message(chat_id, event_id, author).await?;
+    let result = sut.add_reaction(chat_id, event_id, author, thumbs_up).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::MessageDeleted { .. })),
+        "reacting to a deleted message should fail: {result:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn replies_join_the_thread_of_the_message_they_reply_to() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    sut.join_chat(chat_id, user_id, None).await?;
+    let root = test_message(chat_id, user_id, EventId::random());
+    sut.send_message(root.clone()).await?;
+    let first = ChatMessage {
+        in_reply_to: Some(root.event_id),
+        ..test_message(chat_id, user_id, EventId::random())
+    };
+    sut.send_message(first.clone()).await?;
+    send_test_message(&sut, chat_id, user_id, EventId::random()).await?;
+    let second = ChatMessage {
+        in_reply_to: Some(first.event_id),
+        ..test_message(chat_id, user_id, EventId::random())
+    };
+    sut.send_message(second.clone()).await?;
+
+    let thread = sut.get_thread(chat_id, second.event_id, user_id).await?;
+    assert_eq!(
+        thread
+            .iter()
+            .map(|message| (message.event_id, message.thread_root))
+            .collect::<Vec<_>>(),
+        vec![
+            (root.event_id, None),
+            (first.event_id, Some(root.event_id)),
+            (second.event_id, Some(root.event_id)),
+        ],
+        "a reply to a reply should belong to the thread of the first message"
+    );
+    assert_eq!(
+        sut.get_thread(chat_id, root.event_id, user_id).await?,
+        thread
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn replies_to_messages_of_other_chats_are_rejected() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let user_id = UserId::random();
+    let elsewhere = EventId::random();
+    send_test_message(&sut, ChatId::random(), user_id, elsewhere).await?;
+    let chat_id = ChatId::random();
+    send_test_message(&sut, chat_id, user_id, EventId::random()).await?;
+
+    let result = sut
+        .send_message(ChatMessage {
+            in_reply_to: Some(elsewhere),
+            ..test_message(chat_id, user_id, EventId::random())
+        })
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::EventNotFound { .. })),
+        "a reply to a message of another chat should fail: {result:?}"
+    );
+    let history = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await?;
+    assert_eq!(
+        history.messages.len(),
+        1,
+        "the reply should not be appended"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn members_are_told_who_joins_and_leaves() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let hugo = UserId::random();
+    let erna = UserId::random();
+    let invite = {
+        let joined = sut.join_chat(chat_id, hugo, None).await?;
+        assert_eq!(joined.present, vec![hugo]);
+        drop(joined);
+        sut.part_chat(chat_id, hugo).await;
+        sut.create_invite(
+            chat_id,
+            hugo,
+            Role::Member,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?
+    };
+    sut.redeem_invite(&invite, erna).await?;
+    let mut receiver = sut.join_chat(chat_id, hugo, None).await?.events;
+
+    let first = sut.join_chat(chat_id, erna, None).await?;
+    let mut present = vec![hugo, erna];
+    present.sort();
+    assert_eq!(first.present, present);
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::MemberJoined(erna)));
+    let second = sut.join_chat(chat_id, erna, None).await?;
+    expect_no_message_available!(receiver, "a second session should not join again");
+
+    drop(first);
+    sut.part_chat(chat_id, erna).await;
+    expect_no_message_available!(receiver, "erna should still be connected");
+    assert_eq!(sut.present_members(chat_id).await, present);
+    drop(second);
+    sut.part_chat(chat_id, erna).await;
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::MemberLeft(erna)));
+    assert_eq!(sut.present_members(chat_id).await, vec![hugo]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn typing_is_pushed_to_the_members_but_not_stored() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let mut receiver = sut.join_chat(chat_id, user_id, None).await?.events;
+
+    sut.notify_typing(chat_id, user_id).await;
+    let event = receiver.try_next().await?;
+    assert_eq!(event, Some(ChatEvent::Typing(user_id)));
+    let history = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await?;
+    assert_eq!(history.messages, vec![]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn read_receipts_are_pushed_to_the_members_and_counted() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let author = UserId::random();
+    let (first, second) = (EventId::random(), EventId::random());
+    send_test_message(&sut, chat_id, author, first).await?;
+    send_test_message(&sut, chat_id, author, second).await?;
+    let reader = UserId::random();
+    let invite = sut
+        .create_invite(
+            chat_id,
+            author,
+            Role::Member,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, reader).await?;
+    let mut receiver = sut.join_chat(chat_id, author, None).await?.events;
+
+    sut.mark_read(chat_id, reader, first).await?;
+    let event = receiver.try_next().await?;
+    assert_eq!(
+        event,
+        Some(ChatEvent::ReadUpTo(ReadReceipt {
+            user_id: reader,
+            event_id: first,
+            sequence: 1,
+        }))
+    );
+    let unread = sut.get_unread_messages(reader).await?;
+    assert_eq!(
+        unread,
+        vec![UnreadMessages {
+            chat_id,
+            last_read: Some(first),
+            unread: 1,
+        }]
+    );
+
+    sut.mark_read(chat_id, reader, second).await?;
+    receiver.try_next().await?;
+    sut.mark_read(chat_id, reader, first).await?;
+    expect_no_message_available!(receiver, "the marker should not move backwards");
+    let result = sut.mark_read(chat_id, reader, EventId::random()).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::EventNotFound { .. })),
+        "marking an unknown message read should fail: {result:?}"
+    );
+    let result = sut.mark_read(chat_id, UserId::random(), second).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotAMember { .. })),
+        "strangers should have no read marker: {result:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn member_chats_are_listed_most_recently_active_first() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let user_id = UserId::random();
+    let (earlier_chat, later_chat, joined_chat) =
+        (ChatId::random(), ChatId::random(), ChatId::random());
+    let minutes_ago = |minutes| {
+        ChatTimestamp::from_datetime(chrono::Utc::now() - chrono::Duration::minutes(minutes))
+    };
+    for chat_id in [earlier_chat, later_chat] {
+        sut.store()
+            .create_chat(&ChatMetadata::unnamed(chat_id, user_id, minutes_ago(3)))
+            .await?;
+    }
+    sut.send_message(ChatMessage {
+        timestamp: minutes_ago(2),
+        message: Message::new("Tee?".to_string()),
+        ..test_message(earlier_chat, user_id, EventId::random())
+    })
+    .await?;
+    sut.send_message(ChatMessage {
+        timestamp: minutes_ago(1),
+        message: Message::new("a".repeat(MessagePreview::MAX_CHARS + 1)),
+        ..test_message(later_chat, user_id, EventId::random())
+    })
+    .await?;
+    // Joining creates the chat, its creation is its latest activity.
+    sut.join_chat(joined_chat, user_id, None).await?;
+    sut.part_chat(joined_chat, user_id).await;
+
+    let chats = sut.get_member_chats(user_id).await?;
+    assert_eq!(
+        chats
+            .iter()
+            .map(|chat| chat.metadata.chat_id)
+            .collect::<Vec<_>>(),
+        vec![joined_chat, later_chat, earlier_chat]
+    );
+    assert_eq!(chats[0].last_message, None);
+    let preview = chats[1].last_message.as_ref().context("no preview")?;
+    assert_eq!(preview.text, "a".repeat(MessagePreview::MAX_CHARS));
+    assert!(preview.truncated);
+    let preview = chats[2].last_message.as_ref().context("no preview")?;
+    assert_eq!((preview.text.as_str(), preview.truncated), ("Tee?", false));
+    assert_eq!(sut.get_member_chats(UserId::random()).await?, vec![]);
+    Ok(())
+}
+
+#[test]
+fn token_buckets_allow_bursts_and_refill_at_a_constant_rate() {
+    let start = std::time::Instant::now();
+    let second = std::time::Duration::from_secs(1);
+    let mut bucket = TokenBucket::new(3, second, start);
+
+    for _ in 0..3 {
+        assert_eq!(bucket.try_take(start), Ok(()));
+    }
+    assert_eq!(bucket.try_take(start), Err(second));
+    assert_eq!(bucket.try_take(start + second / 2), Err(second / 2));
+    assert_eq!(bucket.try_take(start + second), Ok(()));
+    assert_eq!(bucket.try_take(start + second), Err(second));
+    assert!(!bucket.is_full(start + 3 * second));
+    assert!(bucket.is_full(start + 4 * second));
+}
+
+fn rate_limited_chat_server(rate_limits: RateLimitSettings) -> ChatServer {
+    ChatServer::new(
+        ChatSettings {
+            rate_limits,
+            ..Default::default()
+        },
+        Box::new(InMemoryChatStore::new()),
+    )
+}
+
+fn assert_rate_limited(result: Result<(), ChatServerErrors>, mesg: &str) -> std::time::Duration {
+    match result {
+        Err(ChatServerErrors::RateLimited { retry_after, .. }) => retry_after,
+        result => panic!("{mesg}: expected the message to be rate limited but got {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn messages_are_limited_by_the_policy_of_a_chat_or_the_defaults() -> anyhow::Result<()> {
+    let sut = rate_limited_chat_server(RateLimitSettings {
+        defaults: RateLimitPolicy {
+            burst: Some(2),
+            per_minute: Some(1),
+            slow_mode_secs: None,
+        },
+        ..Default::default()
+    });
+    let owner = UserId::random();
+    let chat = sut
+        .create_chat(ChatName::new("Bremse".to_string()), None, owner)
+        .await?;
+    let mut connection = sut.connection_rate_limits(None);
+
+    for _ in 0..2 {
+        sut.admit_message(chat.chat_id, owner, &mut connection)
+            .await?;
+    }
+    let retry_after = assert_rate_limited(
+        sut.admit_message(chat.chat_id, owner, &mut connection)
+            .await,
+        "the default burst is used up",
+    );
+    assert!(retry_after > std::time::Duration::from_secs(50));
+    sut.admit_message(chat.chat_id, UserId::random(), &mut connection)
+        .await
+        .context("others should have buckets of their own")?;
+
+    sut.set_rate_limit(
+        chat.chat_id,
+        owner,
+        RateLimitPolicy {
+            burst: Some(100),
+            per_minute: Some(100),
+            slow_mode_secs: None,
+        },
+    )
+    .await?;
+    sut.admit_message(chat.chat_id, owner, &mut connection)
+        .await
+        .context("a changed policy should start over")?;
+
+    let result = sut
+        .admit_message(ChatId::random(), owner, &mut connection)
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::ChatNotFound { .. })),
+        "unknown chats should have no limits: {result:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn slow_mode_admits_a_single_message_per_interval() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let owner = UserId::random();
+    let chat = sut
+        .create_chat(ChatName::new("Schnecke".to_string()), None, owner)
+        .await?;
+    sut.set_rate_limit(
+        chat.chat_id,
+        owner,
+        RateLimitPolicy {
+            slow_mode_secs: Some(30),
+            ..Default::default()
+        },
+    )
+    .await?;
+    let mut connection = sut.connection_rate_limits(None);
+
+    sut.admit_message(chat.chat_id, owner, &mut connection)
+        .await?;
+    let retry_after = assert_rate_limited(
+        sut.admit_message(chat.chat_id, owner, &mut connection)
+            .await,
+        "slow mode admits one message",
+    );
+    assert!(retry_after > std::time::Duration::from_secs(29));
+    assert!(retry_after <= std::time::Duration::from_secs(30));
+    Ok(())
+}
+
+#[tokio::test]
+async fn connections_and_ip_addresses_are_limited_across_chats() -> anyhow::Result<()> {
+    let sut = rate_limited_chat_server(RateLimitSettings {
+        per_connection: RateLimit {
+            burst: 2,
+            per_minute: 1,
+        },
+        per_ip: RateLimit {
+            burst: 3,
+            per_minute: 1,
+        },
+        ..Default::default()
+    });
+    let user_id = UserId::random();
+    let mut chats = Vec::new();
+    for name in ["eins", "zwei", "drei"] {
+        let chat = sut
+            .create_chat(ChatName::new(name.to_string()), None, user_id)
+            .await?;
+        chats.push(chat.chat_id);
+    }
+    let ip = Some(std::net::IpAddr::from([192, 0, 2, 1]));
+    let mut first = sut.connection_rate_limits(ip);
+    let mut second = sut.connection_rate_limits(ip);
+
+    sut.admit_message(chats[0], user_id, &mut first).await?;
+    sut.admit_message(chats[1], user_id, &mut first).await?;
+    assert_rate_limited(
+        sut.admit_message(chats[2], user_id, &mut first).await,
+        "the connection is used up",
+    );
+    sut.admit_message(chats[2], user_id, &mut second).await?;
+    assert_rate_limited(
+        sut.admit_message(chats[2], user_id, &mut second).await,
+        "the IP address is used up",
+    );
+    let mut elsewhere = sut.connection_rate_limits(Some(std::net::IpAddr::from([192, 0, 2, 2])));
+    sut.admit_message(chats[2], user_id, &mut elsewhere)
+        .await
+        .context("other IP addresses should have buckets of their own")?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn other_requests_are_limited_per_connection_and_typing_per_member() -> anyhow::Result<()> {
+    let sut = rate_limited_chat_server(RateLimitSettings {
+        per_connection_events: RateLimit {
+            burst: 2,
+            per_minute: 1,
+        },
+        ..Default::default()
+    });
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let mut receiver = sut.join_chat(chat_id, user_id, None).await?.events;
+    let mut connection = sut.connection_rate_limits(None);
+
+    for _ in 0..2 {
+        sut.admit_event(chat_id, user_id, &mut connection)?;
+    }
+    assert_rate_limited(
+        sut.admit_event(chat_id, user_id, &mut connection),
+        "the events of the connection are used up",
+    );
+    sut.admit_message(chat_id, user_id, &mut connection)
+        .await
+        .context("messages should have buckets of their own")?;
+
+    sut.notify_typing(chat_id, user_id).await;
+    sut.notify_typing(chat_id, user_id).await;
+    let other = UserId::random();
+    sut.notify_typing(chat_id, other).await;
+    assert_eq!(receiver.try_next().await?, Some(ChatEvent::Typing(user_id)));
+    assert_eq!(
+        receiver.try_next().await?,
+        Some(ChatEvent::Typing(other)),
+        "repeated typing indicators should be dropped"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn idle_rate_limits_are_swept() -> anyhow::Result<()> {
+    // Full again after 100ms.
+    let limit = RateLimit {
+        burst: 1,
+        per_minute: 600,
+    };
+    let sut = rate_limited_chat_server(RateLimitSettings {
+        defaults: RateLimitPolicy {
+            burst: Some(limit.burst),
+            per_minute: Some(limit.per_minute),
+            slow_mode_secs: None,
+        },
+        per_ip: limit,
+        ..Default::default()
+    });
+    let user_id = UserId::random();
+    let chat = sut
+        .create_chat(ChatName::new("Besen".to_string()), None, user_id)
+        .await?;
+    let mut connection = sut.connection_rate_limits(Some(std::net::IpAddr::from([192, 0, 2, 1])));
+    sut.admit_message(chat.chat_id, user_id, &mut connection)
+        .await?;
+    // Closed right after sending, so the buckets aren't full yet.
+    sut.release_rate_limits(chat.chat_id, user_id, connection);
+    assert_eq!(sut.sweep_rate_limits(), 0);
+
+    tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+    assert_eq!(
+        sut.sweep_rate_limits(),
+        2,
+        "the member and the IP address should be dropped"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_owners_may_change_the_rate_limit_policy() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let owner = UserId::random();
+    let chat = sut
+        .create_chat(ChatName::new("Regeln".to_string()), None, owner)
+        .await?;
+    let member = UserId::random();
+    let invite = sut
+        .create_invite(
+            chat.chat_id,
+            owner,
+            Role::Member,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, member).await?;
+    let rate_limit = RateLimitPolicy {
+        slow_mode_secs: Some(60),
+        ..Default::default()
+    };
+
+    let result = sut.set_rate_limit(chat.chat_id, member, rate_limit).await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "a member should not be able to change the rate limits: {result:?}"
+    );
+    let changed = sut.set_rate_limit(chat.chat_id, owner, rate_limit).await?;
+    assert_eq!(changed, ChatMetadata { rate_limit, ..chat });
+    Ok(())
+}
+
+#[tokio::test]
+async fn sent_messages_are_normalized_and_checked_like_edits() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    sut.join_chat(chat_id, user_id, None).await?;
+
+    let result = sut
+        .send_message(ChatMessage {
+            message: Message::new(" \n ".to_string()),
+            ..test_message(chat_id, user_id, EventId::random())
+        })
+        .await;
+    assert!(
+        matches!(
+            result,
+            Err(ChatServerErrors::InvalidText {
+                invalid_text: InvalidText::Empty {
+                    field: TextField::Message
+                },
+                ..
+            })
+        ),
+        "an empty message should be rejected: {result:?}"
+    );
+    let result = sut
+        .send_message(ChatMessage {
+            display_name: DisplayName::new("Eve\u{202e}".to_string()),
+            ..test_message(chat_id, user_id, EventId::random())
+        })
+        .await;
+    assert!(
+        matches!(
+            result,
+            Err(ChatServerErrors::InvalidText {
+                invalid_text: InvalidText::ForbiddenCharacter {
+                    field: TextField::DisplayName,
+                    ..
+                },
+                ..
+            })
+        ),
+        "a display name with a bidi override should be rejected: {result:?}"
+    );
+
+    sut.send_message(ChatMessage {
+        display_name: DisplayName::new(" Rene\u{301} ".to_string()),
+        message: Message::new(" Hallo\n".to_string()),
+        ..test_message(chat_id, user_id, EventId::random())
+    })
+    .await?;
+    let history = sut
+        .get_chat_history(chat_id, user_id, HistoryCursor::Latest, MAX_HISTORY_LIMIT)
+        .await?;
+    let sent: Vec<_> = history
+        .messages
+        .iter()
+        .map(|message| (message.display_name.as_str(), message.message.as_str()))
+        .collect();
+    assert_eq!(sent, vec![("Ren\u{e9}", "Hallo")]);
+    Ok(())
+}
+
+#[test]
+fn texts_of_users_are_normalized_and_trimmed() -> anyhow::Result<()> {
+    let display_name = DisplayName::try_new("  Rene\u{301} ".to_string())?;
+    assert_eq!(display_name.as_str(), "Ren\u{e9}");
+    let message = Message::try_new("\n Zeile 1\n\tZeile 2 \n".to_string())?;
+    assert_eq!(message.as_str(), "Zeile 1\n\tZeile 2");
+    Ok(())
+}
+
+#[test]
+fn empty_and_too_long_texts_are_rejected() {
+    assert_eq!(
+        DisplayName::try_new(" \t ".to_string()),
+        Err(InvalidText::Empty {
+            field: TextField::DisplayName
+        })
+    );
+    assert_eq!(
+        Message::try_new(String::new()),
+        Err(InvalidText::Empty {
+            field: TextField::Message
+        })
+    );
+    let longest = "ä".repeat(DisplayName::MAX_CHARS);
+    assert!(DisplayName::try_new(longest.clone()).is_ok());
+    assert_eq!(
+        DisplayName::try_new(longest + "ä"),
+        Err(InvalidText::TooLong {
+            field: TextField::DisplayName,
+            max_chars: DisplayName::MAX_CHARS
+        })
+    );
+    // Counted after composing, a + combining diaeresis is a single character.
+    let decomposed = "a\u{308}".repeat(Message::MAX_CHARS);
+    assert!(Message::try_new(decomposed).is_ok());
+}
+
+#[test]
+fn control_and_bidi_override_characters_are_rejected() {
+    for (text, character) in [
+        ("Hugo\nHugo", '\n'),
+        ("Hu\u{7}go", '\u{7}'),
+        ("Hugo\u{202E}ogaH", '\u{202E}'),
+        ("\u{2067}Hugo", '\u{2067}'),
+    ] {
+        assert_eq!(
+            DisplayName::try_new(text.to_string()),
+            Err(InvalidText::ForbiddenCharacter {
+                field: TextField::DisplayName,
+                character
+            }),
+            "{text:?}"
+        );
+    }
+    assert_eq!(
+        Message::try_new("txt.exe\u{202E}fdp".to_string()),
+        Err(InvalidText::ForbiddenCharacter {
+            field: TextField::Message,
+            character: '\u{202E}'
+        })
+    );
+    assert_eq!(
+        Message::try_new("Hallo\u{0}".to_string())
+            .unwrap_err()
+            .to_string(),
+        "the message must not contain the character U+0000"
+    );
 }
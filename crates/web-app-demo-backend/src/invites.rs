@@ -0,0 +1,152 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::{chat::models::Invite, settings::InviteSettings};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Error)]
+pub enum InviteErrors {
+    #[error("malformed invite token")]
+    Malformed,
+
+    #[error("invalid signature of invite token")]
+    InvalidSignature,
+
+    #[error("invite expired at {0}")]
+    Expired(chrono::DateTime<Utc>),
+}
+
+/// Signs invites into tokens and verifies them. A token is the base64url
+/// encoded JSON of the invite and its HMAC-SHA256 signature, separated by a
+/// dot.
+pub struct InviteSigner {
+    key: Vec<u8>,
+}
+
+impl InviteSigner {
+    pub fn from_settings(settings: &InviteSettings) -> Self {
+        match &settings.secret {
+            Some(secret) => Self::new(secret.expose().as_bytes().to_vec()),
+            None => {
+                tracing::warn!(
+                    "no invites.secret configured, invites are only valid until the next restart"
+                );
+                // Version 4 UUIDs come from the operating system's random
+                // number generator, two of them make a 244 bit key.
+                let key = [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+                    .iter()
+                    .flat_map(|uuid| uuid.into_bytes())
+                    .collect();
+                Self::new(key)
+            }
+        }
+    }
+
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+
+    pub fn sign(&self, invite: &Invite) -> String {
+        let payload = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(invite).expect("an invite always serializes to json"));
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Invite, InviteErrors> {
+        let (payload, signature) = token.split_once('.').ok_or(InviteErrors::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| InviteErrors::Malformed)?;
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        // Compares in constant time, so the signature can't be guessed
+        // byte by byte.
+        mac.verify_slice(&signature)
+            .map_err(|_| InviteErrors::InvalidSignature)?;
+
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| InviteErrors::Malformed)?;
+        let invite: Invite =
+            serde_json::from_slice(&payload).map_err(|_| InviteErrors::Malformed)?;
+        if invite.expires_at <= Utc::now() {
+            return Err(InviteErrors::Expired(invite.expires_at));
+        }
+        Ok(invite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::chat::models::{ChatId, InviteId, Role};
+
+    fn test_invite(expires_in: Duration) -> Invite {
+        Invite {
+            invite_id: InviteId::random(),
+            chat_id: ChatId::random(),
+            role: Role::Member,
+            max_uses: 3,
+            expires_at: Utc::now() + expires_in,
+        }
+    }
+
+    fn test_signer() -> InviteSigner {
+        InviteSigner::new(b"web-app-demo-test-invite-secret-0123456789".to_vec())
+    }
+
+    #[test]
+    fn a_signed_invite_is_verified() -> anyhow::Result<()> {
+        let invite = test_invite(Duration::hours(1));
+        let signer = test_signer();
+        assert_eq!(signer.verify(&signer.sign(&invite))?, invite);
+        Ok(())
+    }
+
+    #[test]
+    fn an_expired_invite_is_rejected() {
+        let signer = test_signer();
+        let token = signer.sign(&test_invite(Duration::seconds(-1)));
+        assert!(matches!(
+            signer.verify(&token),
+            Err(InviteErrors::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn a_tampered_invite_is_rejected() {
+        let signer = test_signer();
+        let mut invite = test_invite(Duration::hours(1));
+        let token = signer.sign(&invite);
+        let (_, signature) = token.split_once('.').unwrap();
+        invite.role = Role::Owner;
+        let tampered_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&invite).unwrap());
+        assert!(matches!(
+            signer.verify(&format!("{tampered_payload}.{signature}")),
+            Err(InviteErrors::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn an_invite_signed_with_another_key_is_rejected() {
+        let token =
+            InviteSigner::new(b"some-other-key".to_vec()).sign(&test_invite(Duration::hours(1)));
+        assert!(matches!(
+            test_signer().verify(&token),
+            Err(InviteErrors::InvalidSignature)
+        ));
+    }
+}
@@ -1,24 +1,49 @@
-use actix_cors::Cors;
 use actix_web::{HttpServer, web};
-use chat::ChatServer;
+use auth::Authenticator;
+use chat::{ChatServer, store};
+use invites::InviteSigner;
+use settings::Settings;
 
+mod auth;
 mod chat;
 mod infrastructure;
+mod invites;
+mod notes;
+mod problems;
 mod services;
+mod settings;
 pub(crate) mod util;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     infrastructure::setup_tracing_subscriber()?;
 
-    let chat_server = ChatServer::new();
+    let settings = Settings::load()?;
+    tracing::info!(?settings, "settings loaded");
+
+    let chat_store = store::open(&settings.storage).await?;
+    let chat_server = ChatServer::new(settings.chat.clone(), chat_store);
     let app_state = web::Data::new(chat_server);
+    tokio::spawn(chat::retention::run_retention(
+        app_state.clone().into_inner(),
+        settings.retention.clone(),
+    ));
+    let authenticator = web::Data::new(Authenticator::from_settings(settings.auth.clone()).await?);
+    let invite_signer = web::Data::new(InviteSigner::from_settings(&settings.invites));
+    let bind_address = (settings.server.host.clone(), settings.server.port);
+    let settings = web::Data::new(settings);
 
     HttpServer::new(move || {
-        let cors = Cors::permissive();
-        services::setup_app(app_state.clone()).wrap(cors)
+        let cors = services::setup_cors(&settings.server.cors);
+        services::setup_app(
+            app_state.clone(),
+            settings.clone(),
+            authenticator.clone(),
+            invite_signer.clone(),
+        )
+        .wrap(cors)
     })
-    .bind(("127.0.0.1", 8080))?
+    .bind(bind_address)?
     .run()
     .await?;
     Ok(())
@@ -0,0 +1,96 @@
+// Notes live next to the messages of a chat and are stored with them, so
+// the notes are just another part of the `ChatServer` API.
+
+use crate::chat::{
+    ChatServer, ChatServerErrors,
+    models::{ChatEvent, ChatId, ChatTimestamp, UserId},
+};
+
+pub mod models;
+
+use models::{Note, NoteBody, NoteId};
+
+impl ChatServer {
+    /// Takes a note in the chat and pushes it to the members connected to
+    /// the chat. Read-only members can't take notes.
+    pub async fn create_note(
+        &self,
+        chat_id: ChatId,
+        author: UserId,
+        body: NoteBody,
+    ) -> Result<Note, ChatServerErrors> {
+        let role = self.authorize(chat_id, author).await?;
+        if !role.can_post() {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                author,
+                format!("{role} members can't take notes"),
+            ));
+        }
+        let now = ChatTimestamp::now();
+        let note = Note {
+            note_id: NoteId::random(),
+            chat_id,
+            author,
+            body,
+            created_at: now.clone(),
+            updated_at: now,
+        };
+        self.store().create_note(&note).await?;
+        self.broadcast_event(chat_id, ChatEvent::NoteCreated(note.clone()))
+            .await;
+        Ok(note)
+    }
+
+    /// Reads the notes of a chat the user is a member of, in the order they
+    /// were taken.
+    pub async fn get_notes(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<Vec<Note>, ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        self.store()
+            .read_notes(chat_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::chat_not_found(chat_id))
+    }
+
+    /// Replaces the body of a note taken by the user and pushes the edited
+    /// note to the members connected to the chat.
+    pub async fn edit_note(
+        &self,
+        chat_id: ChatId,
+        note_id: NoteId,
+        user_id: UserId,
+        body: NoteBody,
+    ) -> Result<Note, ChatServerErrors> {
+        self.authorize(chat_id, user_id).await?;
+        let note = self
+            .store()
+            .read_note(chat_id, note_id)
+            .await?
+            .ok_or_else(|| ChatServerErrors::note_not_found(chat_id, note_id))?;
+        if note.author != user_id {
+            return Err(ChatServerErrors::not_permitted(
+                chat_id,
+                user_id,
+                "only the author may edit a note".to_string(),
+            ));
+        }
+        let note = Note {
+            body,
+            updated_at: ChatTimestamp::now(),
+            ..note
+        };
+        if !self.store().update_note(&note).await? {
+            return Err(ChatServerErrors::note_not_found(chat_id, note_id));
+        }
+        self.broadcast_event(chat_id, ChatEvent::NoteUpdated(note.clone()))
+            .await;
+        Ok(note)
+    }
+}
+
+#[cfg(test)]
+mod tests;
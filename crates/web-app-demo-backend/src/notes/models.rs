@@ -0,0 +1,60 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat::models::{ChatId, ChatTimestamp, UserId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NoteId(uuid::Uuid);
+
+impl Display for NoteId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(dead_code)]
+impl NoteId {
+    pub fn random() -> Self {
+        Self(uuid::Uuid::new_v4())
+    }
+
+    pub fn from_uuid(uuid: uuid::Uuid) -> Self {
+        Self(uuid)
+    }
+
+    pub fn as_uuid(&self) -> uuid::Uuid {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NoteBody(String);
+
+impl Display for NoteBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[allow(dead_code)]
+impl NoteBody {
+    pub fn new(body: String) -> Self {
+        Self(body)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A note taken in a chat. Only its author may edit it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    pub note_id: NoteId,
+    pub chat_id: ChatId,
+    pub author: UserId,
+    pub body: NoteBody,
+    pub created_at: ChatTimestamp,
+    pub updated_at: ChatTimestamp,
+}
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use futures::FutureExt as _;
+use tokio_stream::StreamExt;
+
+use super::*;
+use crate::{
+    chat::{models::Role, store::memory::InMemoryChatStore},
+    settings::ChatSettings,
+};
+
+fn in_memory_chat_server() -> ChatServer {
+    ChatServer::new(ChatSettings::default(), Box::new(InMemoryChatStore::new()))
+}
+
+fn body(text: &str) -> NoteBody {
+    NoteBody::new(text.to_string())
+}
+
+#[tokio::test]
+async fn notes_are_listed_in_the_order_they_were_taken() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let _stream = sut.join_chat(chat_id, user_id, None).await?;
+
+    let first = sut.create_note(chat_id, user_id, body("Milch")).await?;
+    let second = sut.create_note(chat_id, user_id, body("Eier")).await?;
+
+    let notes = sut.get_notes(chat_id, user_id).await?;
+    assert_eq!(notes, vec![first, second]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn created_and_edited_notes_are_pushed_to_the_chat() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let mut stream = sut.join_chat(chat_id, user_id, None).await?.events;
+
+    let note = sut.create_note(chat_id, user_id, body("Milch")).await?;
+    let event = stream
+        .try_next()
+        .now_or_never()
+        .context("the created note should have been pushed")??;
+    assert_eq!(event, Some(ChatEvent::NoteCreated(note.clone())));
+
+    let edited = sut
+        .edit_note(chat_id, note.note_id, user_id, body("Hafermilch"))
+        .await?;
+    assert_eq!(edited.body, body("Hafermilch"));
+    assert_eq!(edited.created_at, note.created_at);
+    let event = stream
+        .try_next()
+        .now_or_never()
+        .context("the edited note should have been pushed")??;
+    assert_eq!(event, Some(ChatEvent::NoteUpdated(edited)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_the_author_may_edit_a_note() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let owner = UserId::random();
+    let _stream = sut.join_chat(chat_id, owner, None).await?;
+    let member = UserId::random();
+    let invite = sut
+        .create_invite(
+            chat_id,
+            owner,
+            Role::Member,
+            1,
+            chrono::Utc::now() + chrono::Duration::hours(1),
+        )
+        .await?;
+    sut.redeem_invite(&invite, member).await?;
+    let note = sut.create_note(chat_id, owner, body("Milch")).await?;
+
+    let result = sut
+        .edit_note(chat_id, note.note_id, member, body("Bier"))
+        .await;
+    assert!(
+        matches!(result, Err(ChatServerErrors::NotPermitted { .. })),
+        "another member should not be able to edit the note: {result:?}"
+    );
+    assert_eq!(sut.get_notes(chat_id, owner).await?, vec![note]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn editing_an_unknown_note_fails() -> anyhow::Result<()> {
+    let sut = in_memory_chat_server();
+    let chat_id = ChatId::random();
+    let user_id = UserId::random();
+    let _stream = sut.join_chat(chat_id, user_id, None).await?;
+
+    let result = sut
+        .edit_note(chat_id, NoteId::random(), user_id, body("Milch"))
+        .await;
+    assert!(matches!(result, Err(ChatServerErrors::NoteNotFound { .. })));
+    Ok(())
+}
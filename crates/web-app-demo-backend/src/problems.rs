@@ -0,0 +1,262 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    chat::{
+        ChatServerErrors,
+        models::{ChatId, EventId, InvalidText},
+    },
+    notes::models::NoteId,
+};
+
+/// Stable codes of the errors reported to clients, so they don't have to
+/// parse the messages. Over HTTP they are part of the problem details, over
+/// websockets of `Outgoing::Error`. Codes are only ever added, never renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The server failed, retrying may help. Mention the correlation id when
+    /// reporting it.
+    InternalError,
+    /// No or no valid access token was sent.
+    Unauthenticated,
+    NotAMember,
+    /// The role of the user doesn't permit it.
+    NotPermitted,
+    ChatNotFound,
+    NoteNotFound,
+    MessageNotFound,
+    MessageDeleted,
+    /// The invite expired or is used up.
+    InviteUnusable,
+    /// The request was understood, but a value isn't acceptable.
+    InvalidInput,
+    /// A display name or message entered by the user was rejected, see
+    /// `invalid_text` for the reason.
+    InvalidText,
+    /// The request couldn't be parsed.
+    MalformedRequest,
+    /// Sent too fast, see `retry_after_ms` for when to retry.
+    RateLimited,
+}
+
+impl ErrorCode {
+    /// Summary of the code, which doesn't change from occurrence to
+    /// occurrence.
+    pub fn title(self) -> &'static str {
+        match self {
+            ErrorCode::InternalError => "Internal server error",
+            ErrorCode::Unauthenticated => "Not authenticated",
+            ErrorCode::NotAMember => "Not a member of the chat",
+            ErrorCode::NotPermitted => "Not permitted",
+            ErrorCode::ChatNotFound => "Chat not found",
+            ErrorCode::NoteNotFound => "Note not found",
+            ErrorCode::MessageNotFound => "Message not found",
+            ErrorCode::MessageDeleted => "Message deleted",
+            ErrorCode::InviteUnusable => "Invite unusable",
+            ErrorCode::InvalidInput => "Invalid input",
+            ErrorCode::InvalidText => "Invalid text",
+            ErrorCode::MalformedRequest => "Malformed request",
+            ErrorCode::RateLimited => "Rate limited",
+        }
+    }
+
+    /// The URI identifying the problem type, see RFC 7807.
+    pub fn problem_type(self) -> String {
+        let code = serde_json::to_value(self).expect("codes should serialize");
+        format!(
+            "urn:web-app-demo:problem:{}",
+            code.as_str().expect("codes should serialize to strings")
+        )
+    }
+}
+
+/// What an error refers to, as far as it is known. Clients may rely on the
+/// fields the code of the error names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<ChatId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<EventId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<NoteId>,
+    /// Milliseconds to wait before sending again, if the request was
+    /// rejected by a rate limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+    /// Why a text entered by the user was rejected, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invalid_text: Option<InvalidText>,
+}
+
+impl From<&ChatServerErrors> for ErrorCode {
+    fn from(err: &ChatServerErrors) -> Self {
+        match err {
+            ChatServerErrors::LockPoisoned { .. } | ChatServerErrors::StorageFailure { .. } => {
+                ErrorCode::InternalError
+            }
+            ChatServerErrors::ChatNotFound { .. } => ErrorCode::ChatNotFound,
+            ChatServerErrors::NotAMember { .. } => ErrorCode::NotAMember,
+            ChatServerErrors::NotPermitted { .. } => ErrorCode::NotPermitted,
+            ChatServerErrors::NoteNotFound { .. } => ErrorCode::NoteNotFound,
+            ChatServerErrors::EventNotFound { .. } => ErrorCode::MessageNotFound,
+            ChatServerErrors::MessageDeleted { .. } => ErrorCode::MessageDeleted,
+            ChatServerErrors::InvalidText { .. } => ErrorCode::InvalidText,
+            ChatServerErrors::RateLimited { .. } => ErrorCode::RateLimited,
+        }
+    }
+}
+
+impl From<&ChatServerErrors> for ErrorDetails {
+    fn from(err: &ChatServerErrors) -> Self {
+        match *err {
+            ChatServerErrors::LockPoisoned { .. } | ChatServerErrors::StorageFailure { .. } => {
+                ErrorDetails::default()
+            }
+            ChatServerErrors::ChatNotFound { chat_id }
+            | ChatServerErrors::NotAMember { chat_id, .. }
+            | ChatServerErrors::NotPermitted { chat_id, .. } => ErrorDetails::for_chat(chat_id),
+            ChatServerErrors::NoteNotFound { chat_id, note_id } => ErrorDetails {
+                note_id: Some(note_id),
+                ..ErrorDetails::for_chat(chat_id)
+            },
+            ChatServerErrors::EventNotFound { chat_id, event_id }
+            | ChatServerErrors::MessageDeleted { chat_id, event_id } => ErrorDetails {
+                event_id: Some(event_id),
+                ..ErrorDetails::for_chat(chat_id)
+            },
+            ChatServerErrors::InvalidText {
+                chat_id,
+                ref invalid_text,
+            } => ErrorDetails {
+                invalid_text: Some(invalid_text.clone()),
+                ..ErrorDetails::for_chat(chat_id)
+            },
+            ChatServerErrors::RateLimited {
+                chat_id,
+                retry_after,
+                ..
+            } => ErrorDetails {
+                chat_id: Some(chat_id),
+                ..ErrorDetails::for_retry(retry_after)
+            },
+        }
+    }
+}
+
+impl ErrorDetails {
+    pub fn for_chat(chat_id: ChatId) -> Self {
+        Self {
+            chat_id: Some(chat_id),
+            ..Default::default()
+        }
+    }
+
+    pub fn for_retry(retry_after: Duration) -> Self {
+        Self {
+            retry_after_ms: Some(u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Problem details of a failed HTTP request, see RFC 7807. The members
+/// beyond the standard ones are the code, the correlation id and the
+/// details of the error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: ErrorCode,
+    /// Identifies the occurrence in the logs of the server.
+    pub correlation_id: Uuid,
+    #[serde(flatten)]
+    pub details: ErrorDetails,
+}
+
+impl Problem {
+    pub const CONTENT_TYPE: &str = "application/problem+json";
+
+    pub fn new(code: ErrorCode, status: u16, detail: String, details: ErrorDetails) -> Self {
+        Self {
+            problem_type: code.problem_type(),
+            title: code.title().to_string(),
+            status,
+            detail,
+            code,
+            correlation_id: Uuid::new_v4(),
+            details,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::models::{TextField, UserId};
+
+    #[test]
+    fn errors_of_the_chat_server_are_mapped_to_codes_and_details() {
+        let chat_id = ChatId::random();
+        let event_id = EventId::random();
+        let err = ChatServerErrors::message_deleted(chat_id, event_id);
+        assert_eq!(ErrorCode::from(&err), ErrorCode::MessageDeleted);
+        assert_eq!(
+            ErrorDetails::from(&err),
+            ErrorDetails {
+                chat_id: Some(chat_id),
+                event_id: Some(event_id),
+                ..Default::default()
+            }
+        );
+
+        let err = ChatServerErrors::rate_limited(
+            chat_id,
+            UserId::random(),
+            Duration::from_micros(1_500_500),
+        );
+        assert_eq!(ErrorCode::from(&err), ErrorCode::RateLimited);
+        assert_eq!(ErrorDetails::from(&err).retry_after_ms, Some(1500));
+
+        let err = ChatServerErrors::lock_poisened("kaputt".to_string());
+        assert_eq!(ErrorCode::from(&err), ErrorCode::InternalError);
+        assert_eq!(ErrorDetails::from(&err), ErrorDetails::default());
+    }
+
+    #[test]
+    fn problems_are_serialized_with_their_details_as_members() -> anyhow::Result<()> {
+        let problem = Problem::new(
+            ErrorCode::InvalidText,
+            400,
+            "the message must not be empty".to_string(),
+            ErrorDetails {
+                invalid_text: Some(InvalidText::Empty {
+                    field: TextField::Message,
+                }),
+                ..Default::default()
+            },
+        );
+
+        let json = serde_json::to_value(&problem)?;
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": "urn:web-app-demo:problem:invalid_text",
+                "title": "Invalid text",
+                "status": 400,
+                "detail": "the message must not be empty",
+                "code": "invalid_text",
+                "correlation_id": problem.correlation_id,
+                "invalid_text": {"reason": "empty", "field": "message"},
+            })
+        );
+        assert_eq!(serde_json::from_value::<Problem>(json)?, problem);
+        Ok(())
+    }
+}
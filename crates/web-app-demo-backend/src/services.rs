@@ -1,24 +1,50 @@
-use std::{ops::ControlFlow, pin::pin};
+use std::{
+    net::{IpAddr, SocketAddr},
+    ops::ControlFlow,
+    pin::pin,
+    time::Duration,
+};
 
+use actix_cors::Cors;
 use actix_web::{
-    App, HttpRequest, HttpResponse, Responder,
-    dev::ServiceFactory,
+    App, FromRequest, HttpRequest, HttpResponse, Responder, delete,
+    dev::{Payload, ServiceFactory},
     error, get,
-    http::{StatusCode, header::ContentType},
-    web::{self, Bytes, PathConfig},
+    http::{
+        StatusCode,
+        header::{self, HeaderValue},
+    },
+    post, put,
+    web::{self, Bytes, JsonConfig, PathConfig, QueryConfig},
+};
+use actix_ws::{
+    AggregatedMessage, CloseCode, CloseReason, Closed, MessageStream, ProtocolError, Session,
 };
-use actix_ws::{AggregatedMessage, CloseReason, Closed, MessageStream, ProtocolError, Session};
-use futures::StreamExt;
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, future::LocalBoxFuture};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio_stream::wrappers::BroadcastStream;
+use tokio::time::Instant;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
 use tracing::instrument;
 use tracing_actix_web::TracingLogger;
 use uuid::Uuid;
 
-use crate::chat::{
-    ChatServer, ChatServerErrors,
-    models::{ChatId, ChatMessage, ChatTimestamp, DisplayName, EventId, Message, UserId},
+use crate::{
+    auth::{AuthenticatedUser, Authenticator},
+    chat::{
+        ChatServer, ChatServerErrors, DEFAULT_HISTORY_LIMIT, JoinedChat,
+        models::{
+            ChatEvent, ChatId, ChatMessage, ChatName, ChatTimestamp, ChatTopic, DisplayName, Emoji,
+            EventId, HistoryCursor, HistoryPage, InvalidText, Invite, InviteRedemption, Message,
+            RateLimitPolicy, ReactionChange, ReadReceipt, RetentionPolicy, Role, UserId,
+        },
+        ratelimit::ConnectionRateLimits,
+    },
+    invites::{InviteErrors, InviteSigner},
+    notes::models::{Note, NoteBody, NoteId},
+    problems::{ErrorCode, ErrorDetails, Problem},
+    settings::{CorsSettings, Settings, WebSocketSettings},
 };
 
 #[derive(Debug, Error)]
@@ -28,19 +54,145 @@ enum EndpointErrors {
 
     #[error("Chat Not Found {0}")]
     ChatNotFound(ChatId),
+
+    #[error("Note Not Found {0}")]
+    NoteNotFound(NoteId),
+
+    #[error("Message Not Found {0}")]
+    MessageNotFound(EventId),
+
+    #[error("Unauthorized")]
+    Unauthenticated,
+
+    #[error("Forbidden, not a member of chat {0}")]
+    Forbidden(ChatId),
+
+    #[error("Forbidden, {0}")]
+    NotPermitted(String),
+
+    #[error("Gone, message {0} was deleted")]
+    MessageDeleted(EventId),
+
+    #[error("Gone, {0}")]
+    InviteUnusable(String),
+
+    #[error("Bad Request: {0}")]
+    InvalidInput(String),
+
+    #[error("Bad Request: {0}")]
+    MalformedRequest(String),
+
+    #[error("Bad Request: {0}")]
+    InvalidText(InvalidText),
+
+    #[error("Too Many Requests, retry in {}s", retry_after_secs(.0))]
+    TooManyRequests(Duration),
+}
+
+// Rounded up, so clients retrying on time aren't too early.
+fn retry_after_secs(retry_after: &Duration) -> u64 {
+    retry_after
+        .as_millis()
+        .div_ceil(1000)
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+impl EndpointErrors {
+    fn code(&self) -> ErrorCode {
+        match self {
+            EndpointErrors::InternalServerError => ErrorCode::InternalError,
+            EndpointErrors::ChatNotFound(_) => ErrorCode::ChatNotFound,
+            EndpointErrors::NoteNotFound(_) => ErrorCode::NoteNotFound,
+            EndpointErrors::MessageNotFound(_) => ErrorCode::MessageNotFound,
+            EndpointErrors::Unauthenticated => ErrorCode::Unauthenticated,
+            EndpointErrors::Forbidden(_) => ErrorCode::NotAMember,
+            EndpointErrors::NotPermitted(_) => ErrorCode::NotPermitted,
+            EndpointErrors::MessageDeleted(_) => ErrorCode::MessageDeleted,
+            EndpointErrors::InviteUnusable(_) => ErrorCode::InviteUnusable,
+            EndpointErrors::InvalidInput(_) => ErrorCode::InvalidInput,
+            EndpointErrors::MalformedRequest(_) => ErrorCode::MalformedRequest,
+            EndpointErrors::InvalidText(_) => ErrorCode::InvalidText,
+            EndpointErrors::TooManyRequests(_) => ErrorCode::RateLimited,
+        }
+    }
+
+    fn details(&self) -> ErrorDetails {
+        if let EndpointErrors::InvalidText(invalid_text) = self {
+            return ErrorDetails {
+                invalid_text: Some(invalid_text.clone()),
+                ..Default::default()
+            };
+        }
+        match *self {
+            EndpointErrors::ChatNotFound(chat_id) | EndpointErrors::Forbidden(chat_id) => {
+                ErrorDetails::for_chat(chat_id)
+            }
+            EndpointErrors::NoteNotFound(note_id) => ErrorDetails {
+                note_id: Some(note_id),
+                ..Default::default()
+            },
+            EndpointErrors::MessageNotFound(event_id)
+            | EndpointErrors::MessageDeleted(event_id) => ErrorDetails {
+                event_id: Some(event_id),
+                ..Default::default()
+            },
+            EndpointErrors::TooManyRequests(retry_after) => ErrorDetails::for_retry(retry_after),
+            _ => ErrorDetails::default(),
+        }
+    }
+}
+
+// Failures to parse the path, query or body of a request.
+fn malformed_request(err: impl std::fmt::Display) -> actix_web::Error {
+    EndpointErrors::MalformedRequest(err.to_string()).into()
 }
 
 impl error::ResponseError for EndpointErrors {
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::plaintext())
-            .body(self.to_string())
+        let status = self.status_code();
+        let problem = Problem::new(
+            self.code(),
+            status.as_u16(),
+            self.to_string(),
+            self.details(),
+        );
+        tracing::info!(
+            correlation_id = %problem.correlation_id,
+            code = ?problem.code,
+            %status,
+            "reporting problem"
+        );
+        let mut response = HttpResponse::build(status);
+        match self {
+            EndpointErrors::Unauthenticated => {
+                response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            EndpointErrors::TooManyRequests(retry_after) => {
+                response.insert_header((header::RETRY_AFTER, retry_after_secs(retry_after)));
+            }
+            _ => {}
+        }
+        response
+            .insert_header((header::CONTENT_TYPE, Problem::CONTENT_TYPE))
+            .body(serde_json::to_string(&problem).expect("problems should serialize"))
     }
 
     fn status_code(&self) -> StatusCode {
         match self {
             EndpointErrors::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             EndpointErrors::ChatNotFound(_) => StatusCode::NOT_FOUND,
+            EndpointErrors::NoteNotFound(_) => StatusCode::NOT_FOUND,
+            EndpointErrors::MessageNotFound(_) => StatusCode::NOT_FOUND,
+            EndpointErrors::Unauthenticated => StatusCode::UNAUTHORIZED,
+            EndpointErrors::Forbidden(_) => StatusCode::FORBIDDEN,
+            EndpointErrors::NotPermitted(_) => StatusCode::FORBIDDEN,
+            EndpointErrors::MessageDeleted(_) => StatusCode::GONE,
+            EndpointErrors::InviteUnusable(_) => StatusCode::GONE,
+            EndpointErrors::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            EndpointErrors::MalformedRequest(_) => StatusCode::BAD_REQUEST,
+            EndpointErrors::InvalidText(_) => StatusCode::BAD_REQUEST,
+            EndpointErrors::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 }
@@ -53,30 +205,598 @@ impl From<ChatServerErrors> for EndpointErrors {
                 EndpointErrors::InternalServerError
             }
             ChatServerErrors::ChatNotFound { chat_id } => EndpointErrors::ChatNotFound(chat_id),
+            ChatServerErrors::NoteNotFound { note_id, .. } => EndpointErrors::NoteNotFound(note_id),
+            ChatServerErrors::EventNotFound { event_id, .. } => {
+                EndpointErrors::MessageNotFound(event_id)
+            }
+            ChatServerErrors::MessageDeleted { event_id, .. } => {
+                EndpointErrors::MessageDeleted(event_id)
+            }
+            ChatServerErrors::NotAMember { chat_id, user_id } => {
+                tracing::info!(%chat_id, %user_id, "access by non-member denied");
+                EndpointErrors::Forbidden(chat_id)
+            }
+            ChatServerErrors::NotPermitted {
+                chat_id,
+                user_id,
+                reason,
+            } => {
+                tracing::info!(%chat_id, %user_id, reason, "action not permitted");
+                EndpointErrors::NotPermitted(reason)
+            }
+            ChatServerErrors::InvalidText { invalid_text, .. } => {
+                EndpointErrors::InvalidText(invalid_text)
+            }
+            ChatServerErrors::RateLimited { retry_after, .. } => {
+                EndpointErrors::TooManyRequests(retry_after)
+            }
+            ChatServerErrors::StorageFailure {
+                backtrace,
+                message,
+                source,
+            } => {
+                tracing::error!(message, %source, ?backtrace, "storage failure");
+                EndpointErrors::InternalServerError
+            }
+        }
+    }
+}
+
+// Browsers can't set headers for websocket connections. Instead they offer
+// `bearer` followed by the token as subprotocols, e.g.
+// `new WebSocket(url, ["bearer", token])`.
+const WEBSOCKET_BEARER_PROTOCOL: &str = "bearer";
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        let (scheme, token) = authorization.to_str().ok()?.split_once(' ')?;
+        return scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| token.trim().to_string());
+    }
+    websocket_protocol_token(req)
+}
+
+fn websocket_protocol_token(req: &HttpRequest) -> Option<String> {
+    let mut protocols = req
+        .headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+    protocols.find(|protocol| *protocol == WEBSOCKET_BEARER_PROTOCOL)?;
+    protocols.next().map(str::to_string)
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let authenticator = req.app_data::<web::Data<Authenticator>>().cloned();
+        let token = bearer_token(req);
+        Box::pin(async move {
+            let Some(authenticator) = authenticator else {
+                tracing::error!("no authenticator configured");
+                return Err(EndpointErrors::InternalServerError.into());
+            };
+            let token = token.ok_or(EndpointErrors::Unauthenticated)?;
+            authenticator.authenticate(&token).await.map_err(|err| {
+                tracing::info!(%err, "authentication failed");
+                EndpointErrors::Unauthenticated.into()
+            })
+        })
+    }
+}
+
+/// Resumes a chat session after a dropped connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ConnectQuery {
+    /// The sequence number of the last message the client received.
+    since: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HistoryQuery {
+    before: Option<EventId>,
+    after: Option<EventId>,
+    limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    fn cursor(&self) -> Result<HistoryCursor, EndpointErrors> {
+        match (self.before, self.after) {
+            (None, None) => Ok(HistoryCursor::Latest),
+            (Some(before), None) => Ok(HistoryCursor::Before(before)),
+            (None, Some(after)) => Ok(HistoryCursor::After(after)),
+            (Some(_), Some(_)) => Err(EndpointErrors::InvalidInput(
+                "only one of before and after may be given".to_string(),
+            )),
         }
     }
 }
 
+/// Without a cursor, the most recent messages are returned. The
+/// `next_cursor` of the page is passed as `before` to page backwards, or as
+/// `after` to page forwards from a page requested with `after`.
 #[get("/history/{chat_id}")]
 #[instrument(skip(app_state))]
 pub async fn get_chat_history(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<Uuid>,
+    query: web::Query<HistoryQuery>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let cursor = query.cursor()?;
+    let limit = match query.limit {
+        Some(0) => {
+            return Err(EndpointErrors::InvalidInput(
+                "the limit must be at least 1".to_string(),
+            ));
+        }
+        Some(limit) => limit,
+        None => DEFAULT_HISTORY_LIMIT,
+    };
+    let page = app_state
+        .get_chat_history(chat_id, user.user_id, cursor, limit)
+        .await
+        .map_err(|err| match err {
+            ChatServerErrors::EventNotFound { event_id, .. } => {
+                EndpointErrors::InvalidInput(format!("unknown cursor {event_id}"))
+            }
+            err => err.into(),
+        })?;
+    Ok(web::Json(page))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NewChat {
+    name: ChatName,
+    #[serde(default)]
+    topic: Option<ChatTopic>,
+}
+
+#[post("/chats")]
+#[instrument(skip(app_state))]
+pub async fn create_chat(
+    user: AuthenticatedUser,
+    new_chat: web::Json<NewChat>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let NewChat { name, topic } = new_chat.into_inner();
+    if name.as_str().trim().is_empty() {
+        return Err(EndpointErrors::InvalidInput(
+            "the name of a chat must not be empty".to_string(),
+        ));
+    }
+    let chat = app_state.create_chat(name, topic, user.user_id).await?;
+    Ok(HttpResponse::Created().json(chat))
+}
+
+#[get("/chats/{chat_id}")]
+#[instrument(skip(app_state))]
+pub async fn get_chat(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<Uuid>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let chat = app_state.get_chat(chat_id, user.user_id).await?;
+    Ok(web::Json(chat))
+}
+
+#[put("/chats/{chat_id}/retention")]
+#[instrument(skip(app_state))]
+pub async fn set_retention(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<Uuid>,
+    retention: web::Json<RetentionPolicy>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let chat = app_state
+        .set_retention(chat_id, user.user_id, retention.into_inner())
+        .await?;
+    Ok(web::Json(chat))
+}
+
+#[put("/chats/{chat_id}/rate-limit")]
+#[instrument(skip(app_state))]
+pub async fn set_rate_limit(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<Uuid>,
+    rate_limit: web::Json<RateLimitPolicy>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let rate_limit = rate_limit.into_inner();
+    if rate_limit.burst == Some(0)
+        || rate_limit.per_minute == Some(0)
+        || rate_limit.slow_mode_secs == Some(0)
+    {
+        return Err(EndpointErrors::InvalidInput(
+            "rate limits must be positive, leave them out to use the defaults".to_string(),
+        ));
+    }
+    let chat = app_state
+        .set_rate_limit(chat_id, user.user_id, rate_limit)
+        .await?;
+    Ok(web::Json(chat))
+}
+
+impl From<InviteErrors> for EndpointErrors {
+    fn from(value: InviteErrors) -> Self {
+        match value {
+            InviteErrors::Malformed | InviteErrors::InvalidSignature => {
+                EndpointErrors::InvalidInput(value.to_string())
+            }
+            InviteErrors::Expired(_) => EndpointErrors::InviteUnusable(value.to_string()),
+        }
+    }
+}
+
+fn default_invite_role() -> Role {
+    Role::Member
+}
+
+fn default_invite_max_uses() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NewInvite {
+    #[serde(default = "default_invite_role")]
+    role: Role,
+    #[serde(default = "default_invite_max_uses")]
+    max_uses: u32,
+    /// Validity in seconds, capped by `invites.max_ttl_secs`.
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignedInvite {
+    token: String,
+    invite: Invite,
+}
+
+#[post("/chats/{chat_id}/invites")]
+#[instrument(skip(app_state, settings, signer))]
+pub async fn create_invite(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<Uuid>,
+    new_invite: web::Json<NewInvite>,
+    app_state: web::Data<ChatServer>,
+    settings: web::Data<Settings>,
+    signer: web::Data<InviteSigner>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let NewInvite {
+        role,
+        max_uses,
+        ttl_secs,
+    } = new_invite.into_inner();
+    if max_uses == 0 {
+        return Err(EndpointErrors::InvalidInput(
+            "an invite must be usable at least once".to_string(),
+        ));
+    }
+    let ttl_secs = ttl_secs
+        .unwrap_or(settings.invites.max_ttl_secs)
+        .min(settings.invites.max_ttl_secs);
+    if ttl_secs == 0 {
+        return Err(EndpointErrors::InvalidInput(
+            "an invite must be valid for at least a second".to_string(),
+        ));
+    }
+    let expires_at =
+        Utc::now() + chrono::Duration::seconds(i64::try_from(ttl_secs).unwrap_or(i64::MAX));
+    let invite = app_state
+        .create_invite(chat_id, user.user_id, role, max_uses, expires_at)
+        .await?;
+    Ok(HttpResponse::Created().json(SignedInvite {
+        token: signer.sign(&invite),
+        invite,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InviteRedemptionRequest {
+    token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Membership {
+    chat_id: ChatId,
+    role: Role,
+}
+
+#[post("/invites/redeem")]
+#[instrument(skip(app_state, signer, redemption))]
+pub async fn redeem_invite(
+    user: AuthenticatedUser,
+    redemption: web::Json<InviteRedemptionRequest>,
+    app_state: web::Data<ChatServer>,
+    signer: web::Data<InviteSigner>,
+) -> Result<impl Responder, EndpointErrors> {
+    let invite = signer.verify(&redemption.token)?;
+    let role = match app_state.redeem_invite(&invite, user.user_id).await? {
+        InviteRedemption::Joined(role) | InviteRedemption::AlreadyMember(role) => role,
+        InviteRedemption::UsedUp => {
+            return Err(EndpointErrors::InviteUnusable(
+                "the invite is used up".to_string(),
+            ));
+        }
+    };
+    Ok(web::Json(Membership {
+        chat_id: invite.chat_id,
+        role,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NoteContent {
+    body: NoteBody,
+}
+
+impl NoteContent {
+    fn into_body(self) -> Result<NoteBody, EndpointErrors> {
+        if self.body.as_str().trim().is_empty() {
+            return Err(EndpointErrors::InvalidInput(
+                "the body of a note must not be empty".to_string(),
+            ));
+        }
+        Ok(self.body)
+    }
+}
+
+#[post("/chats/{chat_id}/notes")]
+#[instrument(skip(app_state, content))]
+pub async fn create_note(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<Uuid>,
+    content: web::Json<NoteContent>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let body = content.into_inner().into_body()?;
+    let note = app_state.create_note(chat_id, user.user_id, body).await?;
+    Ok(HttpResponse::Created().json(note))
+}
+
+#[get("/chats/{chat_id}/notes")]
+#[instrument(skip(app_state))]
+pub async fn get_notes(
+    user: AuthenticatedUser,
     path_parameter: web::Path<Uuid>,
     app_state: web::Data<ChatServer>,
 ) -> Result<impl Responder, EndpointErrors> {
     let chat_id = ChatId::from_uuid(path_parameter.into_inner());
-    let history = app_state.get_chat_history(chat_id)?;
-    Ok(web::Json(history))
+    let notes = app_state.get_notes(chat_id, user.user_id).await?;
+    Ok(web::Json(notes))
+}
+
+#[put("/chats/{chat_id}/notes/{note_id}")]
+#[instrument(skip(app_state, content))]
+pub async fn edit_note(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid)>,
+    content: web::Json<NoteContent>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, note_id) = path_parameter.into_inner();
+    let body = content.into_inner().into_body()?;
+    let note = app_state
+        .edit_note(
+            ChatId::from_uuid(chat_id),
+            NoteId::from_uuid(note_id),
+            user.user_id,
+            body,
+        )
+        .await?;
+    Ok(web::Json(note))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MessageEdit {
+    message: Message,
+}
+
+#[put("/chats/{chat_id}/messages/{event_id}")]
+#[instrument(skip(app_state, edit))]
+pub async fn edit_message(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid)>,
+    edit: web::Json<MessageEdit>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, event_id) = path_parameter.into_inner();
+    let message = app_state
+        .edit_message(
+            ChatId::from_uuid(chat_id),
+            EventId::from_uuid(event_id),
+            user.user_id,
+            edit.into_inner().message,
+        )
+        .await?;
+    Ok(web::Json(message))
+}
+
+/// Yields the tombstone left by the message.
+#[delete("/chats/{chat_id}/messages/{event_id}")]
+#[instrument(skip(app_state))]
+pub async fn delete_message(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid)>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, event_id) = path_parameter.into_inner();
+    let tombstone = app_state
+        .delete_message(
+            ChatId::from_uuid(chat_id),
+            EventId::from_uuid(event_id),
+            user.user_id,
+        )
+        .await?;
+    Ok(web::Json(tombstone))
+}
+
+#[get("/chats/{chat_id}/messages/{event_id}/revisions")]
+#[instrument(skip(app_state))]
+pub async fn get_message_revisions(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid)>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, event_id) = path_parameter.into_inner();
+    let revisions = app_state
+        .get_message_revisions(
+            ChatId::from_uuid(chat_id),
+            EventId::from_uuid(event_id),
+            user.user_id,
+        )
+        .await?;
+    Ok(web::Json(revisions))
+}
+
+// Emojis are checked here like other input, so only plausible ones are
+// stored.
+fn check_emoji(emoji: Emoji) -> Result<Emoji, String> {
+    if !emoji.is_plausible() {
+        return Err(format!("{emoji:?} is no emoji"));
+    }
+    Ok(emoji)
+}
+
+#[put("/chats/{chat_id}/messages/{event_id}/reactions/{emoji}")]
+#[instrument(skip(app_state))]
+pub async fn add_reaction(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid, String)>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, event_id, emoji) = path_parameter.into_inner();
+    let emoji = check_emoji(Emoji::new(emoji)).map_err(EndpointErrors::InvalidInput)?;
+    app_state
+        .add_reaction(
+            ChatId::from_uuid(chat_id),
+            EventId::from_uuid(event_id),
+            user.user_id,
+            emoji,
+        )
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/chats/{chat_id}/messages/{event_id}/reactions/{emoji}")]
+#[instrument(skip(app_state))]
+pub async fn remove_reaction(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid, String)>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, event_id, emoji) = path_parameter.into_inner();
+    app_state
+        .remove_reaction(
+            ChatId::from_uuid(chat_id),
+            EventId::from_uuid(event_id),
+            user.user_id,
+            Emoji::new(emoji),
+        )
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Yields the first message of the thread the message belongs to followed by
+/// the replies in it.
+#[get("/chats/{chat_id}/threads/{event_id}")]
+#[instrument(skip(app_state))]
+pub async fn get_thread(
+    user: AuthenticatedUser,
+    path_parameter: web::Path<(Uuid, Uuid)>,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let (chat_id, event_id) = path_parameter.into_inner();
+    let thread = app_state
+        .get_thread(
+            ChatId::from_uuid(chat_id),
+            EventId::from_uuid(event_id),
+            user.user_id,
+        )
+        .await?;
+    Ok(web::Json(thread))
+}
+
+/// Yields the chats the user is a member of, the most recently active first,
+/// with a preview of their latest message.
+#[get("/users/me/chats")]
+#[instrument(skip(app_state))]
+pub async fn get_member_chats(
+    user: AuthenticatedUser,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let chats = app_state.get_member_chats(user.user_id).await?;
+    Ok(web::Json(chats))
+}
+
+/// Yields the chats the user is a member of with the number of messages of
+/// others the user hasn't read yet.
+#[get("/users/me/unread")]
+#[instrument(skip(app_state))]
+pub async fn get_unread_messages(
+    user: AuthenticatedUser,
+    app_state: web::Data<ChatServer>,
+) -> Result<impl Responder, EndpointErrors> {
+    let unread = app_state.get_unread_messages(user.user_id).await?;
+    Ok(web::Json(unread))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct IncomingChatMessage {
     pub display_name: DisplayName,
     pub message: Message,
+    #[serde(default)]
+    pub in_reply_to: Option<EventId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Incoming {
+    ChatMessage(IncomingChatMessage),
+    /// Replaces the access token of the session before it expires.
+    Reauthenticate {
+        token: String,
+    },
+    /// Replaces the text of a message the user sent.
+    EditMessage {
+        event_id: EventId,
+        message: Message,
+    },
+    /// Replaces a message the user sent by a tombstone.
+    DeleteMessage {
+        event_id: EventId,
+    },
+    AddReaction {
+        event_id: EventId,
+        emoji: Emoji,
+    },
+    RemoveReaction {
+        event_id: EventId,
+        emoji: Emoji,
+    },
+    /// The user is typing, sent repeatedly while they are. It is passed on to
+    /// the other members but not stored.
+    Typing,
+    /// The user has read the chat up to the message, including it.
+    MarkRead {
+        event_id: EventId,
+    },
 }
 
 #[derive(Debug)]
 enum IncomingStreamEventSuccess {
-    ChatMessage(IncomingChatMessage),
+    Incoming(Incoming),
     Ping(Bytes),
     Close(Option<CloseReason>),
 }
@@ -90,10 +810,126 @@ enum IncomingStreamEventError {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum Outgoing {
-    ChatMessage { msg: ChatMessage },
-    Error { msg: String },
+    /// The latest messages of the chat, sent first on connecting. The
+    /// messages sent afterwards continue right after them.
+    History {
+        page: HistoryPage,
+    },
+    /// The session lagged behind the chat and skipped more messages than can
+    /// be caught up with, or changes to what it received before, like edits,
+    /// reactions, notes or read receipts. The client should replace the
+    /// messages it shows by the latest page and fetch older ones, the notes
+    /// and the unread counts again.
+    Resync {
+        page: HistoryPage,
+    },
+    ChatMessage {
+        msg: ChatMessage,
+    },
+    /// Replaces the message with the same `event_id`.
+    MessageEdited {
+        msg: ChatMessage,
+    },
+    /// Replaces the message with the same `event_id` by its tombstone.
+    MessageDeleted {
+        msg: ChatMessage,
+    },
+    /// A request of the client failed. Clients should tell failures apart
+    /// by `code`, the message is meant for humans.
+    Error {
+        code: ErrorCode,
+        msg: String,
+        /// Identifies the occurrence in the logs of the server.
+        correlation_id: Uuid,
+        #[serde(flatten)]
+        details: ErrorDetails,
+    },
+    /// The access token of the session expires soon, the client should send
+    /// a fresh one with [`Incoming::Reauthenticate`].
+    TokenExpiring {
+        expires_at: DateTime<Utc>,
+    },
+    Reauthenticated {
+        expires_at: DateTime<Utc>,
+    },
+    NoteCreated {
+        note: Note,
+    },
+    NoteUpdated {
+        note: Note,
+    },
+    /// Adds the user to the reaction with the emoji of the message, a user
+    /// listed already is no change.
+    ReactionAdded {
+        reaction: ReactionChange,
+    },
+    /// Drops the user from the reaction with the emoji of the message.
+    ReactionRemoved {
+        reaction: ReactionChange,
+    },
+    /// The members connected to the chat, sent after the history and after
+    /// catching up, as joins and leaves may have been skipped meanwhile.
+    Presence {
+        users: Vec<UserId>,
+    },
+    MemberJoined {
+        user_id: UserId,
+    },
+    MemberLeft {
+        user_id: UserId,
+    },
+    /// Another member is typing. Clients should stop showing it a few
+    /// seconds after the last one.
+    Typing {
+        user_id: UserId,
+    },
+    /// A member has read the chat up to a message. Receipts of a member may
+    /// arrive out of order, clients should keep the one with the highest
+    /// sequence number.
+    ReadUpTo {
+        receipt: ReadReceipt,
+    },
+}
+
+impl Outgoing {
+    fn error(code: ErrorCode, msg: String) -> Self {
+        Self::error_with_details(code, msg, ErrorDetails::default())
+    }
+
+    fn error_with_details(code: ErrorCode, msg: String, details: ErrorDetails) -> Self {
+        let correlation_id = Uuid::new_v4();
+        tracing::info!(%correlation_id, ?code, msg, "reporting error");
+        Outgoing::Error {
+            code,
+            msg,
+            correlation_id,
+            details,
+        }
+    }
 }
 
+impl From<ChatEvent> for Outgoing {
+    fn from(event: ChatEvent) -> Self {
+        match event {
+            ChatEvent::Message(msg) => Outgoing::ChatMessage { msg },
+            ChatEvent::MessageEdited(msg) => Outgoing::MessageEdited { msg },
+            ChatEvent::MessageDeleted(msg) => Outgoing::MessageDeleted { msg },
+            ChatEvent::NoteCreated(note) => Outgoing::NoteCreated { note },
+            ChatEvent::NoteUpdated(note) => Outgoing::NoteUpdated { note },
+            ChatEvent::ReactionAdded(reaction) => Outgoing::ReactionAdded { reaction },
+            ChatEvent::ReactionRemoved(reaction) => Outgoing::ReactionRemoved { reaction },
+            ChatEvent::MemberJoined(user_id) => Outgoing::MemberJoined { user_id },
+            ChatEvent::MemberLeft(user_id) => Outgoing::MemberLeft { user_id },
+            ChatEvent::Typing(user_id) => Outgoing::Typing { user_id },
+            ChatEvent::ReadUpTo(receipt) => Outgoing::ReadUpTo { receipt },
+        }
+    }
+}
+
+/// Close code of websocket sessions, whose access token expired without
+/// being replaced. Codes from 4000 on are reserved for applications.
+const TOKEN_EXPIRED_CLOSE_CODE: u16 = 4001;
+
 type IncomingStreamEvent = Result<IncomingStreamEventSuccess, IncomingStreamEventError>;
 
 #[instrument]
@@ -104,8 +940,8 @@ async fn preprocess_incoming_stream_event(
         Ok(inner_message) => match inner_message {
             AggregatedMessage::Text(byte_string) => Some(
                 serde_json::from_slice(byte_string.as_ref())
-                    .map(|v| IncomingStreamEventSuccess::ChatMessage(v))
-                    .map_err(|err| IncomingStreamEventError::ParseError(err)),
+                    .map(IncomingStreamEventSuccess::Incoming)
+                    .map_err(IncomingStreamEventError::ParseError),
             ),
             AggregatedMessage::Binary(_) => {
                 tracing::warn!("unexpected binary message received");
@@ -139,30 +975,249 @@ async fn send_message(session: &mut Session, message: Outgoing) -> Result<(), Se
     Ok(())
 }
 
-#[instrument(skip(chat_server, session))]
-async fn handle_incoming_stream_event(
+/// The user behind a websocket session and what they may do in the chat.
+#[derive(Debug)]
+pub struct ChatSession {
     chat_id: ChatId,
-    user_id: UserId,
-    stream_event: Option<IncomingStreamEvent>,
-    chat_server: &ChatServer,
+    user: AuthenticatedUser,
+    role: Role,
+    rate_limits: ConnectionRateLimits,
+}
+
+// Only accepts tokens of the user the session belongs to, the expiry of the
+// session is extended on success.
+async fn reauthenticate(
+    token: &str,
+    user: &mut AuthenticatedUser,
+    authenticator: &Authenticator,
+) -> Outgoing {
+    match authenticator.authenticate(token).await {
+        Ok(fresh_user) if fresh_user.user_id == user.user_id => {
+            tracing::info!(expires_at = %fresh_user.expires_at, "reauthenticated");
+            user.expires_at = fresh_user.expires_at;
+            Outgoing::Reauthenticated {
+                expires_at: fresh_user.expires_at,
+            }
+        }
+        Ok(fresh_user) => {
+            tracing::warn!(other_user_id = %fresh_user.user_id, "reauthentication as another user");
+            Outgoing::error(
+                ErrorCode::Unauthenticated,
+                "the token belongs to another user".to_string(),
+            )
+        }
+        Err(err) => {
+            tracing::info!(%err, "reauthentication failed");
+            Outgoing::error(
+                ErrorCode::Unauthenticated,
+                format!("reauthentication failed: {err}"),
+            )
+        }
+    }
+}
+
+// Tells the client why its request failed. The session is closed on
+// failures of the server instead, as it can't go on reliably.
+async fn reply_with_error(session: &mut Session, err: ChatServerErrors) -> ControlFlow<(), ()> {
+    let code = ErrorCode::from(&err);
+    if code == ErrorCode::InternalError {
+        tracing::error!(?err, "error handling request");
+        // The details of the failure are for the logs only.
+        let outgoing = Outgoing::error(code, "internal server error".to_string());
+        if let Err(err) = send_message(session, outgoing).await {
+            tracing::error!(?err, "error sending message to websocket");
+        }
+        return ControlFlow::Break(());
+    }
+    tracing::info!(%err, "request failed");
+    let outgoing = Outgoing::error_with_details(code, err.to_string(), ErrorDetails::from(&err));
+    if let Err(err) = send_message(session, outgoing).await {
+        tracing::error!(?err, "error sending message to websocket");
+        return ControlFlow::Break(());
+    }
+    ControlFlow::Continue(())
+}
+
+// Rejects a request, closing the session if it keeps sending too fast.
+async fn reply_with_rejection(
     session: &mut Session,
+    rate_limits: &mut ConnectionRateLimits,
+    err: ChatServerErrors,
 ) -> ControlFlow<(), ()> {
-    match stream_event {
-        Some(Ok(msg)) => match msg {
-            IncomingStreamEventSuccess::ChatMessage(incoming_chat_message) => {
-                tracing::debug!(?incoming_chat_message, "received");
-                if let Err(err) = chat_server.send_message(ChatMessage {
-                    event_id: EventId::random(),
-                    timestamp: ChatTimestamp::now(),
-                    chat_id,
-                    user_id,
-                    display_name: incoming_chat_message.display_name,
-                    message: incoming_chat_message.message,
-                }) {
-                    tracing::error!(?err, "error sending message to chat");
+    if matches!(err, ChatServerErrors::RateLimited { .. }) && !rate_limits.tolerate_violation() {
+        tracing::warn!("closing session sending too fast");
+        let close_reason = CloseReason {
+            code: CloseCode::Policy,
+            description: Some("sending too fast".to_string()),
+        };
+        if let Err(err) = session.clone().close(Some(close_reason)).await {
+            tracing::error!(?err, "failed to close websocket");
+        }
+        return ControlFlow::Break(());
+    }
+    reply_with_error(session, err).await
+}
+
+// Charges a request other than a message to the connection. Yields how to go
+// on if it was rejected.
+async fn charge_event(
+    chat_server: &ChatServer,
+    chat_session: &mut ChatSession,
+    session: &mut Session,
+) -> Option<ControlFlow<(), ()>> {
+    let admitted = chat_server.admit_event(
+        chat_session.chat_id,
+        chat_session.user.user_id,
+        &mut chat_session.rate_limits,
+    );
+    match admitted {
+        Ok(()) => None,
+        Err(err) => Some(reply_with_rejection(session, &mut chat_session.rate_limits, err).await),
+    }
+}
+
+#[instrument(skip(chat_server, authenticator, session))]
+async fn handle_incoming_stream_event(
+    chat_session: &mut ChatSession,
+    stream_event: Option<IncomingStreamEvent>,
+    chat_server: &ChatServer,
+    authenticator: &Authenticator,
+    session: &mut Session,
+) -> ControlFlow<(), ()> {
+    match stream_event {
+        Some(Ok(msg)) => match msg {
+            IncomingStreamEventSuccess::Incoming(Incoming::Reauthenticate { token }) => {
+                let reply = reauthenticate(&token, &mut chat_session.user, authenticator).await;
+                if let Err(err) = send_message(session, reply).await {
+                    tracing::error!(?err, "error sending message to websocket");
                     return ControlFlow::Break(());
                 }
             }
+            IncomingStreamEventSuccess::Incoming(Incoming::ChatMessage(incoming_chat_message)) => {
+                tracing::debug!(?incoming_chat_message, "received");
+                let admitted = chat_server
+                    .admit_message(
+                        chat_session.chat_id,
+                        chat_session.user.user_id,
+                        &mut chat_session.rate_limits,
+                    )
+                    .await;
+                if let Err(err) = admitted {
+                    return reply_with_rejection(session, &mut chat_session.rate_limits, err).await;
+                }
+                if let Err(err) = chat_server
+                    .send_message(ChatMessage {
+                        event_id: EventId::random(),
+                        timestamp: ChatTimestamp::now(),
+                        chat_id: chat_session.chat_id,
+                        // Assigned by the store.
+                        sequence: 0,
+                        user_id: chat_session.user.user_id,
+                        display_name: incoming_chat_message.display_name,
+                        message: incoming_chat_message.message,
+                        in_reply_to: incoming_chat_message.in_reply_to,
+                        // Derived by the chat server.
+                        thread_root: None,
+                        edited_at: None,
+                        deleted_at: None,
+                        reactions: Vec::new(),
+                    })
+                    .await
+                {
+                    return reply_with_error(session, err).await;
+                }
+            }
+            IncomingStreamEventSuccess::Incoming(Incoming::EditMessage { event_id, message }) => {
+                if let Some(rejected) = charge_event(chat_server, chat_session, session).await {
+                    return rejected;
+                }
+                let edited = chat_server
+                    .edit_message(
+                        chat_session.chat_id,
+                        event_id,
+                        chat_session.user.user_id,
+                        message,
+                    )
+                    .await;
+                // The edit reaches the session like every other member.
+                if let Err(err) = edited {
+                    return reply_with_error(session, err).await;
+                }
+            }
+            IncomingStreamEventSuccess::Incoming(Incoming::DeleteMessage { event_id }) => {
+                if let Some(rejected) = charge_event(chat_server, chat_session, session).await {
+                    return rejected;
+                }
+                let deleted = chat_server
+                    .delete_message(chat_session.chat_id, event_id, chat_session.user.user_id)
+                    .await;
+                if let Err(err) = deleted {
+                    return reply_with_error(session, err).await;
+                }
+            }
+            IncomingStreamEventSuccess::Incoming(Incoming::AddReaction { event_id, emoji }) => {
+                if let Some(rejected) = charge_event(chat_server, chat_session, session).await {
+                    return rejected;
+                }
+                let emoji = match check_emoji(emoji) {
+                    Ok(emoji) => emoji,
+                    Err(msg) => {
+                        let outgoing = Outgoing::error(ErrorCode::InvalidInput, msg);
+                        if let Err(err) = send_message(session, outgoing).await {
+                            tracing::error!(?err, "error sending message to websocket");
+                            return ControlFlow::Break(());
+                        }
+                        return ControlFlow::Continue(());
+                    }
+                };
+                let added = chat_server
+                    .add_reaction(
+                        chat_session.chat_id,
+                        event_id,
+                        chat_session.user.user_id,
+                        emoji,
+                    )
+                    .await;
+                if let Err(err) = added {
+                    return reply_with_error(session, err).await;
+                }
+            }
+            IncomingStreamEventSuccess::Incoming(Incoming::RemoveReaction { event_id, emoji }) => {
+                if let Some(rejected) = charge_event(chat_server, chat_session, session).await {
+                    return rejected;
+                }
+                let removed = chat_server
+                    .remove_reaction(
+                        chat_session.chat_id,
+                        event_id,
+                        chat_session.user.user_id,
+                        emoji,
+                    )
+                    .await;
+                if let Err(err) = removed {
+                    return reply_with_error(session, err).await;
+                }
+            }
+            IncomingStreamEventSuccess::Incoming(Incoming::Typing) => {
+                // Read-only members have nothing to type. Throttled by the
+                // chat server, without replying.
+                if chat_session.role.can_post() {
+                    chat_server
+                        .notify_typing(chat_session.chat_id, chat_session.user.user_id)
+                        .await;
+                }
+            }
+            IncomingStreamEventSuccess::Incoming(Incoming::MarkRead { event_id }) => {
+                if let Some(rejected) = charge_event(chat_server, chat_session, session).await {
+                    return rejected;
+                }
+                let marked = chat_server
+                    .mark_read(chat_session.chat_id, chat_session.user.user_id, event_id)
+                    .await;
+                if let Err(err) = marked {
+                    return reply_with_error(session, err).await;
+                }
+            }
             IncomingStreamEventSuccess::Ping(bytes) => {
                 if let Err(err) = session.pong(&bytes).await {
                     tracing::error!(?err, "error sending pong");
@@ -182,7 +1237,8 @@ async fn handle_incoming_stream_event(
             IncomingStreamEventError::ParseError(error) => {
                 let msg = format!("couldn't parse incoming message: {error}");
                 tracing::warn!("{}", msg);
-                if let Err(err) = send_message(session, Outgoing::Error { msg }).await {
+                let outgoing = Outgoing::error(ErrorCode::MalformedRequest, msg);
+                if let Err(err) = send_message(session, outgoing).await {
                     tracing::error!(?err, "error sending message to websocket");
                     return ControlFlow::Break(());
                 }
@@ -196,44 +1252,208 @@ async fn handle_incoming_stream_event(
     ControlFlow::Continue(())
 }
 
-#[instrument(skip(chat_server, session, stream, broadcast))]
-pub async fn handle_websocket_connection(
+// Reads the messages skipped after lagging behind the chat, which follow the
+// one with `last_sequence`, from the history. Only new messages can be caught
+// up with that way, so the client has to resync if it may have skipped an
+// amendment, or if the messages don't continue right after the last one,
+// because more than a page was skipped.
+async fn catch_up(
+    chat_server: &ChatServer,
+    chat_id: ChatId,
+    last_sequence: &mut u64,
+    amended: bool,
+) -> Result<Vec<Outgoing>, ChatServerErrors> {
+    if amended {
+        let page = chat_server.read_latest_messages(chat_id).await?;
+        if let Some(last) = page.messages.last() {
+            *last_sequence = (*last_sequence).max(last.sequence);
+        }
+        tracing::warn!("skipped amendments, resyncing");
+        return Ok(vec![Outgoing::Resync { page }]);
+    }
+    let page = chat_server
+        .read_missed_messages(chat_id, *last_sequence)
+        .await?;
+    let continues = page
+        .messages
+        .first()
+        .is_none_or(|message| message.sequence == *last_sequence + 1);
+    if let Some(last) = page.messages.last() {
+        *last_sequence = last.sequence;
+    }
+    if !continues {
+        tracing::warn!("skipped too many messages, resyncing");
+        return Ok(vec![Outgoing::Resync { page }]);
+    }
+    tracing::info!(caught_up = page.messages.len(), "caught up");
+    Ok(page
+        .messages
+        .into_iter()
+        .map(|msg| Outgoing::ChatMessage { msg })
+        .collect())
+}
+
+#[instrument(skip(chat_server, session))]
+async fn send_catch_up(
     chat_id: ChatId,
-    user_id: UserId,
+    last_sequence: &mut u64,
+    amended: bool,
+    chat_server: &ChatServer,
+    session: &mut Session,
+) -> ControlFlow<(), ()> {
+    let outgoing = match catch_up(chat_server, chat_id, last_sequence, amended).await {
+        Ok(outgoing) => outgoing,
+        Err(err) => {
+            tracing::error!(?err, "error reading skipped messages");
+            let msg = "skipped messages, please reconnect".to_string();
+            if let Err(err) =
+                send_message(session, Outgoing::error(ErrorCode::InternalError, msg)).await
+            {
+                tracing::error!(?err, "error sending message to websocket");
+            }
+            return ControlFlow::Break(());
+        }
+    };
+    let users = chat_server.present_members(chat_id).await;
+    for message in outgoing.into_iter().chain([Outgoing::Presence { users }]) {
+        if let Err(err) = send_message(session, message).await {
+            tracing::error!(?err, "error sending message to websocket");
+            return ControlFlow::Break(());
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+// Converts a wall clock time into a deadline for the tokio timer, times in
+// the past yield an immediate deadline.
+fn deadline_at(time: DateTime<Utc>) -> Instant {
+    Instant::now() + (time - Utc::now()).to_std().unwrap_or_default()
+}
+
+#[instrument(skip(chat_server, authenticator, session, stream, joined_chat, settings))]
+pub async fn handle_websocket_connection(
+    mut chat_session: ChatSession,
     chat_server: web::Data<ChatServer>,
+    authenticator: web::Data<Authenticator>,
     mut session: Session,
     stream: MessageStream,
-    mut broadcast: BroadcastStream<ChatMessage>,
+    joined_chat: JoinedChat,
+    settings: WebSocketSettings,
 ) {
     let stream = stream
         .aggregate_continuations()
-        .max_continuation_size(2usize.pow(22))
+        .max_continuation_size(settings.max_continuation_size)
         .filter_map(preprocess_incoming_stream_event);
+    let expiry_warning = chrono::Duration::seconds(
+        i64::try_from(settings.token_expiry_warning_secs).unwrap_or(i64::MAX),
+    );
+
+    // Fires first to warn the client about the expiring token and then, if
+    // the token still wasn't replaced, to close the session.
+    let expiry =
+        tokio::time::sleep_until(deadline_at(chat_session.user.expires_at - expiry_warning));
+    let mut expiry = pin!(expiry);
+    let mut expiry_warned = false;
+
+    // The history and presence go first, the events continue right after
+    // them.
+    let JoinedChat {
+        history,
+        mut last_sequence,
+        present,
+        mut published,
+        events: mut broadcast,
+        ..
+    } = joined_chat;
+    for outgoing in [
+        Outgoing::History { page: history },
+        Outgoing::Presence { users: present },
+    ] {
+        if let Err(err) = send_message(&mut session, outgoing).await {
+            tracing::error!(?err, "failed to send history to websocket");
+            drop(broadcast);
+            chat_server
+                .part_chat(chat_session.chat_id, chat_session.user.user_id)
+                .await;
+            chat_server.release_rate_limits(
+                chat_session.chat_id,
+                chat_session.user.user_id,
+                chat_session.rate_limits,
+            );
+            return;
+        }
+    }
 
     let mut pinned_stream = pin!(stream);
     loop {
         tokio::select! {
             incoming_stream_event = pinned_stream.next() => {
+                let expires_at = chat_session.user.expires_at;
                 if let ControlFlow::Break(()) =
-                    handle_incoming_stream_event(chat_id, user_id, incoming_stream_event, &chat_server, &mut session)
+                    handle_incoming_stream_event(&mut chat_session, incoming_stream_event, &chat_server, &authenticator, &mut session)
                         .await
                 {
                     break;
                 }
+                if chat_session.user.expires_at != expires_at {
+                    expiry_warned = false;
+                    expiry.as_mut().reset(deadline_at(chat_session.user.expires_at - expiry_warning));
+                }
+            },
+            () = &mut expiry => {
+                if expiry_warned {
+                    tracing::info!(expires_at = %chat_session.user.expires_at, "access token expired, closing session");
+                    let close_reason = CloseReason {
+                        code: CloseCode::Other(TOKEN_EXPIRED_CLOSE_CODE),
+                        description: Some("access token expired".to_string()),
+                    };
+                    if let Err(err) = session.close(Some(close_reason)).await {
+                        tracing::error!(?err, "failed to close websocket");
+                    }
+                    break;
+                }
+                expiry_warned = true;
+                expiry.as_mut().reset(deadline_at(chat_session.user.expires_at));
+                if let Err(err) =
+                    send_message(&mut session, Outgoing::TokenExpiring { expires_at: chat_session.user.expires_at }).await
+                {
+                    tracing::error!(?err, "failed to send message to websocket");
+                    break;
+                }
             },
-            outgoing_message = broadcast.next() => {
-                match outgoing_message {
-                    Some(Ok(message)) => {
+            chat_event = broadcast.next() => {
+                match chat_event {
+                    Some(Ok(event)) => {
+                        published += 1;
+                        match &event {
+                            ChatEvent::Message(message) => {
+                                // Caught up with already after lagging behind.
+                                if message.sequence <= last_sequence {
+                                    continue;
+                                }
+                                last_sequence = message.sequence;
+                            }
+                            ChatEvent::Typing(user_id) if *user_id == chat_session.user.user_id => {
+                                continue;
+                            }
+                            _ => {}
+                        }
                         if let Err(err) =
-                            send_message(&mut session, Outgoing::ChatMessage { msg: message }).await
+                            send_message(&mut session, Outgoing::from(event)).await
                         {
                             tracing::error!(?err, "failed to send message to websocket");
                             break;
                         }
                     }
-                    Some(Err(err)) => {
-                        tracing::error!(?err, "receiving chat messages from chat server");
-                        break;
+                    Some(Err(BroadcastStreamRecvError::Lagged(skipped))) => {
+                        tracing::warn!(skipped, "lagging behind the chat");
+                        let amended = chat_server.amended_since(chat_session.chat_id, published).await;
+                        published += skipped;
+                        if let ControlFlow::Break(()) =
+                            send_catch_up(chat_session.chat_id, &mut last_sequence, amended, &chat_server, &mut session).await
+                        {
+                            break;
+                        }
                     },
                     None => {
                         tracing::error!("message stream from chat server closed");
@@ -244,38 +1464,100 @@ pub async fn handle_websocket_connection(
         }
     }
     tracing::info!("leaving chat");
-    chat_server.part_chat(chat_id);
+    drop(broadcast);
+    chat_server
+        .part_chat(chat_session.chat_id, chat_session.user.user_id)
+        .await;
+    chat_server.release_rate_limits(
+        chat_session.chat_id,
+        chat_session.user.user_id,
+        chat_session.rate_limits,
+    );
+}
+
+// The address of the client, as forwarded by a trusted proxy. Anyone else
+// could claim any address with the headers.
+fn client_ip(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+    if peer_ip.is_none_or(|ip| !trusted_proxies.contains(&ip)) {
+        return peer_ip;
+    }
+    let connection_info = req.connection_info();
+    // Either a bare address or one with a port.
+    let forwarded = connection_info.realip_remote_addr()?;
+    forwarded
+        .parse()
+        .or_else(|_| forwarded.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .or(peer_ip)
 }
 
-#[get("/chat/{chat_id}/{user_id}")]
-#[instrument(skip(app_state, stream))]
+#[get("/chat/{chat_id}")]
+#[instrument(skip(app_state, settings, authenticator, stream))]
 pub async fn connect_to_chat(
+    user: AuthenticatedUser,
     app_state: web::Data<ChatServer>,
-    path_parameters: web::Path<(Uuid, Uuid)>,
+    settings: web::Data<Settings>,
+    authenticator: web::Data<Authenticator>,
+    path_parameter: web::Path<Uuid>,
     req: HttpRequest,
     stream: web::Payload,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let (chat_uuid, user_uuid) = path_parameters.into_inner();
-    let chat_id = ChatId::from_uuid(chat_uuid);
-    let user_id = UserId::from_uuid(user_uuid);
+    let chat_id = ChatId::from_uuid(path_parameter.into_inner());
+    let query = web::Query::<ConnectQuery>::from_query(req.query_string())?;
 
-    let (res, session, stream) = actix_ws::handle(&req, stream)?;
-    let chat_messages_receiver = app_state.join_chat(chat_id);
+    let (mut res, session, stream) = actix_ws::handle(&req, stream)?;
+    if websocket_protocol_token(&req).is_some() {
+        // Browsers drop the connection, if the server doesn't agree on one
+        // of the offered subprotocols.
+        res.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(WEBSOCKET_BEARER_PROTOCOL),
+        );
+    }
+    let joined_chat = app_state
+        .join_chat(chat_id, user.user_id, query.since)
+        .await
+        .map_err(EndpointErrors::from)?;
 
     actix_web::rt::spawn(handle_websocket_connection(
-        chat_id,
-        user_id,
+        ChatSession {
+            chat_id,
+            user,
+            role: joined_chat.role,
+            rate_limits: app_state.connection_rate_limits(client_ip(
+                &req,
+                &settings.chat.rate_limits.trusted_proxies,
+            )),
+        },
         app_state,
+        authenticator,
         session,
         stream,
-        chat_messages_receiver,
+        joined_chat,
+        settings.websocket.clone(),
     ));
 
     Ok(res)
 }
 
+pub fn setup_cors(settings: &CorsSettings) -> Cors {
+    if settings.is_permissive() {
+        return Cors::permissive();
+    }
+    settings
+        .allowed_origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allow_any_method()
+        .allow_any_header()
+}
+
 pub fn setup_app(
     chat_server: web::Data<ChatServer>,
+    settings: web::Data<Settings>,
+    authenticator: web::Data<Authenticator>,
+    invite_signer: web::Data<InviteSigner>,
 ) -> App<
     impl ServiceFactory<
         actix_web::dev::ServiceRequest,
@@ -287,217 +1569,1770 @@ pub fn setup_app(
         InitError = (),
     >,
 > {
-    return App::new()
+    App::new()
         .wrap(TracingLogger::default())
         .app_data(chat_server)
-        .app_data(PathConfig::default().error_handler(|err, _| err.into()))
+        .app_data(settings)
+        .app_data(authenticator)
+        .app_data(invite_signer)
+        .app_data(PathConfig::default().error_handler(|err, _| malformed_request(err)))
+        .app_data(QueryConfig::default().error_handler(|err, _| malformed_request(err)))
+        .app_data(JsonConfig::default().error_handler(|err, _| malformed_request(err)))
         .service(get_chat_history)
-        .service(connect_to_chat);
+        .service(create_chat)
+        .service(get_chat)
+        .service(set_retention)
+        .service(set_rate_limit)
+        .service(create_invite)
+        .service(redeem_invite)
+        .service(create_note)
+        .service(get_notes)
+        .service(edit_note)
+        .service(edit_message)
+        .service(delete_message)
+        .service(get_message_revisions)
+        .service(get_thread)
+        .service(add_reaction)
+        .service(remove_reaction)
+        .service(get_member_chats)
+        .service(get_unread_messages)
+        .service(connect_to_chat)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{
+        net::{IpAddr, SocketAddr},
+        time::Duration,
+    };
 
-    use actix_http::ws::{self, Frame};
+    use actix_codec::Framed;
+    use actix_http::error::PayloadError;
+    use actix_http::ws::{self, CloseCode, Frame};
     use actix_test::TestServer;
+    use actix_web::web::Bytes;
     use actix_web::{
-        http::{StatusCode, header::Accept},
+        http::{
+            StatusCode,
+            header::{self, Accept},
+        },
         test, web,
     };
     use anyhow::Context;
+    use awc::BoxedSocket;
+    use chrono::Utc;
     use futures::{SinkExt, StreamExt};
 
     use crate::{
+        auth::{
+            Authenticator,
+            testing::{test_authenticator, token_expiring_at, token_for},
+        },
         chat::{
-            ChatServer,
-            models::{ChatId, ChatMessage, DisplayName, Message, UserId},
+            ChatServer, MAX_HISTORY_LIMIT,
+            models::{
+                ChatId, ChatMessage, ChatMetadata, ChatName, ChatSummary, ChatTimestamp,
+                DisplayName, Emoji, EventId, HistoryPage, InvalidText, Message, MessagePreview,
+                MessageRevision, RateLimitPolicy, Reaction, ReactionChange, ReadReceipt,
+                RetentionPolicy, TextField, UnreadMessages, UserId,
+            },
+            store::memory::InMemoryChatStore,
         },
-        services::{IncomingChatMessage, Outgoing, setup_app},
+        invites::InviteSigner,
+        notes::models::Note,
+        problems::{ErrorCode, Problem},
+        services::{
+            Incoming, IncomingChatMessage, Outgoing, TOKEN_EXPIRED_CLOSE_CODE, catch_up, client_ip,
+            setup_app,
+        },
+        settings::{ChatSettings, Settings},
     };
 
+    async fn create_app_state() -> (
+        web::Data<ChatServer>,
+        web::Data<Settings>,
+        web::Data<Authenticator>,
+        web::Data<InviteSigner>,
+    ) {
+        let settings = Settings::default();
+        let chat_server = web::Data::new(ChatServer::new(
+            settings.chat.clone(),
+            Box::new(InMemoryChatStore::new()),
+        ));
+        let authenticator = web::Data::new(test_authenticator().await);
+        let invite_signer = web::Data::new(InviteSigner::from_settings(&settings.invites));
+        (
+            chat_server,
+            web::Data::new(settings),
+            authenticator,
+            invite_signer,
+        )
+    }
+
     #[test_log::test(tokio::test)]
     async fn requesting_a_history_for_an_unknown_chat_yields_404() {
-        let chat_server = web::Data::new(ChatServer::new());
-        let app = test::init_service(setup_app(chat_server)).await;
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
         let req = test::TestRequest::get()
             .uri("/history/f48d88c2-efe7-462f-97ca-3b6350e1a1a4")
             .insert_header(Accept::json())
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Bearer {}", token_for(UserId::random())),
+            ))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            Problem::CONTENT_TYPE
+        );
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(problem.code, ErrorCode::ChatNotFound);
+        assert_eq!(problem.status, 404);
+        assert_eq!(
+            problem.problem_type,
+            "urn:web-app-demo:problem:chat_not_found"
+        );
+        assert_eq!(
+            problem.details.chat_id,
+            Some(ChatId::from_uuid(
+                "f48d88c2-efe7-462f-97ca-3b6350e1a1a4".parse().unwrap()
+            ))
+        );
     }
 
     #[test_log::test(tokio::test)]
     async fn requesting_a_history_for_an_unparsable_chat_id_yields_400() {
-        let chat_server = web::Data::new(ChatServer::new());
-        let app = test::init_service(setup_app(chat_server)).await;
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
         let req = test::TestRequest::get()
             .uri("/history/slartibartfass")
             .insert_header(Accept::json())
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Bearer {}", token_for(UserId::random())),
+            ))
             .to_request();
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(problem.code, ErrorCode::MalformedRequest);
     }
 
-    fn create_testserver() -> TestServer {
-        let chat_server = web::Data::new(ChatServer::new());
-        actix_test::start(move || setup_app(chat_server.clone()))
+    #[test_log::test(tokio::test)]
+    async fn failed_requests_are_answered_with_problem_details() {
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/chats")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Bearer {}", token_for(UserId::random())),
+            ))
+            .set_payload(r#"{"name": "#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let first: Problem = test::read_body_json(resp).await;
+        assert_eq!(first.code, ErrorCode::MalformedRequest);
+        assert_eq!(first.title, "Malformed request");
+
+        let req = test::TestRequest::post()
+            .uri("/chats")
+            .set_json(serde_json::json!({"name": "Kaffeeklatsch"}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(resp.headers().contains_key(header::WWW_AUTHENTICATE));
+        let second: Problem = test::read_body_json(resp).await;
+        assert_eq!(second.code, ErrorCode::Unauthenticated);
+        assert_ne!(
+            first.correlation_id, second.correlation_id,
+            "every failure should be told apart in the logs"
+        );
     }
 
-    #[test_log::test(actix_web::test)]
-    async fn connecting_the_websocket_for_an_unknown_chat_succeeds_and_creates_the_chat() {
-        let mut app = create_testserver();
+    #[test_log::test(tokio::test)]
+    async fn a_created_chat_can_be_read_by_its_creator() {
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
+        let token = token_for(UserId::random());
 
-        let chat_id = ChatId::random();
-        let user_id = UserId::random();
+        let req = test::TestRequest::post()
+            .uri("/chats")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .set_json(serde_json::json!({"name": "Kaffeeklatsch", "topic": "Kuchen"}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let created: ChatMetadata = test::read_body_json(resp).await;
+        assert_eq!(
+            created.name,
+            Some(ChatName::new("Kaffeeklatsch".to_string()))
+        );
 
-        let _framed = app
-            .ws_at(&format!("/chat/{chat_id}/{user_id}"))
-            .await
-            .unwrap();
-        let mut history_response = app
-            .get(&format!("/history/{chat_id}"))
-            .insert_header(Accept::json())
-            .send()
-            .await
-            .unwrap();
-        assert_eq!(history_response.status(), StatusCode::OK);
-        let history: Vec<ChatMessage> = history_response.json().await.unwrap();
-        assert_eq!(history, vec![]);
+        let req = test::TestRequest::get()
+            .uri(&format!("/chats/{}", created.chat_id))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .to_request();
+        let read: ChatMetadata = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(read, created);
     }
 
-    fn chat_message_as_ws_text(display_name: String, message: String) -> ws::Message {
-        ws::Message::Text(
-            serde_json::to_string(&IncomingChatMessage {
-                display_name: DisplayName::new(display_name),
-                message: Message::new(message),
-            })
-            .unwrap()
-            .into(),
-        )
+    #[test_log::test(tokio::test)]
+    async fn creating_a_chat_without_a_name_yields_400() {
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/chats")
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Bearer {}", token_for(UserId::random())),
+            ))
+            .set_json(serde_json::json!({"name": "  "}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 
-    #[test_log::test(actix_web::test)]
-    async fn messages_sent_to_a_chat_are_in_the_history() {
-        let mut app = create_testserver();
+    #[test_log::test(tokio::test)]
+    async fn requesting_a_history_without_a_token_yields_401() {
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/history/f48d88c2-efe7-462f-97ca-3b6350e1a1a4")
+            .insert_header(Accept::json())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            resp.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer"
+        );
+    }
 
-        let chat_id = ChatId::random();
-        let user_id = UserId::random();
+    #[test_log::test(tokio::test)]
+    async fn requesting_a_history_with_an_invalid_token_yields_401() {
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        let app = test::init_service(setup_app(
+            chat_server,
+            settings,
+            authenticator,
+            invite_signer,
+        ))
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/history/f48d88c2-efe7-462f-97ca-3b6350e1a1a4")
+            .insert_header(Accept::json())
+            .insert_header((header::AUTHORIZATION, "Bearer slartibartfass"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 
-        let mut framed = app
-            .ws_at(&format!("/chat/{chat_id}/{user_id}"))
-            .await
-            .unwrap();
+    async fn create_testserver() -> TestServer {
+        let (chat_server, settings, authenticator, invite_signer) = create_app_state().await;
+        actix_test::start(move || {
+            setup_app(
+                chat_server.clone(),
+                settings.clone(),
+                authenticator.clone(),
+                invite_signer.clone(),
+            )
+        })
+    }
 
-        framed
-            .send(chat_message_as_ws_text(
-                "Hugo".to_string(),
-                "Nachricht 1".to_string(),
-            ))
-            .await
-            .unwrap();
+    type TestWebSocket = Framed<BoxedSocket, ws::Codec>;
 
-        framed
-            .send(chat_message_as_ws_text(
-                "Hugo".to_string(),
-                "Nachricht 2".to_string(),
-            ))
+    /// Connects to the chat and receives the history and presence sent
+    /// first.
+    async fn join_websocket_with_token(
+        app: &TestServer,
+        chat_id: ChatId,
+        token: String,
+    ) -> (TestWebSocket, HistoryPage) {
+        let (_, mut framed) = awc::Client::new()
+            .ws(app.url(&format!("/chat/{chat_id}")))
+            .bearer_auth(token)
+            .connect()
             .await
             .unwrap();
+        let Outgoing::History { page: history } = receive_outgoing(&mut framed).await else {
+            panic!("the history wasn't sent first");
+        };
+        let Outgoing::Presence { .. } = receive_outgoing(&mut framed).await else {
+            panic!("the presence didn't follow the history");
+        };
+        (framed, history)
+    }
 
-        // reading the messages back, so we can be sure, they should have been added to the
-        // history now.
+    async fn connect_websocket_with_token(
+        app: &TestServer,
+        chat_id: ChatId,
+        token: String,
+    ) -> TestWebSocket {
+        join_websocket_with_token(app, chat_id, token).await.0
+    }
 
-        tokio::time::timeout(Duration::from_millis(100), framed.next())
-            .await
-            .context("sent messages not echoed back")
-            .unwrap()
-            .unwrap()
-            .unwrap();
-        tokio::time::timeout(Duration::from_millis(100), framed.next())
+    async fn connect_websocket(
+        app: &TestServer,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> TestWebSocket {
+        connect_websocket_with_token(app, chat_id, token_for(user_id)).await
+    }
+
+    async fn receive_outgoing(framed: &mut TestWebSocket) -> Outgoing {
+        let frame = tokio::time::timeout(Duration::from_secs(5), framed.next())
             .await
-            .context("sent messages not echoed backe")
-            .unwrap()
+            .expect("no message received")
             .unwrap()
             .unwrap();
+        let Frame::Text(bytes) = frame else {
+            panic!("Didn't receive a text frame but {frame:?}");
+        };
+        serde_json::from_slice(&bytes).unwrap()
+    }
 
+    async fn fetch_history(app: &TestServer, chat_id: ChatId, user_id: UserId) -> Vec<ChatMessage> {
         let mut history_response = app
-            .get(&format!("/history/{chat_id}"))
+            .get(format!("/history/{chat_id}"))
             .insert_header(Accept::json())
+            .bearer_auth(token_for(user_id))
             .send()
             .await
             .unwrap();
-
         assert_eq!(history_response.status(), StatusCode::OK);
-        let history: Vec<ChatMessage> = history_response.json().await.unwrap();
+        let page: HistoryPage = history_response.json().await.unwrap();
+        page.messages
+    }
 
-        let messages: Vec<_> = history.into_iter().map(|cm| cm.message).collect();
-        pretty_assertions::assert_eq!(
-            messages,
-            vec![
-                Message::new("Nachricht 1".to_string()),
-                Message::new("Nachricht 2".to_string())
-            ]
-        )
+    #[test_log::test(actix_web::test)]
+    async fn connecting_the_websocket_without_a_token_yields_401() {
+        let app = create_testserver().await;
+
+        let result = awc::Client::new()
+            .ws(app.url(&format!("/chat/{}", ChatId::random())))
+            .connect()
+            .await;
+        let Err(awc::error::WsClientError::InvalidResponseStatus(status)) = result else {
+            panic!("expected the handshake to be rejected");
+        };
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
     }
 
     #[test_log::test(actix_web::test)]
-    async fn a_message_sent_to_a_chat_will_be_echoed_back() {
-        let mut app = create_testserver();
+    async fn connecting_the_websocket_to_a_chat_of_others_yields_403() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let _owner = connect_websocket(&app, chat_id, UserId::random()).await;
 
+        let result = awc::Client::new()
+            .ws(app.url(&format!("/chat/{chat_id}")))
+            .bearer_auth(token_for(UserId::random()))
+            .connect()
+            .await;
+        let Err(awc::error::WsClientError::InvalidResponseStatus(status)) = result else {
+            panic!("expected the handshake to be rejected");
+        };
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn requesting_the_history_of_a_chat_of_others_yields_403() {
+        let app = create_testserver().await;
         let chat_id = ChatId::random();
-        let user_id = UserId::random();
+        let _owner = connect_websocket(&app, chat_id, UserId::random()).await;
 
-        let mut framed = app
-            .ws_at(&format!("/chat/{chat_id}/{user_id}"))
+        let history_response = app
+            .get(format!("/history/{chat_id}"))
+            .bearer_auth(token_for(UserId::random()))
+            .send()
             .await
             .unwrap();
+        assert_eq!(history_response.status(), StatusCode::FORBIDDEN);
+    }
 
-        framed
-            .send(chat_message_as_ws_text(
-                "Hugo".to_string(),
-                "Nachricht 1".to_string(),
-            ))
+    async fn mint_invite(
+        app: &TestServer,
+        chat_id: ChatId,
+        inviter: UserId,
+        invite: serde_json::Value,
+    ) -> awc::ClientResponse<impl futures::Stream<Item = Result<Bytes, PayloadError>>> {
+        app.post(format!("/chats/{chat_id}/invites"))
+            .bearer_auth(token_for(inviter))
+            .send_json(&invite)
             .await
-            .unwrap();
+            .unwrap()
+    }
 
-        let message = framed.next().await.unwrap().unwrap();
-        let Frame::Text(bytes) = message else {
-            panic!("Didn't receive a text frame");
-        };
-        let message: Outgoing = serde_json::from_slice(&bytes).unwrap();
-        let Outgoing::ChatMessage { msg } = message else {
-            panic!("Didn't receive a chat message");
-        };
-        assert_eq!(msg.message, Message::new("Nachricht 1".to_string()));
+    async fn redeem_invite(
+        app: &TestServer,
+        token: &str,
+        invitee: UserId,
+    ) -> awc::ClientResponse<impl futures::Stream<Item = Result<Bytes, PayloadError>>> {
+        app.post("/invites/redeem")
+            .bearer_auth(token_for(invitee))
+            .send_json(&serde_json::json!({ "token": token }))
+            .await
+            .unwrap()
     }
 
     #[test_log::test(actix_web::test)]
-    async fn a_message_sent_to_a_chat_will_not_be_received_in_a_different_chat() {
-        let mut app = create_testserver();
+    async fn a_redeemed_invite_grants_access_to_the_chat() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let invitee = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
 
-        let chat_id_1: ChatId = ChatId::random();
-        let chat_id_2: ChatId = ChatId::random();
+        let mut response = mint_invite(&app, chat_id, owner, serde_json::json!({})).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let token = signed["token"].as_str().unwrap();
 
-        let user_id: UserId = UserId::random();
+        let mut response = redeem_invite(&app, token, invitee).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let membership: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            membership,
+            serde_json::json!({"chat_id": chat_id, "role": "member"})
+        );
+        assert_eq!(fetch_history(&app, chat_id, invitee).await, vec![]);
+    }
 
-        let mut ws_chat1 = app
-            .ws_at(&format!("/chat/{chat_id_1}/{user_id}"))
-            .await
-            .unwrap();
+    #[test_log::test(actix_web::test)]
+    async fn a_used_up_invite_yields_410() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
 
-        let mut ws_chat2 = app
-            .ws_at(&format!("/chat/{chat_id_2}/{user_id}"))
-            .await
-            .unwrap();
+        let mut response =
+            mint_invite(&app, chat_id, owner, serde_json::json!({"max_uses": 1})).await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let token = signed["token"].as_str().unwrap();
 
-        ws_chat1
-            .send(chat_message_as_ws_text(
-                "Hugo".to_string(),
-                "Chat 1".to_string(),
-            ))
-            .await
+        let response = redeem_invite(&app, token, UserId::random()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = redeem_invite(&app, token, UserId::random()).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn a_forged_invite_yields_400() {
+        let app = create_testserver().await;
+        let response = redeem_invite(&app, "slartibartfass.bistromathics", UserId::random()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn only_members_can_mint_invites_for_roles_they_may_grant() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
+
+        let response = mint_invite(&app, chat_id, UserId::random(), serde_json::json!({})).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut response = mint_invite(
+            &app,
+            chat_id,
+            owner,
+            serde_json::json!({"role": "read_only"}),
+        )
+        .await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let reader = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), reader).await;
+        let response = mint_invite(&app, chat_id, reader, serde_json::json!({})).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn read_only_members_invited_to_a_chat_cannot_post() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
+        let mut response = mint_invite(
+            &app,
+            chat_id,
+            owner,
+            serde_json::json!({"role": "read_only"}),
+        )
+        .await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let reader = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), reader).await;
+
+        let mut framed = connect_websocket(&app, chat_id, reader).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Darf ich?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::Error { code, details, .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected posting to be rejected");
+        };
+        assert_eq!(code, ErrorCode::NotPermitted);
+        assert_eq!(details.chat_id, Some(chat_id));
+        assert_eq!(fetch_history(&app, chat_id, reader).await, vec![]);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn members_see_who_is_connected_and_typing() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let mut owner_ws = connect_websocket(&app, chat_id, owner).await;
+        let mut response = mint_invite(&app, chat_id, owner, serde_json::json!({})).await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let member = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), member).await;
+
+        let mut member_ws = connect_websocket(&app, chat_id, member).await;
+        let Outgoing::MemberJoined { user_id } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the join");
+        };
+        assert_eq!(user_id, member);
+
+        member_ws
+            .send(incoming_as_ws_text(&Incoming::Typing))
+            .await
+            .unwrap();
+        member_ws
+            .send(chat_message_as_ws_text(
+                "Erna".to_string(),
+                "Hallo".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::Typing { user_id } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the typing member");
+        };
+        assert_eq!(user_id, member);
+        let outgoing = receive_outgoing(&mut member_ws).await;
+        assert!(
+            matches!(outgoing, Outgoing::ChatMessage { .. }),
+            "members should not be told about their own typing, but got {outgoing:?}"
+        );
+        let Outgoing::ChatMessage { .. } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the chat message");
+        };
+
+        member_ws.send(ws::Message::Close(None)).await.unwrap();
+        let Outgoing::MemberLeft { user_id } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the leave");
+        };
+        assert_eq!(user_id, member);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn notes_can_be_taken_listed_and_edited_by_their_author() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let author = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, author).await;
+
+        let mut response = app
+            .post(format!("/chats/{chat_id}/notes"))
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"body": "Milch"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let note: Note = response.json().await.unwrap();
+        let Outgoing::NoteCreated { note: pushed } = receive_outgoing(&mut framed).await else {
+            panic!("expected the note to be pushed");
+        };
+        assert_eq!(pushed, note);
+
+        let mut response = app
+            .put(format!("/chats/{chat_id}/notes/{}", note.note_id))
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"body": "Hafermilch"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let edited: Note = response.json().await.unwrap();
+        let Outgoing::NoteUpdated { note: pushed } = receive_outgoing(&mut framed).await else {
+            panic!("expected the edited note to be pushed");
+        };
+        assert_eq!(pushed, edited);
+
+        let mut response = app
+            .get(format!("/chats/{chat_id}/notes"))
+            .bearer_auth(token_for(author))
+            .send()
+            .await
+            .unwrap();
+        let notes: Vec<Note> = response.json().await.unwrap();
+        assert_eq!(notes, vec![edited]);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn editing_a_note_of_another_member_yields_403() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
+        let mut response = mint_invite(&app, chat_id, owner, serde_json::json!({})).await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let member = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), member).await;
+
+        let mut response = app
+            .post(format!("/chats/{chat_id}/notes"))
+            .bearer_auth(token_for(owner))
+            .send_json(&serde_json::json!({"body": "Milch"}))
+            .await
+            .unwrap();
+        let note: Note = response.json().await.unwrap();
+
+        let response = app
+            .put(format!("/chats/{chat_id}/notes/{}", note.note_id))
+            .bearer_auth(token_for(member))
+            .send_json(&serde_json::json!({"body": "Bier"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn the_owner_sets_the_retention_policy_of_a_chat() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
+        let mut response = mint_invite(&app, chat_id, owner, serde_json::json!({})).await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let member = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), member).await;
+        let retention = serde_json::json!({"max_age_secs": 86400, "max_messages": 1000});
+
+        let response = app
+            .put(format!("/chats/{chat_id}/retention"))
+            .bearer_auth(token_for(member))
+            .send_json(&retention)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut response = app
+            .put(format!("/chats/{chat_id}/retention"))
+            .bearer_auth(token_for(owner))
+            .send_json(&retention)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let chat: ChatMetadata = response.json().await.unwrap();
+        assert_eq!(
+            chat.retention,
+            RetentionPolicy {
+                max_age_secs: Some(86400),
+                max_messages: Some(1000),
+                max_bytes: None,
+            }
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn only_trusted_proxies_forward_the_address_of_the_client() {
+        let proxy = SocketAddr::from(([10, 0, 0, 1], 4711));
+        let forwarded = |peer| {
+            test::TestRequest::get()
+                .peer_addr(peer)
+                .insert_header(("X-Forwarded-For", "203.0.113.7, 10.0.0.1"))
+                .to_http_request()
+        };
+        let client = IpAddr::from([203, 0, 113, 7]);
+
+        assert_eq!(client_ip(&forwarded(proxy), &[proxy.ip()]), Some(client));
+        assert_eq!(client_ip(&forwarded(proxy), &[]), Some(proxy.ip()));
+        let other = SocketAddr::from(([198, 51, 100, 2], 4711));
+        assert_eq!(
+            client_ip(&forwarded(other), &[proxy.ip()]),
+            Some(other.ip()),
+            "the headers of others should be ignored"
+        );
+        let without_header = test::TestRequest::get().peer_addr(proxy).to_http_request();
+        assert_eq!(client_ip(&without_header, &[proxy.ip()]), Some(proxy.ip()));
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn the_owner_sets_the_rate_limit_policy_of_a_chat() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let _owner_ws = connect_websocket(&app, chat_id, owner).await;
+        let rate_limit = serde_json::json!({"burst": 5, "slow_mode_secs": 10});
+
+        let response = app
+            .put(format!("/chats/{chat_id}/rate-limit"))
+            .bearer_auth(token_for(UserId::random()))
+            .send_json(&rate_limit)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .put(format!("/chats/{chat_id}/rate-limit"))
+            .bearer_auth(token_for(owner))
+            .send_json(&serde_json::json!({"per_minute": 0}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let mut response = app
+            .put(format!("/chats/{chat_id}/rate-limit"))
+            .bearer_auth(token_for(owner))
+            .send_json(&rate_limit)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let chat: ChatMetadata = response.json().await.unwrap();
+        assert_eq!(
+            chat.rate_limit,
+            RateLimitPolicy {
+                burst: Some(5),
+                per_minute: None,
+                slow_mode_secs: Some(10),
+            }
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn messages_sent_too_fast_are_rejected_until_the_session_is_closed() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        let response = app
+            .put(format!("/chats/{chat_id}/rate-limit"))
+            .bearer_auth(token_for(user_id))
+            .send_json(&serde_json::json!({"slow_mode_secs": 60}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "langsam".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected the first message to be sent");
+        };
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "zu schnell".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::Error { code, details, .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected the second message to be rejected");
+        };
+        assert_eq!(code, ErrorCode::RateLimited);
+        let retry_after_ms = details
+            .retry_after_ms
+            .expect("the error should tell when to retry");
+        assert!(
+            (59_000..=60_000).contains(&retry_after_ms),
+            "unexpected retry after {retry_after_ms} ms"
+        );
+
+        // The default settings tolerate 10 violations in a row.
+        for _ in 0..10 {
+            framed
+                .send(chat_message_as_ws_text(
+                    "Hugo".to_string(),
+                    "immer noch".to_string(),
+                ))
+                .await
+                .unwrap();
+        }
+        let frame = loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), framed.next())
+                .await
+                .expect("session wasn't closed")
+                .unwrap()
+                .unwrap();
+            if !matches!(frame, Frame::Text(_)) {
+                break frame;
+            }
+        };
+        let Frame::Close(Some(close_reason)) = frame else {
+            panic!("expected a close frame but got {frame:?}");
+        };
+        assert_eq!(close_reason.code, CloseCode::Policy);
+        assert_eq!(fetch_history(&app, chat_id, user_id).await.len(), 1);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn invalid_texts_are_rejected_and_valid_ones_normalized() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo\u{202E}".to_string(),
+                "Hallo".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::Error { code, details, .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected the display name to be rejected");
+        };
+        assert_eq!(code, ErrorCode::InvalidText);
+        assert_eq!(
+            details.invalid_text,
+            Some(InvalidText::ForbiddenCharacter {
+                field: TextField::DisplayName,
+                character: '\u{202E}'
+            })
+        );
+
+        framed
+            .send(chat_message_as_ws_text(
+                " Hugo ".to_string(),
+                " Cafe\u{301} ".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("expected the message to be sent");
+        };
+        assert_eq!(sent.display_name, DisplayName::new("Hugo".to_string()));
+        assert_eq!(sent.message, Message::new("Caf\u{e9}".to_string()));
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::EditMessage {
+                event_id: sent.event_id,
+                message: Message::new("\n".to_string()),
+            }))
+            .await
+            .unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(5), framed.next())
+            .await
+            .expect("no message received")
+            .unwrap()
+            .unwrap();
+        let Frame::Text(bytes) = frame else {
+            panic!("Didn't receive a text frame but {frame:?}");
+        };
+        let error: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            error["invalid_text"],
+            serde_json::json!({"reason": "empty", "field": "message"})
+        );
+        assert_eq!(
+            fetch_history(&app, chat_id, user_id).await,
+            vec![sent],
+            "only the valid message should be stored"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn unparsable_requests_over_the_websocket_are_reported_with_a_code() {
+        let app = create_testserver().await;
+        let mut framed = connect_websocket(&app, ChatId::random(), UserId::random()).await;
+
+        framed
+            .send(ws::Message::Text(r#"{"type": "Shout"}"#.into()))
+            .await
+            .unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(5), framed.next())
+            .await
+            .expect("no message received")
+            .unwrap()
+            .unwrap();
+        let Frame::Text(bytes) = frame else {
+            panic!("Didn't receive a text frame but {frame:?}");
+        };
+        let error: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(error["type"], "Error");
+        assert_eq!(error["code"], "malformed_request");
+        assert!(
+            error["correlation_id"].as_str().is_some(),
+            "{error} should have a correlation id"
+        );
+
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "noch da?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected the session to go on");
+        };
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn the_history_is_paged_with_cursors() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        for i in 1..=3 {
+            framed
+                .send(chat_message_as_ws_text(
+                    "Hugo".to_string(),
+                    format!("Nachricht {i}"),
+                ))
+                .await
+                .unwrap();
+            receive_outgoing(&mut framed).await;
+        }
+
+        let mut response = app
+            .get(format!("/history/{chat_id}?limit=2"))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        let latest: HistoryPage = response.json().await.unwrap();
+        assert_eq!(latest.messages.len(), 2);
+        let next_cursor = latest.next_cursor.expect("there should be an older page");
+        assert_eq!(next_cursor, latest.messages[0].event_id);
+
+        let mut response = app
+            .get(format!("/history/{chat_id}?limit=2&before={next_cursor}"))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        let older: HistoryPage = response.json().await.unwrap();
+        let messages: Vec<_> = older.messages.into_iter().map(|m| m.message).collect();
+        assert_eq!(messages, vec![Message::new("Nachricht 1".to_string())]);
+        assert_eq!(older.next_cursor, None);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn requesting_a_history_page_with_two_cursors_yields_400() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let _framed = connect_websocket(&app, chat_id, user_id).await;
+
+        let response = app
+            .get(format!(
+                "/history/{chat_id}?before={}&after={}",
+                EventId::random(),
+                EventId::random()
+            ))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn the_token_can_be_offered_as_websocket_subprotocol() {
+        let app = create_testserver().await;
+
+        let (response, _framed) = awc::Client::new()
+            .ws(app.url(&format!("/chat/{}", ChatId::random())))
+            .protocols(["bearer", &token_for(UserId::random())])
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(
+            response
+                .headers()
+                .get(header::SEC_WEBSOCKET_PROTOCOL)
+                .unwrap(),
+            "bearer"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn connecting_the_websocket_for_an_unknown_chat_succeeds_and_creates_the_chat() {
+        let app = create_testserver().await;
+
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+
+        let _framed = connect_websocket(&app, chat_id, user_id).await;
+        let history = fetch_history(&app, chat_id, user_id).await;
+        assert_eq!(history, vec![]);
+    }
+
+    fn incoming_as_ws_text(incoming: &Incoming) -> ws::Message {
+        ws::Message::Text(serde_json::to_string(incoming).unwrap().into())
+    }
+
+    fn chat_message_as_ws_text(display_name: String, message: String) -> ws::Message {
+        incoming_as_ws_text(&Incoming::ChatMessage(IncomingChatMessage {
+            display_name: DisplayName::new(display_name),
+            message: Message::new(message),
+            in_reply_to: None,
+        }))
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn messages_sent_to_a_chat_are_in_the_history() {
+        let app = create_testserver().await;
+
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Nachricht 1".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Nachricht 2".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        // reading the messages back, so we can be sure, they should have been added to the
+        // history now.
+
+        tokio::time::timeout(Duration::from_millis(100), framed.next())
+            .await
+            .context("sent messages not echoed back")
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        tokio::time::timeout(Duration::from_millis(100), framed.next())
+            .await
+            .context("sent messages not echoed backe")
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let history = fetch_history(&app, chat_id, user_id).await;
+
+        let messages: Vec<_> = history.into_iter().map(|cm| cm.message).collect();
+        pretty_assertions::assert_eq!(
+            messages,
+            vec![
+                Message::new("Nachricht 1".to_string()),
+                Message::new("Nachricht 2".to_string())
+            ]
+        )
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn joining_a_chat_sends_its_history_before_new_messages() {
+        let app = create_testserver().await;
+
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+
+        let mut first = connect_websocket(&app, chat_id, user_id).await;
+        first
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Nachricht 1".to_string(),
+            ))
+            .await
+            .unwrap();
+        receive_outgoing(&mut first).await;
+
+        let (mut second, history) =
+            join_websocket_with_token(&app, chat_id, token_for(user_id)).await;
+        let messages: Vec<_> = history.messages.into_iter().map(|cm| cm.message).collect();
+        pretty_assertions::assert_eq!(messages, vec![Message::new("Nachricht 1".to_string())]);
+
+        first
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Nachricht 2".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg } = receive_outgoing(&mut second).await else {
+            panic!("expected the new message");
+        };
+        assert_eq!(msg.message, Message::new("Nachricht 2".to_string()));
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn reconnecting_since_the_last_received_message_replays_the_missed_ones() {
+        let app = create_testserver().await;
+
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        for text in ["Nachricht 1", "Nachricht 2", "Nachricht 3"] {
+            framed
+                .send(chat_message_as_ws_text(
+                    "Hugo".to_string(),
+                    text.to_string(),
+                ))
+                .await
+                .unwrap();
+        }
+        let Outgoing::ChatMessage { msg: first } = receive_outgoing(&mut framed).await else {
+            panic!("expected the first message");
+        };
+        receive_outgoing(&mut framed).await;
+        receive_outgoing(&mut framed).await;
+        drop(framed);
+
+        let (_, mut resumed) = awc::Client::new()
+            .ws(app.url(&format!("/chat/{chat_id}?since={}", first.sequence)))
+            .bearer_auth(token_for(user_id))
+            .connect()
+            .await
+            .unwrap();
+        let Outgoing::History { page } = receive_outgoing(&mut resumed).await else {
+            panic!("the missed messages weren't sent first");
+        };
+        let replayed: Vec<_> = page
+            .messages
+            .into_iter()
+            .map(|cm| (cm.sequence, cm.message))
+            .collect();
+        pretty_assertions::assert_eq!(
+            replayed,
+            vec![
+                (2, Message::new("Nachricht 2".to_string())),
+                (3, Message::new("Nachricht 3".to_string()))
+            ]
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn messages_are_edited_and_deleted_over_the_websocket() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::EditMessage {
+                event_id: sent.event_id,
+                message: Message::new("Kaffee".to_string()),
+            }))
+            .await
+            .unwrap();
+        let Outgoing::MessageEdited { msg: edited } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the edited message");
+        };
+        assert_eq!(edited.event_id, sent.event_id);
+        assert_eq!(edited.message, Message::new("Kaffee".to_string()));
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::DeleteMessage {
+                event_id: sent.event_id,
+            }))
+            .await
+            .unwrap();
+        let Outgoing::MessageDeleted { msg: tombstone } = receive_outgoing(&mut framed).await
+        else {
+            panic!("Didn't receive the tombstone");
+        };
+        assert!(tombstone.is_deleted());
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::DeleteMessage {
+                event_id: sent.event_id,
+            }))
+            .await
+            .unwrap();
+        let outgoing = receive_outgoing(&mut framed).await;
+        assert!(
+            matches!(outgoing, Outgoing::Error { .. }),
+            "deleting twice should be reported, but got {outgoing:?}"
+        );
+        assert_eq!(fetch_history(&app, chat_id, user_id).await, vec![tombstone]);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn messages_are_edited_and_deleted_by_their_author_over_http() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let author = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, author).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+        let mut response = mint_invite(&app, chat_id, author, serde_json::json!({})).await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let member = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), member).await;
+        let message_url = format!("/chats/{chat_id}/messages/{}", sent.event_id);
+
+        let response = app
+            .put(&message_url)
+            .bearer_auth(token_for(member))
+            .send_json(&serde_json::json!({"message": "fremd"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let mut response = app
+            .put(&message_url)
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"message": "Kaffee"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let edited: ChatMessage = response.json().await.unwrap();
+        assert_eq!(edited.message, Message::new("Kaffee".to_string()));
+
+        let mut response = app
+            .get(format!("{message_url}/revisions"))
+            .bearer_auth(token_for(member))
+            .send()
+            .await
+            .unwrap();
+        let revisions: Vec<MessageRevision> = response.json().await.unwrap();
+        assert_eq!(
+            revisions,
+            vec![MessageRevision {
+                message: Message::new("Tee".to_string()),
+                timestamp: sent.timestamp,
+            }]
+        );
+
+        let response = app
+            .delete(&message_url)
+            .bearer_auth(token_for(author))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .put(&message_url)
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"message": "doch"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let response = app
+            .delete(format!("/chats/{chat_id}/messages/{}", EventId::random()))
+            .bearer_auth(token_for(author))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn invalid_edits_over_http_are_rejected_like_over_the_websocket() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let author = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, author).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+        let message_url = format!("/chats/{chat_id}/messages/{}", sent.event_id);
+
+        let mut response = app
+            .put(&message_url)
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"message": "Kaffee\u{202E}"}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem: Problem = response.json().await.unwrap();
+        assert_eq!(problem.code, ErrorCode::InvalidText);
+        assert_eq!(
+            problem.details.invalid_text,
+            Some(InvalidText::ForbiddenCharacter {
+                field: TextField::Message,
+                character: '\u{202E}'
+            })
+        );
+
+        let mut response = app
+            .put(&message_url)
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"message": "x".repeat(Message::MAX_CHARS + 1)}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem: Problem = response.json().await.unwrap();
+        assert_eq!(
+            problem.details.invalid_text,
+            Some(InvalidText::TooLong {
+                field: TextField::Message,
+                max_chars: Message::MAX_CHARS
+            })
+        );
+
+        let mut response = app
+            .put(&message_url)
+            .bearer_auth(token_for(author))
+            .send_json(&serde_json::json!({"message": " Cafe\u{301} "}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let edited: ChatMessage = response.json().await.unwrap();
+        assert_eq!(edited.message, Message::new("Caf\u{e9}".to_string()));
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::EditMessage {
+                event_id: sent.event_id,
+                message: Message::new("   ".to_string()),
+            }))
+            .await
+            .unwrap();
+        let outgoing = loop {
+            match receive_outgoing(&mut framed).await {
+                Outgoing::MessageEdited { .. } => continue,
+                outgoing => break outgoing,
+            }
+        };
+        let Outgoing::Error { code, details, .. } = outgoing else {
+            panic!("expected the edit to be rejected, but got {outgoing:?}");
+        };
+        assert_eq!(code, ErrorCode::InvalidText);
+        assert_eq!(
+            details.invalid_text,
+            Some(InvalidText::Empty {
+                field: TextField::Message
+            })
+        );
+        assert_eq!(
+            fetch_history(&app, chat_id, author).await[0].message,
+            Message::new("Caf\u{e9}".to_string())
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn reactions_are_added_and_removed_over_the_websocket() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+        let thumbs_up = Emoji::new("👍".to_string());
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::AddReaction {
+                event_id: sent.event_id,
+                emoji: Emoji::new("ja".to_string()),
+            }))
+            .await
+            .unwrap();
+        let outgoing = receive_outgoing(&mut framed).await;
+        assert!(
+            matches!(outgoing, Outgoing::Error { .. }),
+            "words should not be accepted as emojis, but got {outgoing:?}"
+        );
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::AddReaction {
+                event_id: sent.event_id,
+                emoji: thumbs_up.clone(),
+            }))
+            .await
+            .unwrap();
+        let Outgoing::ReactionAdded { reaction } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the added reaction");
+        };
+        let expected = ReactionChange {
+            event_id: sent.event_id,
+            user_id,
+            emoji: thumbs_up,
+        };
+        assert_eq!(reaction, expected);
+        assert_eq!(
+            fetch_history(&app, chat_id, user_id).await[0].reactions,
+            vec![Reaction {
+                emoji: expected.emoji.clone(),
+                count: 1,
+                users: vec![user_id],
+            }]
+        );
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::RemoveReaction {
+                event_id: sent.event_id,
+                emoji: expected.emoji.clone(),
+            }))
+            .await
+            .unwrap();
+        let Outgoing::ReactionRemoved { reaction } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the removed reaction");
+        };
+        assert_eq!(reaction, expected);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn reactions_are_added_and_removed_over_http() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+        // 👍 percent-encoded.
+        let reaction_url = format!(
+            "/chats/{chat_id}/messages/{}/reactions/%F0%9F%91%8D",
+            sent.event_id
+        );
+
+        let response = app
+            .put(&reaction_url)
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let Outgoing::ReactionAdded { reaction } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the added reaction");
+        };
+        assert_eq!(reaction.emoji, Emoji::new("👍".to_string()));
+
+        let response = app
+            .delete(&reaction_url)
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let Outgoing::ReactionRemoved { reaction } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the removed reaction");
+        };
+        assert_eq!(reaction.emoji, Emoji::new("👍".to_string()));
+
+        let response = app
+            .put(format!(
+                "/chats/{chat_id}/messages/{}/reactions/ja",
+                sent.event_id
+            ))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app
+            .put(format!(
+                "/chats/{chat_id}/messages/{}/reactions/%F0%9F%91%8D",
+                EventId::random()
+            ))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn fetch_unread_messages(app: &TestServer, user_id: UserId) -> Vec<UnreadMessages> {
+        let mut response = app
+            .get("/users/me/unread")
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.json().await.unwrap()
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn messages_are_marked_read_over_the_websocket() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let owner = UserId::random();
+        let mut owner_ws = connect_websocket(&app, chat_id, owner).await;
+        owner_ws
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the chat message");
+        };
+        let mut response = mint_invite(&app, chat_id, owner, serde_json::json!({})).await;
+        let signed: serde_json::Value = response.json().await.unwrap();
+        let member = UserId::random();
+        redeem_invite(&app, signed["token"].as_str().unwrap(), member).await;
+        assert_eq!(
+            fetch_unread_messages(&app, member).await,
+            vec![UnreadMessages {
+                chat_id,
+                last_read: None,
+                unread: 1,
+            }]
+        );
+
+        let mut member_ws = connect_websocket(&app, chat_id, member).await;
+        let Outgoing::MemberJoined { .. } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the join");
+        };
+        member_ws
+            .send(incoming_as_ws_text(&Incoming::MarkRead {
+                event_id: EventId::random(),
+            }))
+            .await
+            .unwrap();
+        let outgoing = receive_outgoing(&mut member_ws).await;
+        assert!(
+            matches!(outgoing, Outgoing::Error { .. }),
+            "marking an unknown message read should fail, but got {outgoing:?}"
+        );
+        member_ws
+            .send(incoming_as_ws_text(&Incoming::MarkRead {
+                event_id: sent.event_id,
+            }))
+            .await
+            .unwrap();
+        let Outgoing::ReadUpTo { receipt } = receive_outgoing(&mut owner_ws).await else {
+            panic!("Didn't receive the read receipt");
+        };
+        assert_eq!(
+            receipt,
+            ReadReceipt {
+                user_id: member,
+                event_id: sent.event_id,
+                sequence: sent.sequence,
+            }
+        );
+        assert_eq!(
+            fetch_unread_messages(&app, member).await,
+            vec![UnreadMessages {
+                chat_id,
+                last_read: Some(sent.event_id),
+                unread: 0,
+            }]
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn member_chats_are_listed_with_their_latest_message() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+
+        let mut response = app
+            .get("/users/me/chats")
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let chats: Vec<ChatSummary> = response.json().await.unwrap();
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].metadata.chat_id, chat_id);
+        assert_eq!(chats[0].last_activity, sent.timestamp);
+        assert_eq!(chats[0].last_message, Some(MessagePreview::of(&sent)));
+
+        let response = app.get("/users/me/chats").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn replies_are_sent_over_the_websocket_and_read_as_thread() {
+        let app = create_testserver().await;
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Tee?".to_string(),
+            ))
+            .await
+            .unwrap();
+        let Outgoing::ChatMessage { msg: root } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the chat message");
+        };
+        let reply = |in_reply_to| {
+            incoming_as_ws_text(&Incoming::ChatMessage(IncomingChatMessage {
+                display_name: DisplayName::new("Erna".to_string()),
+                message: Message::new("Gern".to_string()),
+                in_reply_to: Some(in_reply_to),
+            }))
+        };
+
+        framed.send(reply(EventId::random())).await.unwrap();
+        let outgoing = receive_outgoing(&mut framed).await;
+        assert!(
+            matches!(outgoing, Outgoing::Error { .. }),
+            "replying to an unknown message should be reported, but got {outgoing:?}"
+        );
+        framed.send(reply(root.event_id)).await.unwrap();
+        let Outgoing::ChatMessage { msg: sent } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive the reply");
+        };
+        assert_eq!(sent.in_reply_to, Some(root.event_id));
+        assert_eq!(sent.thread_root, Some(root.event_id));
+
+        let mut response = app
+            .get(format!("/chats/{chat_id}/threads/{}", sent.event_id))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let thread: Vec<ChatMessage> = response.json().await.unwrap();
+        assert_eq!(thread, vec![root, sent]);
+        let response = app
+            .get(format!("/chats/{chat_id}/threads/{}", EventId::random()))
+            .bearer_auth(token_for(user_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn a_message_sent_to_a_chat_will_be_echoed_back() {
+        let app = create_testserver().await;
+
+        let chat_id = ChatId::random();
+        let user_id = UserId::random();
+
+        let mut framed = connect_websocket(&app, chat_id, user_id).await;
+
+        framed
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Nachricht 1".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let Outgoing::ChatMessage { msg } = receive_outgoing(&mut framed).await else {
+            panic!("Didn't receive a chat message");
+        };
+        assert_eq!(msg.message, Message::new("Nachricht 1".to_string()));
+        assert_eq!(
+            msg.user_id, user_id,
+            "the sender should be taken from the token"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn a_message_sent_to_a_chat_will_not_be_received_in_a_different_chat() {
+        let app = create_testserver().await;
+
+        let chat_id_1: ChatId = ChatId::random();
+        let chat_id_2: ChatId = ChatId::random();
+
+        let user_id: UserId = UserId::random();
+
+        let mut ws_chat1 = connect_websocket(&app, chat_id_1, user_id).await;
+
+        let mut ws_chat2 = connect_websocket(&app, chat_id_2, user_id).await;
+
+        ws_chat1
+            .send(chat_message_as_ws_text(
+                "Hugo".to_string(),
+                "Chat 1".to_string(),
+            ))
+            .await
             .unwrap();
 
         assert!(
@@ -507,4 +3342,185 @@ mod tests {
             "didn't expect to receive a message in second chat"
         );
     }
+
+    #[test_log::test(actix_web::test)]
+    async fn a_session_is_closed_when_its_token_expires() {
+        let app = create_testserver().await;
+        let expires_at = Utc::now() + chrono::Duration::seconds(2);
+        let mut framed = connect_websocket_with_token(
+            &app,
+            ChatId::random(),
+            token_expiring_at(UserId::random(), expires_at),
+        )
+        .await;
+
+        let Outgoing::TokenExpiring { .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected a warning about the expiring token");
+        };
+        let frame = tokio::time::timeout(Duration::from_secs(5), framed.next())
+            .await
+            .expect("session wasn't closed")
+            .unwrap()
+            .unwrap();
+        let Frame::Close(Some(close_reason)) = frame else {
+            panic!("expected a close frame but got {frame:?}");
+        };
+        assert_eq!(
+            close_reason.code,
+            CloseCode::Other(TOKEN_EXPIRED_CLOSE_CODE)
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn a_fresh_token_keeps_the_session_open() {
+        let app = create_testserver().await;
+        let user_id = UserId::random();
+        let expires_at = Utc::now() + chrono::Duration::seconds(2);
+        let mut framed = connect_websocket_with_token(
+            &app,
+            ChatId::random(),
+            token_expiring_at(user_id, expires_at),
+        )
+        .await;
+        let Outgoing::TokenExpiring { .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected a warning about the expiring token");
+        };
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::Reauthenticate {
+                token: token_for(user_id),
+            }))
+            .await
+            .unwrap();
+        let Outgoing::Reauthenticated {
+            expires_at: fresh_expires_at,
+        } = receive_outgoing(&mut framed).await
+        else {
+            panic!("expected the fresh token to be accepted");
+        };
+        assert!(fresh_expires_at > expires_at);
+
+        assert!(
+            tokio::time::timeout(Duration::from_secs(3), framed.next())
+                .await
+                .is_err(),
+            "didn't expect the session to be closed"
+        );
+    }
+
+    #[test_log::test(actix_web::test)]
+    async fn a_token_of_another_user_is_rejected_for_reauthentication() {
+        let app = create_testserver().await;
+        let mut framed = connect_websocket(&app, ChatId::random(), UserId::random()).await;
+
+        framed
+            .send(incoming_as_ws_text(&Incoming::Reauthenticate {
+                token: token_for(UserId::random()),
+            }))
+            .await
+            .unwrap();
+        let Outgoing::Error { code, .. } = receive_outgoing(&mut framed).await else {
+            panic!("expected the token to be rejected");
+        };
+        assert_eq!(code, ErrorCode::Unauthenticated);
+    }
+
+    async fn chat_server_with_messages(chat_id: ChatId, messages: usize) -> ChatServer {
+        let chat_server =
+            ChatServer::new(ChatSettings::default(), Box::new(InMemoryChatStore::new()));
+        let user_id = UserId::random();
+        chat_server
+            .store()
+            .create_chat(&ChatMetadata::unnamed(
+                chat_id,
+                user_id,
+                ChatTimestamp::now(),
+            ))
+            .await
+            .unwrap();
+        for i in 1..=messages {
+            chat_server
+                .send_message(ChatMessage {
+                    event_id: EventId::random(),
+                    timestamp: ChatTimestamp::now(),
+                    chat_id,
+                    sequence: 0,
+                    user_id,
+                    display_name: DisplayName::new("Hugo".to_string()),
+                    message: Message::new(format!("Nachricht {i}")),
+                    in_reply_to: None,
+                    thread_root: None,
+                    edited_at: None,
+                    deleted_at: None,
+                    reactions: Vec::new(),
+                })
+                .await
+                .unwrap();
+        }
+        chat_server
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn a_lagging_session_catches_up_with_the_skipped_messages() {
+        let chat_id = ChatId::random();
+        let chat_server = chat_server_with_messages(chat_id, 5).await;
+
+        let mut last_sequence = 2;
+        let outgoing = catch_up(&chat_server, chat_id, &mut last_sequence, false)
+            .await
+            .unwrap();
+        let sequences: Vec<_> = outgoing
+            .iter()
+            .map(|outgoing| match outgoing {
+                Outgoing::ChatMessage { msg } => msg.sequence,
+                other => panic!("expected a chat message but got {other:?}"),
+            })
+            .collect();
+        assert_eq!(sequences, vec![3, 4, 5]);
+        assert_eq!(last_sequence, 5);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn a_session_skipping_more_than_a_page_resyncs() {
+        let chat_id = ChatId::random();
+        let chat_server = chat_server_with_messages(chat_id, MAX_HISTORY_LIMIT + 2).await;
+
+        let mut last_sequence = 1;
+        let outgoing = catch_up(&chat_server, chat_id, &mut last_sequence, false)
+            .await
+            .unwrap();
+        let [Outgoing::Resync { page }] = outgoing.as_slice() else {
+            panic!("expected a resync but got {outgoing:?}");
+        };
+        assert_eq!(page.messages.len(), MAX_HISTORY_LIMIT);
+        assert_eq!(last_sequence, (MAX_HISTORY_LIMIT + 2) as u64);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn a_session_which_may_have_skipped_amendments_resyncs() {
+        let chat_id = ChatId::random();
+        let chat_server = chat_server_with_messages(chat_id, 5).await;
+        let first = chat_server
+            .read_latest_messages(chat_id)
+            .await
+            .unwrap()
+            .messages[0]
+            .clone();
+        let tombstone = chat_server
+            .delete_message(chat_id, first.event_id, first.user_id)
+            .await
+            .unwrap();
+
+        // Caught up with the messages, but not the deletion.
+        let mut last_sequence = 5;
+        let outgoing = catch_up(&chat_server, chat_id, &mut last_sequence, true)
+            .await
+            .unwrap();
+        let [Outgoing::Resync { page }] = outgoing.as_slice() else {
+            panic!("expected a resync but got {outgoing:?}");
+        };
+        assert_eq!(page.messages.len(), 5);
+        assert_eq!(page.messages[0], tombstone);
+        assert_eq!(last_sequence, 5);
+    }
 }
@@ -0,0 +1,863 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use actix_web::http::Uri;
+use figment::{
+    Figment,
+    providers::{Env, Format, Serialized, Toml},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    chat::models::{RateLimitPolicy, RetentionPolicy},
+    util::secret::Secret,
+};
+
+/// Environment variable pointing to the configuration file.
+pub const CONFIG_FILE_ENV_VAR: &str = "WEB_APP_DEMO_CONFIG";
+
+/// Used if `WEB_APP_DEMO_CONFIG` isn't set. A missing file is fine, the
+/// defaults and the environment variables are used then.
+pub const DEFAULT_CONFIG_FILE: &str = "web-app-demo.toml";
+
+/// Prefix of the environment variables overriding settings from the
+/// configuration file. Nested keys are separated by a double underscore,
+/// e.g. `WEB_APP_DEMO_SERVER__PORT=9090`.
+pub const ENV_PREFIX: &str = "WEB_APP_DEMO_";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub chat: ChatSettings,
+    pub websocket: WebSocketSettings,
+    pub storage: StorageSettings,
+    pub auth: AuthSettings,
+    pub invites: InviteSettings,
+    pub retention: RetentionSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub cors: CorsSettings,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            cors: CorsSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsSettings {
+    /// Origins allowed to access the backend, like `https://chat.example.com`.
+    /// A single `"*"` allows every origin, which is only sensible during development.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatSettings {
+    /// Capacity of the broadcast channel of every chat. Receivers lagging
+    /// behind more than this many messages miss messages.
+    pub broadcast_capacity: usize,
+    /// Whether joining an unknown chat creates it. If disabled, chats have to
+    /// be created with `POST /chats`.
+    pub implicit_creation: bool,
+    pub rate_limits: RateLimitSettings,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            broadcast_capacity: 16,
+            implicit_creation: true,
+            rate_limits: RateLimitSettings::default(),
+        }
+    }
+}
+
+/// Limits how fast messages may be sent, e.g.
+///
+/// ```toml
+/// [chat.rate_limits.defaults]
+/// slow_mode_secs = 10
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitSettings {
+    /// Limits of every member of a chat, unless the chat overrides them.
+    pub defaults: RateLimitPolicy,
+    /// Limits every websocket connection, whichever chat it is for.
+    pub per_connection: RateLimit,
+    /// Limits the edits, deletions, reactions and read receipts of every
+    /// websocket connection.
+    pub per_connection_events: RateLimit,
+    /// Typing indicators of a member are passed on at most once per this
+    /// many seconds.
+    pub typing_interval_secs: u64,
+    /// Limits all connections from the same IP address together.
+    pub per_ip: RateLimit,
+    /// Addresses of the reverse proxies in front of the backend. Connections
+    /// through them are limited by the client address they forward with a
+    /// `Forwarded` or `X-Forwarded-For` header, all others by the address
+    /// they come from.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Connections exceeding this rate of rejected messages are closed.
+    pub violations: RateLimit,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            defaults: RateLimitPolicy {
+                burst: Some(10),
+                per_minute: Some(60),
+                slow_mode_secs: None,
+            },
+            per_connection: RateLimit {
+                burst: 20,
+                per_minute: 120,
+            },
+            per_connection_events: RateLimit {
+                burst: 30,
+                per_minute: 120,
+            },
+            typing_interval_secs: 3,
+            per_ip: RateLimit {
+                burst: 50,
+                per_minute: 600,
+            },
+            trusted_proxies: Vec::new(),
+            violations: RateLimit {
+                burst: 10,
+                per_minute: 10,
+            },
+        }
+    }
+}
+
+/// A token bucket holding `burst` tokens, refilled with `per_minute` tokens
+/// per minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebSocketSettings {
+    /// Maximum size in bytes of a message assembled from continuation frames.
+    pub max_continuation_size: usize,
+    /// How many seconds before the access token of a websocket session
+    /// expires, the client is asked to send a fresh one.
+    pub token_expiry_warning_secs: u64,
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> Self {
+        Self {
+            max_continuation_size: 2usize.pow(22),
+            token_expiry_warning_secs: 60,
+        }
+    }
+}
+
+/// Bearer tokens are validated against the keys of an OpenID Connect
+/// provider like keycloak.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSettings {
+    /// Expected `iss` claim, e.g. `https://keycloak.example.com/realms/web-app-demo`.
+    pub issuer: String,
+    /// Expected `aud` claim, not checked if unset.
+    pub audience: Option<String>,
+    /// Reads the JWKS from a file instead of fetching it, e.g. for tests.
+    pub jwks_file: Option<PathBuf>,
+    /// Fetches the JWKS from here. If neither this nor `jwks_file` is set,
+    /// the `jwks_uri` of the discovery document of the issuer is used.
+    pub jwks_url: Option<String>,
+    /// Tolerated clock skew in seconds when checking the expiry.
+    pub leeway_secs: u64,
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            issuer: "http://localhost:8180/realms/web-app-demo".to_string(),
+            audience: None,
+            jwks_file: None,
+            jwks_url: None,
+            leeway_secs: 30,
+        }
+    }
+}
+
+/// Invite links carry a token signed by the backend, granting a role in a
+/// chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InviteSettings {
+    /// Key the invite tokens are signed with, at least 32 bytes long. If
+    /// unset, a random key is generated on startup, so invites are only
+    /// valid until the backend restarts.
+    pub secret: Option<Secret<String>>,
+    /// Validity of an invite in seconds, if the inviter doesn't ask for less.
+    pub max_ttl_secs: u64,
+}
+
+impl Default for InviteSettings {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            max_ttl_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Limits how much of the chat histories is kept, e.g.
+///
+/// ```toml
+/// [retention.defaults]
+/// max_age_secs = 2592000
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionSettings {
+    /// Limits of every chat, unless the chat overrides them.
+    pub defaults: RetentionPolicy,
+    /// Seconds between pruning the histories.
+    pub prune_interval_secs: u64,
+    /// Chats nobody used, which never had a message and have no notes and no
+    /// members but their creator, are evicted once they are this many
+    /// seconds old and nobody is connected to them. Never if unset.
+    pub evict_empty_chats_after_secs: Option<u64>,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            defaults: RetentionPolicy::default(),
+            prune_interval_secs: 60,
+            evict_empty_chats_after_secs: None,
+        }
+    }
+}
+
+/// Selects where the chat histories are stored, e.g.
+///
+/// ```toml
+/// [storage]
+/// type = "postgres"
+/// url = "postgres://web-app-demo@localhost/web-app-demo"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StorageSettings {
+    /// Histories are lost on restart.
+    #[default]
+    Memory,
+    Postgres(PostgresSettings),
+    Sqlite(SqliteSettings),
+    Journal(JournalSettings),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresSettings {
+    pub url: Secret<String>,
+    #[serde(default = "PostgresSettings::default_max_connections")]
+    pub max_connections: u32,
+}
+
+impl PostgresSettings {
+    fn default_max_connections() -> u32 {
+        10
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqliteSettings {
+    /// The database file, created if missing.
+    pub path: PathBuf,
+    #[serde(default = "SqliteSettings::default_max_connections")]
+    pub max_connections: u32,
+}
+
+impl SqliteSettings {
+    fn default_max_connections() -> u32 {
+        4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalSettings {
+    /// Directory of the journal segments, created if missing.
+    pub directory: PathBuf,
+    /// Size in bytes after which a new segment is started.
+    #[serde(default = "JournalSettings::default_segment_size")]
+    pub segment_size: u64,
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+    /// Used with `fsync = "periodic"`.
+    #[serde(default = "JournalSettings::default_fsync_interval_ms")]
+    pub fsync_interval_ms: u64,
+}
+
+impl JournalSettings {
+    fn default_segment_size() -> u64 {
+        64 * 1024 * 1024
+    }
+
+    fn default_fsync_interval_ms() -> u64 {
+        1000
+    }
+}
+
+/// When the journal is flushed to disk. Whatever wasn't flushed is lost
+/// if the machine, not just the backend, crashes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// After every record, before it is acknowledged.
+    #[default]
+    Always,
+    /// Every `fsync_interval_ms` milliseconds.
+    Periodic,
+    /// Whenever the operating system decides to.
+    Never,
+}
+
+#[derive(Debug, Error)]
+pub enum SettingsErrors {
+    #[error("failed to load settings: {0}")]
+    Load(#[from] Box<figment::Error>),
+
+    #[error("invalid setting `{key}`: {reason}")]
+    Invalid { key: &'static str, reason: String },
+}
+
+impl SettingsErrors {
+    fn invalid(key: &'static str, reason: impl Into<String>) -> SettingsErrors {
+        SettingsErrors::Invalid {
+            key,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings from the configuration file and the environment, the
+    /// latter taking precedence, and validates them.
+    pub fn load() -> Result<Settings, SettingsErrors> {
+        let config_file = std::env::var_os(CONFIG_FILE_ENV_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+        Self::from_figment(&Self::figment(config_file))
+    }
+
+    fn from_figment(figment: &Figment) -> Result<Settings, SettingsErrors> {
+        let settings: Settings = figment.extract().map_err(Box::new)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn figment(config_file: PathBuf) -> Figment {
+        Figment::from(Serialized::defaults(Settings::default()))
+            .merge(Toml::file(config_file))
+            .merge(Env::prefixed(ENV_PREFIX).ignore(&["CONFIG"]).split("__"))
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsErrors> {
+        if self.server.host.trim().is_empty() {
+            return Err(SettingsErrors::invalid("server.host", "must not be empty"));
+        }
+        if self.server.port == 0 {
+            return Err(SettingsErrors::invalid("server.port", "must not be 0"));
+        }
+        self.server.cors.validate()?;
+        if self.chat.broadcast_capacity == 0 || self.chat.broadcast_capacity > usize::MAX / 2 {
+            return Err(SettingsErrors::invalid(
+                "chat.broadcast_capacity",
+                format!("must be between 1 and {}", usize::MAX / 2),
+            ));
+        }
+        self.chat.rate_limits.validate()?;
+        if self.websocket.max_continuation_size == 0 {
+            return Err(SettingsErrors::invalid(
+                "websocket.max_continuation_size",
+                "must not be 0",
+            ));
+        }
+        self.storage.validate()?;
+        self.auth.validate()?;
+        self.invites.validate()?;
+        if self.retention.prune_interval_secs == 0 {
+            return Err(SettingsErrors::invalid(
+                "retention.prune_interval_secs",
+                "must not be 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Shorter keys weaken the HMAC-SHA256 signatures of the invites.
+const MIN_INVITE_SECRET_LENGTH: usize = 32;
+
+impl InviteSettings {
+    fn validate(&self) -> Result<(), SettingsErrors> {
+        if let Some(secret) = &self.secret
+            && secret.expose().len() < MIN_INVITE_SECRET_LENGTH
+        {
+            return Err(SettingsErrors::invalid(
+                "invites.secret",
+                format!("must be at least {MIN_INVITE_SECRET_LENGTH} bytes long"),
+            ));
+        }
+        if self.max_ttl_secs == 0 {
+            return Err(SettingsErrors::invalid(
+                "invites.max_ttl_secs",
+                "must not be 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RateLimitSettings {
+    fn validate(&self) -> Result<(), SettingsErrors> {
+        let defaults = &self.defaults;
+        for (key, value) in [
+            ("chat.rate_limits.defaults.burst", defaults.burst),
+            ("chat.rate_limits.defaults.per_minute", defaults.per_minute),
+            (
+                "chat.rate_limits.per_connection.burst",
+                Some(self.per_connection.burst),
+            ),
+            (
+                "chat.rate_limits.per_connection.per_minute",
+                Some(self.per_connection.per_minute),
+            ),
+            (
+                "chat.rate_limits.per_connection_events.burst",
+                Some(self.per_connection_events.burst),
+            ),
+            (
+                "chat.rate_limits.per_connection_events.per_minute",
+                Some(self.per_connection_events.per_minute),
+            ),
+            ("chat.rate_limits.per_ip.burst", Some(self.per_ip.burst)),
+            (
+                "chat.rate_limits.per_ip.per_minute",
+                Some(self.per_ip.per_minute),
+            ),
+            (
+                "chat.rate_limits.violations.burst",
+                Some(self.violations.burst),
+            ),
+            (
+                "chat.rate_limits.violations.per_minute",
+                Some(self.violations.per_minute),
+            ),
+        ] {
+            if value == Some(0) {
+                return Err(SettingsErrors::invalid(key, "must not be 0"));
+            }
+        }
+        if self.typing_interval_secs == 0 {
+            return Err(SettingsErrors::invalid(
+                "chat.rate_limits.typing_interval_secs",
+                "must not be 0",
+            ));
+        }
+        if defaults.slow_mode_secs == Some(0) {
+            return Err(SettingsErrors::invalid(
+                "chat.rate_limits.defaults.slow_mode_secs",
+                "must not be 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AuthSettings {
+    fn validate(&self) -> Result<(), SettingsErrors> {
+        if self.issuer.trim().is_empty() {
+            return Err(SettingsErrors::invalid("auth.issuer", "must not be empty"));
+        }
+        if self.jwks_file.is_some() && self.jwks_url.is_some() {
+            return Err(SettingsErrors::invalid(
+                "auth.jwks_file",
+                "must not be combined with auth.jwks_url",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl StorageSettings {
+    fn validate(&self) -> Result<(), SettingsErrors> {
+        match self {
+            StorageSettings::Memory => {}
+            StorageSettings::Postgres(postgres) => {
+                if postgres.url.expose().trim().is_empty() {
+                    return Err(SettingsErrors::invalid("storage.url", "must not be empty"));
+                }
+                if postgres.max_connections == 0 {
+                    return Err(SettingsErrors::invalid(
+                        "storage.max_connections",
+                        "must not be 0",
+                    ));
+                }
+            }
+            StorageSettings::Sqlite(sqlite) => {
+                if sqlite.path.as_os_str().is_empty() {
+                    return Err(SettingsErrors::invalid("storage.path", "must not be empty"));
+                }
+                if sqlite.max_connections == 0 {
+                    return Err(SettingsErrors::invalid(
+                        "storage.max_connections",
+                        "must not be 0",
+                    ));
+                }
+            }
+            StorageSettings::Journal(journal) => {
+                if journal.directory.as_os_str().is_empty() {
+                    return Err(SettingsErrors::invalid(
+                        "storage.directory",
+                        "must not be empty",
+                    ));
+                }
+                if journal.segment_size == 0 {
+                    return Err(SettingsErrors::invalid(
+                        "storage.segment_size",
+                        "must not be 0",
+                    ));
+                }
+                if journal.fsync == FsyncPolicy::Periodic && journal.fsync_interval_ms == 0 {
+                    return Err(SettingsErrors::invalid(
+                        "storage.fsync_interval_ms",
+                        "must not be 0",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CorsSettings {
+    pub fn is_permissive(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    fn validate(&self) -> Result<(), SettingsErrors> {
+        const KEY: &str = "server.cors.allowed_origins";
+        if self.allowed_origins.is_empty() {
+            return Err(SettingsErrors::invalid(KEY, "must not be empty"));
+        }
+        if self.is_permissive() {
+            if self.allowed_origins.len() > 1 {
+                return Err(SettingsErrors::invalid(
+                    KEY,
+                    "\"*\" must not be combined with other origins",
+                ));
+            }
+            return Ok(());
+        }
+        for origin in &self.allowed_origins {
+            let uri: Uri = origin
+                .parse()
+                .map_err(|err| SettingsErrors::invalid(KEY, format!("{origin}: {err}")))?;
+            let valid = matches!(uri.scheme_str(), Some("http") | Some("https"))
+                && uri.authority().is_some()
+                && uri.path_and_query().is_none_or(|pq| pq.as_str() == "/")
+                && !origin.ends_with('/');
+            if !valid {
+                return Err(SettingsErrors::invalid(
+                    KEY,
+                    format!("{origin} is not an origin like https://example.com"),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// `figment::Jail` dictates the closures to return its large error type.
+#[cfg(test)]
+#[allow(clippy::result_large_err)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use figment::Jail;
+
+    use super::*;
+
+    fn load_in_jail() -> Result<Settings, SettingsErrors> {
+        Settings::from_figment(&Settings::figment(PathBuf::from(DEFAULT_CONFIG_FILE)))
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        Settings::default()
+            .validate()
+            .expect("the default settings should be valid");
+    }
+
+    #[test]
+    fn settings_are_read_from_the_file_and_overridden_by_the_environment() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                DEFAULT_CONFIG_FILE,
+                r#"
+                [server]
+                host = "0.0.0.0"
+                port = 9000
+
+                [server.cors]
+                allowed_origins = ["https://chat.example.com"]
+
+                [chat]
+                broadcast_capacity = 64
+                "#,
+            )?;
+            jail.set_env("WEB_APP_DEMO_SERVER__PORT", "9090");
+            jail.set_env("WEB_APP_DEMO_WEBSOCKET__MAX_CONTINUATION_SIZE", "1024");
+
+            let settings = load_in_jail().expect("settings should load");
+            pretty_assertions::assert_eq!(
+                settings,
+                Settings {
+                    server: ServerSettings {
+                        host: "0.0.0.0".to_string(),
+                        port: 9090,
+                        cors: CorsSettings {
+                            allowed_origins: vec!["https://chat.example.com".to_string()],
+                        },
+                    },
+                    chat: ChatSettings {
+                        broadcast_capacity: 64,
+                        implicit_creation: true,
+                        rate_limits: RateLimitSettings::default(),
+                    },
+                    websocket: WebSocketSettings {
+                        max_continuation_size: 1024,
+                        token_expiry_warning_secs: 60,
+                    },
+                    storage: StorageSettings::Memory,
+                    auth: AuthSettings::default(),
+                    invites: InviteSettings::default(),
+                    retention: RetentionSettings::default(),
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn a_missing_config_file_yields_the_defaults() {
+        Jail::expect_with(|_| {
+            let settings = load_in_jail().expect("settings should load");
+            assert_eq!(settings, Settings::default());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        Jail::expect_with(|jail| {
+            jail.create_file(DEFAULT_CONFIG_FILE, "[server]\nhots = \"0.0.0.0\"\n")?;
+            assert!(
+                matches!(load_in_jail(), Err(SettingsErrors::Load(_))),
+                "a typo in a key should be reported"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn a_broadcast_capacity_of_zero_is_rejected() {
+        Jail::expect_with(|jail| {
+            jail.set_env("WEB_APP_DEMO_CHAT__BROADCAST_CAPACITY", "0");
+            assert!(
+                matches!(
+                    load_in_jail(),
+                    Err(SettingsErrors::Invalid {
+                        key: "chat.broadcast_capacity",
+                        ..
+                    })
+                ),
+                "a broadcast capacity of 0 should be rejected"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn a_short_invite_secret_is_rejected() {
+        Jail::expect_with(|jail| {
+            jail.set_env("WEB_APP_DEMO_INVITES__SECRET", "geheim");
+            assert!(
+                matches!(
+                    load_in_jail(),
+                    Err(SettingsErrors::Invalid {
+                        key: "invites.secret",
+                        ..
+                    })
+                ),
+                "a short invite secret should be rejected"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn retention_defaults_are_read_from_the_file() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                DEFAULT_CONFIG_FILE,
+                r#"
+                [retention]
+                evict_empty_chats_after_secs = 3600
+
+                [retention.defaults]
+                max_age_secs = 86400
+                max_messages = 1000
+                "#,
+            )?;
+            let settings = load_in_jail().expect("settings should load");
+            assert_eq!(
+                settings.retention,
+                RetentionSettings {
+                    defaults: RetentionPolicy {
+                        max_age_secs: Some(86400),
+                        max_messages: Some(1000),
+                        max_bytes: None,
+                    },
+                    prune_interval_secs: 60,
+                    evict_empty_chats_after_secs: Some(3600),
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn rate_limits_are_read_from_the_file_and_validated() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                DEFAULT_CONFIG_FILE,
+                r#"
+                [chat.rate_limits.defaults]
+                slow_mode_secs = 30
+
+                [chat.rate_limits]
+                trusted_proxies = ["10.0.0.1", "::1"]
+
+                [chat.rate_limits.per_ip]
+                burst = 5
+                per_minute = 10
+                "#,
+            )?;
+            let settings = load_in_jail().expect("settings should load");
+            let rate_limits = settings.chat.rate_limits;
+            assert_eq!(rate_limits.defaults.slow_mode_secs, Some(30));
+            assert_eq!(
+                rate_limits.defaults.burst,
+                RateLimitSettings::default().defaults.burst,
+                "unset limits should keep their defaults"
+            );
+            assert_eq!(
+                rate_limits.per_ip,
+                RateLimit {
+                    burst: 5,
+                    per_minute: 10,
+                }
+            );
+            assert_eq!(
+                rate_limits.trusted_proxies,
+                [
+                    IpAddr::from([10, 0, 0, 1]),
+                    IpAddr::from(Ipv6Addr::LOCALHOST)
+                ]
+            );
+
+            jail.set_env(
+                "WEB_APP_DEMO_CHAT__RATE_LIMITS__PER_CONNECTION__PER_MINUTE",
+                "0",
+            );
+            assert!(
+                matches!(
+                    load_in_jail(),
+                    Err(SettingsErrors::Invalid {
+                        key: "chat.rate_limits.per_connection.per_minute",
+                        ..
+                    })
+                ),
+                "a rate of 0 should be rejected"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn the_storage_can_be_selected_from_the_environment() {
+        Jail::expect_with(|jail| {
+            jail.set_env("WEB_APP_DEMO_STORAGE__TYPE", "postgres");
+            jail.set_env("WEB_APP_DEMO_STORAGE__URL", "postgres://localhost/chat");
+            let settings = load_in_jail().expect("settings should load");
+            assert_eq!(
+                settings.storage,
+                StorageSettings::Postgres(PostgresSettings {
+                    url: Secret::new("postgres://localhost/chat".to_string()),
+                    max_connections: 10,
+                })
+            );
+            assert!(
+                !format!("{settings:?}").contains("postgres://localhost/chat"),
+                "the database url might contain a password and must not be logged"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn malformed_cors_origins_are_rejected() {
+        for origins in [
+            vec![],
+            vec!["*".to_string(), "https://example.com".to_string()],
+            vec!["example.com".to_string()],
+            vec!["https://example.com/chat".to_string()],
+        ] {
+            let cors = CorsSettings {
+                allowed_origins: origins.clone(),
+            };
+            assert!(
+                cors.validate().is_err(),
+                "{origins:?} should be rejected as allowed origins"
+            );
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+// Keeps secrets like passwords or database urls out of log messages, as
+// the `Debug` implementation doesn't reveal the wrapped value.
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+#[allow(dead_code)]
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret([redacted])")
+    }
+}
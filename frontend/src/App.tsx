@@ -97,6 +97,7 @@ function ChatClient({ chat, identity }: ChatProps) {
       messages={chatClient.messages}
       identity={identity}
       chat={chat}
+      serverError={chatClient.serverError}
       height="100%"
       marginX="size-300"
       onSend={chatClient.send}
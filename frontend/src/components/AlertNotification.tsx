@@ -1,16 +1,67 @@
 import { Flex, InlineAlert, Text } from "@adobe/react-spectrum";
 import Alert from "@spectrum-icons/workflow/Alert";
+import { ServerError } from "../util/chatClient";
+import { ensureNever } from "../util/util";
 
 export interface AlertNotificationProps {
   msg: string;
+  // Refines the message by what went wrong on the server, if it did.
+  error?: ServerError;
 }
 export function AlertNotification(props: AlertNotificationProps) {
+  const { variant, text } = describe(props.msg, props.error);
   return (
-    <InlineAlert>
+    <InlineAlert variant={variant}>
       <Flex direction="row" alignItems="end" gap="size-200">
-        <Alert color="negative" />
-        <Text>{props.msg}</Text>
+        <Alert color={variant} />
+        <Text>{text}</Text>
       </Flex>
     </InlineAlert>
   );
 }
+
+interface Description {
+  variant: "notice" | "negative";
+  text: string;
+}
+
+// Failures the user can fix are notices, the others are negative.
+function describe(msg: string, error?: ServerError): Description {
+  if (error == null) {
+    return { variant: "negative", text: msg };
+  }
+  switch (error.code) {
+    case "rate_limited": {
+      const seconds = Math.ceil((error.retry_after_ms ?? 0) / 1000);
+      return {
+        variant: "notice",
+        text: `Sending too fast, please wait ${seconds} seconds.`,
+      };
+    }
+    case "invalid_text":
+    case "invalid_input":
+    case "chat_not_found":
+    case "note_not_found":
+    case "message_not_found":
+    case "message_deleted":
+    case "invite_unusable":
+      return { variant: "notice", text: error.msg };
+    case "not_permitted":
+    case "not_a_member":
+      return {
+        variant: "negative",
+        text: `Not permitted in this chat: ${error.msg}`,
+      };
+    case "unauthenticated":
+      return { variant: "negative", text: "Please log in again." };
+    case "internal_error":
+    case "malformed_request":
+      return {
+        variant: "negative",
+        text: `${msg} Please report the error ${error.correlation_id}.`,
+      };
+    default:
+      ensureNever(error.code);
+      return { variant: "negative", text: msg };
+  }
+}
@@ -8,11 +8,12 @@ import {
   TooltipTrigger,
 } from "@adobe/react-spectrum";
 import { Identity } from "../models/Identity";
-import { ChatMessage } from "../util/chatClient";
+import { ChatMessage, ServerError } from "../util/chatClient";
 import { DOMRef, StyleProps } from "@react-types/shared";
 import { Chat } from "../models/Chat";
 import { ChatMessagesView } from "./ChatMessagesView";
 import { SendChatMessageForm } from "./SendChatMessageForm";
+import { AlertNotification } from "./AlertNotification";
 import { useRef } from "react";
 import ShareAndroid from "@spectrum-icons/workflow/ShareAndroid";
 import { useMutation } from "@tanstack/react-query";
@@ -21,12 +22,15 @@ export interface ChatWindowProps extends StyleProps {
   messages: ChatMessage[];
   identity: Identity;
   chat: Chat;
+  // The last request the server rejected, if any.
+  serverError: ServerError | null;
   onSend: (msg: string) => void;
 }
 export function ChatWindow({
   messages,
   identity,
   chat,
+  serverError,
   onSend,
   ...styleProps
 }: ChatWindowProps) {
@@ -69,6 +73,12 @@ export function ChatWindow({
         flex="1 1 auto"
         ref={chatMessagesViewRef}
       />
+      {serverError != null ? (
+        <AlertNotification
+          msg="The server rejected the request."
+          error={serverError}
+        />
+      ) : null}
       <SendChatMessageForm
         onSubmit={onSend}
         onScrollToBottom={() => {}}
@@ -16,6 +16,7 @@ export const pendingChatClientState = {
   closeReason: null,
   isError: false,
   error: null,
+  serverError: null,
   messages: [],
   send: (_: string) => {
     throw new Error(SEND_ON_IS_PENDING_ERROR_MESSAGE);
@@ -28,6 +29,8 @@ export class ChatClient {
   private isError = false;
   private error: unknown = null;
 
+  private serverError: ServerError | null = null;
+
   private isClosed = false;
   private closeReason: string | null = null;
   private closeCode: number | null = null;
@@ -47,6 +50,7 @@ export class ChatClient {
         !this.isError && (this.webSocketPending || this.historyPending),
       isError: this.isError,
       error: this.error,
+      serverError: this.serverError,
       isClosed: this.isClosed,
       closeReason: this.closeReason,
       closeCode: this.closeCode,
@@ -84,33 +88,6 @@ export class ChatClient {
         resolve();
       });
     }
-    fetch(`${ENDPOINT}/history/${chatId}`)
-      .then(async (response) => {
-        if (response.status != 404 && !response.ok) {
-          let errorBody = null;
-          try {
-            errorBody = await response.text();
-          } catch (e) {
-            errorBody = `error receiving error body: ${e}`;
-          }
-          throw new Error(
-            `error fetching history: ${response.status} ${response.statusText} ${errorBody}`,
-          );
-        }
-        if (response.ok) {
-          const body = await response.text();
-          const wireMessages = (JSON.parse(body) as RawChatMessage[]).map(
-            convertChatMessageFromWire,
-          );
-          this.onHistory(wireMessages);
-        }
-      })
-      .catch((reason) => {
-        this.isError = true;
-        this.error = reason;
-        this.historyPending = false;
-        this.dispatchSnapshotChange();
-      });
   }
 
   private onClose = (event: CloseEvent) => {
@@ -136,11 +113,53 @@ export class ChatClient {
         const message = JSON.parse(event.data) as Outgoing;
         const messageType = message.type;
         switch (messageType) {
+          case "History":
+            // Only the latest page of the history is shown for now.
+            this.onHistory(
+              message.page.messages.map(convertChatMessageFromWire),
+            );
+            break;
+          case "Resync":
+            // Too many messages or changes to them were skipped, start
+            // over from the page.
+            this.messages = message.page.messages.map(
+              convertChatMessageFromWire,
+            );
+            break;
           case "ChatMessage":
             this.messages.push(convertChatMessageFromWire(message.msg));
             break;
+          case "MessageEdited":
+          case "MessageDeleted":
+            this.onMessageReplaced(convertChatMessageFromWire(message.msg));
+            break;
+          case "ReactionAdded":
+            this.onReactionChanged(message.reaction, true);
+            break;
+          case "ReactionRemoved":
+            this.onReactionChanged(message.reaction, false);
+            break;
           case "Error":
-            throw new Error(`error from server received: ${message.msg}`);
+            // The session goes on, only the request failed.
+            this.serverError = message;
+            break;
+          case "TokenExpiring":
+          case "Reauthenticated":
+            // There is no login yet, which could provide a fresh token.
+            break;
+          case "NoteCreated":
+          case "NoteUpdated":
+            // Notes aren't shown yet.
+            break;
+          case "Presence":
+          case "MemberJoined":
+          case "MemberLeft":
+          case "Typing":
+            // Who is connected or typing isn't shown yet.
+            break;
+          case "ReadUpTo":
+            // Read receipts aren't shown yet.
+            break;
           default:
             ensureNever(messageType);
             // Should be impossible, but because we casted the parsed
@@ -175,6 +194,44 @@ export class ChatClient {
     this.historyPending = false;
     this.dispatchSnapshotChange();
   };
+  private onMessageReplaced = (replacement: ChatMessage) => {
+    this.messages = this.messages.map((message) =>
+      message.event_id == replacement.event_id ? replacement : message,
+    );
+  };
+  // A reaction that is already known, or already gone, changes nothing.
+  private onReactionChanged = (change: ReactionChange, added: boolean) => {
+    this.messages = this.messages.map((message) => {
+      if (message.event_id != change.event_id) {
+        return message;
+      }
+      const reactions = message.reactions
+        .map((reaction) => {
+          if (reaction.emoji != change.emoji) {
+            return reaction;
+          }
+          const users = reaction.users.filter(
+            (user) => user != change.user_id,
+          );
+          if (added) {
+            users.push(change.user_id);
+          }
+          return { ...reaction, count: users.length, users };
+        })
+        .filter((reaction) => reaction.count > 0);
+      if (
+        added &&
+        !reactions.some((reaction) => reaction.emoji == change.emoji)
+      ) {
+        reactions.push({
+          emoji: change.emoji,
+          count: 1,
+          users: [change.user_id],
+        });
+      }
+      return { ...message, reactions };
+    });
+  };
   public getSnapshot(): ChatClientSnapshot {
     return this.snapshot;
   }
@@ -189,8 +246,11 @@ export class ChatClient {
     } else if (ws == null) {
       throw new Error("cannot send websocket is null");
     }
+    this.serverError = null;
+    this.dispatchSnapshotChange();
     ws.send(
       JSON.stringify({
+        type: "ChatMessage",
         display_name: this.displayName,
         message,
       } satisfies IncomingChatMessage),
@@ -215,6 +275,8 @@ export interface ChatClientSnapshot {
   isPending: boolean;
   isError: boolean;
   error: unknown;
+  // The last request the server rejected, until the next message is sent.
+  serverError: ServerError | null;
   isClosed: boolean;
   closeReason: string | null;
   closeCode: number | null;
@@ -228,40 +290,281 @@ interface RawChatMessage {
   event_id: string;
   timestamp: string;
   chat_id: string;
+  sequence: number;
   user_id: string;
   display_name: string;
   message: string;
+  in_reply_to: string | null;
+  thread_root: string | null;
+  edited_at: string | null;
+  deleted_at: string | null;
+  reactions: Reaction[];
+}
+
+// The users who reacted to a message with the emoji.
+export interface Reaction {
+  emoji: string;
+  count: number;
+  users: string[];
+}
+
+interface ReactionChange {
+  event_id: string;
+  user_id: string;
+  emoji: string;
+}
+
+interface HistoryPage {
+  messages: RawChatMessage[];
+  next_cursor: string | null;
 }
 
 export interface ChatMessage {
   event_id: string;
   timestamp: Date;
   chat_id: string;
+  sequence: number;
   user_id: string;
   display_name: string;
   message: string;
+  in_reply_to: string | null;
+  // The first message of the thread, for replies only.
+  thread_root: string | null;
+  edited_at: Date | null;
+  // Set for the tombstones of deleted messages, which have no text.
+  deleted_at: Date | null;
+  reactions: Reaction[];
 }
 
 function convertChatMessageFromWire(wireMessage: RawChatMessage): ChatMessage {
   return {
     ...wireMessage,
     timestamp: new Date(wireMessage.timestamp),
+    edited_at:
+      wireMessage.edited_at == null ? null : new Date(wireMessage.edited_at),
+    deleted_at:
+      wireMessage.deleted_at == null ? null : new Date(wireMessage.deleted_at),
   };
 }
 
+interface OutgoingHistory {
+  type: "History";
+  page: HistoryPage;
+}
+
+interface OutgoingResync {
+  type: "Resync";
+  page: HistoryPage;
+}
+
 interface OutgoingChatMessage {
   type: "ChatMessage";
   msg: RawChatMessage;
 }
 
-interface OutgoingError {
-  type: "Error";
+interface OutgoingMessageEdited {
+  type: "MessageEdited";
+  msg: RawChatMessage;
+}
+
+interface OutgoingMessageDeleted {
+  type: "MessageDeleted";
+  msg: RawChatMessage;
+}
+
+interface OutgoingReactionAdded {
+  type: "ReactionAdded";
+  reaction: ReactionChange;
+}
+
+interface OutgoingReactionRemoved {
+  type: "ReactionRemoved";
+  reaction: ReactionChange;
+}
+
+// Stable codes of the errors reported by the server, see ErrorCode in the
+// backend.
+export type ErrorCode =
+  | "internal_error"
+  | "unauthenticated"
+  | "not_a_member"
+  | "not_permitted"
+  | "chat_not_found"
+  | "note_not_found"
+  | "message_not_found"
+  | "message_deleted"
+  | "invite_unusable"
+  | "invalid_input"
+  | "invalid_text"
+  | "malformed_request"
+  | "rate_limited";
+
+export interface ServerError {
+  code: ErrorCode;
   msg: string;
+  // Identifies the failure in the logs of the server.
+  correlation_id: string;
+  chat_id?: string;
+  event_id?: string;
+  note_id?: string;
+  // Set if a message was sent too fast, when to send it again.
+  retry_after_ms?: number;
+  // Set if a display name or message was rejected, which one and why.
+  invalid_text?: InvalidText;
 }
 
-type Outgoing = OutgoingChatMessage | OutgoingError;
+interface OutgoingError extends ServerError {
+  type: "Error";
+}
+
+export type TextField = "display_name" | "message";
+
+export type InvalidText =
+  | { reason: "empty"; field: TextField }
+  | { reason: "too_long"; field: TextField; max_chars: number }
+  | { reason: "forbidden_character"; field: TextField; character: string };
+
+interface OutgoingTokenExpiring {
+  type: "TokenExpiring";
+  expires_at: string;
+}
+
+interface OutgoingReauthenticated {
+  type: "Reauthenticated";
+  expires_at: string;
+}
+
+interface RawNote {
+  note_id: string;
+  chat_id: string;
+  author: string;
+  body: string;
+  created_at: string;
+  updated_at: string;
+}
+
+interface OutgoingNoteCreated {
+  type: "NoteCreated";
+  note: RawNote;
+}
+
+interface OutgoingNoteUpdated {
+  type: "NoteUpdated";
+  note: RawNote;
+}
+
+interface OutgoingPresence {
+  type: "Presence";
+  users: string[];
+}
+
+interface OutgoingMemberJoined {
+  type: "MemberJoined";
+  user_id: string;
+}
+
+interface OutgoingMemberLeft {
+  type: "MemberLeft";
+  user_id: string;
+}
+
+interface OutgoingTyping {
+  type: "Typing";
+  user_id: string;
+}
+
+interface OutgoingReadUpTo {
+  type: "ReadUpTo";
+  receipt: ReadReceipt;
+}
+
+type Outgoing =
+  | OutgoingHistory
+  | OutgoingResync
+  | OutgoingChatMessage
+  | OutgoingMessageEdited
+  | OutgoingMessageDeleted
+  | OutgoingReactionAdded
+  | OutgoingReactionRemoved
+  | OutgoingError
+  | OutgoingTokenExpiring
+  | OutgoingReauthenticated
+  | OutgoingNoteCreated
+  | OutgoingNoteUpdated
+  | OutgoingPresence
+  | OutgoingMemberJoined
+  | OutgoingMemberLeft
+  | OutgoingTyping
+  | OutgoingReadUpTo;
 
 export interface IncomingChatMessage {
+  type: "ChatMessage";
   display_name: string;
   message: string;
+  in_reply_to?: string;
+}
+
+export interface IncomingEditMessage {
+  type: "EditMessage";
+  event_id: string;
+  message: string;
+}
+
+export interface IncomingDeleteMessage {
+  type: "DeleteMessage";
+  event_id: string;
+}
+
+export interface IncomingAddReaction {
+  type: "AddReaction";
+  event_id: string;
+  emoji: string;
+}
+
+export interface IncomingRemoveReaction {
+  type: "RemoveReaction";
+  event_id: string;
+  emoji: string;
+}
+
+export interface IncomingTyping {
+  type: "Typing";
+}
+
+export interface IncomingMarkRead {
+  type: "MarkRead";
+  event_id: string;
+}
+
+export interface ReadReceipt {
+  user_id: string;
+  event_id: string;
+  sequence: number;
+}
+
+// An entry of `GET /users/me/unread`.
+export interface UnreadMessages {
+  chat_id: string;
+  last_read: string | null;
+  unread: number;
+}
+
+// An entry of `GET /users/me/chats`, the chats the user participated in.
+export interface ChatSummary {
+  chat_id: string;
+  name: string | null;
+  topic: string | null;
+  creator: string | null;
+  created_at: string;
+  last_activity: string;
+  last_message: MessagePreview | null;
+}
+
+export interface MessagePreview {
+  event_id: string;
+  user_id: string;
+  display_name: string;
+  timestamp: string;
+  text: string;
+  truncated: boolean;
 }